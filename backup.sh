@@ -12,49 +12,64 @@ mkdir -p $DIR
 restore() {
     FILE="$1"
     echo "Restoring $FILE..."
-    $QU raw $(cat .dfx/local/canister_ids.json | jq -r ".taggr.local") "stable_mem_write" --args-file "$FILE" | $QU send --yes --raw - > /dev/null
+    $QU raw $(cat .dfx/local/canister_ids.json | jq -r ".taggr.local") "import_page" --args-file "$FILE" | $QU send --yes --raw - > /dev/null
 }
 
 if [ "$CMD" == "restore" ]; then
     export IC_URL=http://localhost:8080
+    echo "Importing the manifest..."
+    $QU raw $(cat .dfx/local/canister_ids.json | jq -r ".taggr.local") "import_start" --args-file "$DIR/manifest.bin" | $QU send --yes --raw - > /dev/null
+    PAGES=$(cat $DIR/pages.txt)
     PAGE=0
-    while true; do
+    while [ $PAGE -lt $PAGES ]; do
         for _ in {1..10}; do
-            FILE="$DIR/page$PAGE.bin"
-            restore $FILE &
+            if [ $PAGE -lt $PAGES ]; then
+                restore "$DIR/page$PAGE.bin" &
+            fi
             PAGE=$((PAGE + 1))
         done
         wait
-        if [ "$(stat -f%z $FILE)" == "18" ]; then break; fi
     done
     echo "Clearing buckets before restoring heap..."
     dfx canister call taggr clear_buckets '("")' || 1
-    echo "Restoring heap..."
-    dfx canister call taggr stable_to_heap
+    echo "Restoring heap and starting the consistency check (see the logs for the result)..."
+    dfx canister call taggr import_finish
     echo "Clearing buckets after restoring heap..."
     dfx canister call taggr clear_buckets '("")'
-    echo "Checking consistency..."
-    dfx canister call --query taggr check
     exit 0
 fi
 
+ID="6qfxa-ryaaa-aaaai-qbhsq-cai"
+
 fetch() {
     FILE="$1"
-    $QU raw "6qfxa-ryaaa-aaaai-qbhsq-cai" "stable_mem_read" --args "($PAGE:nat64)" --query |\
+    $QU raw $ID "backup_page" --args "($PAGE:nat64)" --query |\
         $QU send --yes --raw - > $FILE
 }
 
 git rev-parse HEAD > $DIR/commit.txt
 
+echo "Starting the backup..."
+PAGES=$(dfx canister --network ic call taggr backup_start | grep -o "page_count = [0-9_]*" | grep -o "[0-9_]*" | tr -d _)
+echo $PAGES > $DIR/pages.txt
+
+echo "Waiting for the manifest..."
+until dfx canister --network ic call --query taggr backup_manifest 2> /dev/null | tee $DIR/manifest.did | grep -q "Ok"; do
+    sleep 5
+done
+$QU raw $ID "backup_manifest" --query | $QU send --yes --raw - > $DIR/manifest.bin
+
 PAGE=0
-while true; do
+while [ $PAGE -lt $PAGES ]; do
     for _ in {1..10}; do
-        FILE="$DIR/page$PAGE.bin"
-        echo "Fetching page $PAGE..."
-        fetch $FILE &
+        if [ $PAGE -lt $PAGES ]; then
+            echo "Fetching page $PAGE..."
+            fetch "$DIR/page$PAGE.bin" &
+        fi
         PAGE=$((PAGE + 1))
     done
     wait
-    if [ "$(stat -f%z $FILE)" == "18" ]; then break; fi
 done
 
+echo "Finishing the backup..."
+dfx canister --network ic call taggr backup_finish
@@ -1,6 +1,8 @@
 use crate::{config::CONFIG, metadata::set_index_metadata};
 use base64::{engine::general_purpose, Engine as _};
-use ic_certified_map::{labeled, labeled_hash, AsHashTree, Hash, RbTree};
+#[cfg(not(test))]
+use ic_certified_map::fork_hash;
+use ic_certified_map::{fork, labeled, labeled_hash, AsHashTree, Hash, HashTree, RbTree};
 use serde_bytes::ByteBuf;
 use sha2::{Digest, Sha256};
 use std::collections::HashMap;
@@ -8,8 +10,11 @@ use std::collections::HashMap;
 pub type Headers = Vec<(String, String)>;
 
 const LABEL: &[u8] = b"http_assets";
+// The hashes of user badges are certified next to the assets, keyed by the big-endian user id.
+const BADGES_LABEL: &[u8] = b"badges";
 static mut ASSET_HASHES: Option<RbTree<Vec<u8>, Hash>> = None;
 static mut ASSETS: Option<HashMap<String, (Headers, Vec<u8>)>> = None;
+static mut BADGE_HASHES: Option<RbTree<Vec<u8>, Hash>> = None;
 
 fn asset_hashes<'a>() -> &'a mut RbTree<Vec<u8>, Hash> {
     unsafe { ASSET_HASHES.as_mut().expect("uninitialized") }
@@ -19,6 +24,10 @@ fn assets<'a>() -> &'a mut HashMap<String, (Headers, Vec<u8>)> {
     unsafe { ASSETS.as_mut().expect("uninitialized") }
 }
 
+fn badge_hashes<'a>() -> &'a mut RbTree<Vec<u8>, Hash> {
+    unsafe { (*std::ptr::addr_of_mut!(BADGE_HASHES)).get_or_insert_with(Default::default) }
+}
+
 pub static INDEX_HTML: &[u8] = include_bytes!("../../dist/frontend/index.html");
 pub fn index_html_headers() -> Headers {
     vec![(
@@ -124,15 +133,50 @@ pub fn load() {
         domains.join("\n").as_bytes().to_vec(),
     );
 
-    set_certified_data(&labeled_hash(LABEL, &asset_hashes().root_hash()));
+    certify();
+}
+
+/// Restores the certified badge hashes after upgrades.
+pub fn load_badges<'a>(hashes: impl Iterator<Item = (&'a u64, &'a Hash)>) {
+    unsafe {
+        BADGE_HASHES = Some(Default::default());
+    }
+    for (user_id, hash) in hashes {
+        badge_hashes().insert(user_id.to_be_bytes().to_vec(), *hash);
+    }
+    certify();
 }
 
-#[allow(unused_variables)]
-pub fn set_certified_data(data: &[u8]) {
-    #[cfg(test)]
-    return;
+/// Updates the certified hash of the user's badges; `None` removes it.
+pub fn certify_badges(user_id: u64, hash: Option<Hash>) {
+    let key = user_id.to_be_bytes().to_vec();
+    match hash {
+        Some(hash) => badge_hashes().insert(key, hash),
+        None => badge_hashes().delete(&key),
+    }
+    certify();
+}
+
+fn certify() {
     #[cfg(not(test))]
-    ic_cdk::api::set_certified_data(&labeled_hash(LABEL, &asset_hashes().root_hash()));
+    ic_cdk::api::set_certified_data(&fork_hash(
+        &labeled_hash(LABEL, &asset_hashes().root_hash()),
+        &labeled_hash(BADGES_LABEL, &badge_hashes().root_hash()),
+    ));
+}
+
+/// Returns the certificate and the tree witnessing the hash of the user's badges, both
+/// base64-encoded.
+pub fn badge_certificate(user_id: u64) -> (String, String) {
+    let certificate = ic_cdk::api::data_certificate().unwrap_or_default();
+    let tree = fork(
+        HashTree::Pruned(labeled_hash(LABEL, &asset_hashes().root_hash())),
+        labeled(BADGES_LABEL, badge_hashes().witness(&user_id.to_be_bytes())),
+    );
+    (
+        general_purpose::STANDARD.encode(certificate),
+        encode_tree(tree),
+    )
 }
 
 fn add_asset(paths: &[&str], headers: Headers, bytes: Vec<u8>) {
@@ -159,17 +203,24 @@ pub fn asset(path: &str) -> Option<(Headers, ByteBuf)> {
 fn certificate_header(path: &str) -> (String, String) {
     let certificate = ic_cdk::api::data_certificate().expect("no certificate");
     let witness = asset_hashes().witness(path.as_bytes());
-    let tree = labeled(LABEL, witness);
-    let mut serializer = serde_cbor::ser::Serializer::new(Vec::new());
-    serializer.self_describe().expect("tagging failed");
-    use serde::Serialize;
-    tree.serialize(&mut serializer).expect("couldn't serialize");
+    let tree = fork(
+        labeled(LABEL, witness),
+        HashTree::Pruned(labeled_hash(BADGES_LABEL, &badge_hashes().root_hash())),
+    );
     (
         "IC-Certificate".to_string(),
         format!(
             "certificate=:{}:, tree=:{}:",
             general_purpose::STANDARD.encode(certificate),
-            general_purpose::STANDARD.encode(serializer.into_inner())
+            encode_tree(tree)
         ),
     )
 }
+
+fn encode_tree(tree: HashTree) -> String {
+    let mut serializer = serde_cbor::ser::Serializer::new(Vec::new());
+    serializer.self_describe().expect("tagging failed");
+    use serde::Serialize;
+    tree.serialize(&mut serializer).expect("couldn't serialize");
+    general_purpose::STANDARD.encode(serializer.into_inner())
+}
@@ -8,21 +8,6 @@ async fn chores() {
     State::chores(time()).await;
 }
 
-#[query]
-async fn check() {
-    let (sum, last_id): (u64, u64) = read(|state| {
-        let last_id = state.next_post_id.saturating_sub(1);
-        (
-            (0..=last_id)
-                .filter_map(|id| Post::get(&state, &id))
-                .map(|post| post.id)
-                .sum(),
-            last_id,
-        )
-    });
-    assert_eq!(sum, (last_id.pow(2) + last_id) / 2);
-}
-
 #[update]
 // Promotes any user to a stalwart with 20k tokens.
 async fn godmode(username: String) {
@@ -78,27 +63,3 @@ async fn clear_buckets() {
         let _: Result<(), _> = management_canister_call(canister_id, "delete_canister").await;
     }
 }
-
-#[update]
-// Backup restore method.
-fn stable_mem_write(input: Vec<(u64, ByteBuf)>) {
-    if let Some((page, buffer)) = input.get(0) {
-        if buffer.is_empty() {
-            return;
-        }
-        let offset = page * BACKUP_PAGE_SIZE as u64;
-        let current_size = ic_cdk::api::stable::stable64_size();
-        let needed_size = ((offset + buffer.len() as u64) >> 16) + 1;
-        let delta = needed_size.saturating_sub(current_size);
-        if delta > 0 {
-            api::stable::stable64_grow(delta).unwrap_or_else(|_| panic!("couldn't grow memory"));
-        }
-        api::stable::stable64_write(offset, buffer);
-    }
-}
-
-#[update]
-// Backup restore method.
-fn stable_to_heap() {
-    stable_to_heap_core();
-}
@@ -0,0 +1,406 @@
+//! Backups of the stable memory.
+//!
+//! A backup is a consistent snapshot of the stable memory exported in pages of `PAGE_SIZE`
+//! bytes. Starting a backup writes the state snapshot to the stable memory and freezes the memory
+//! up to the end of the snapshot: until the backup is finished, objects are allocated above the
+//! frozen memory, freed segments are only released afterwards, and no snapshots or compactions
+//! are made by the chores. The SHA-256 hashes of the pages are computed in steps; once all hashes
+//! are computed, the manifest is complete and the pages can be downloaded and verified against
+//! it. An upgrade aborts a running backup, because it writes a new snapshot.
+//!
+//! A backup is imported into a fresh canister page by page; every page is verified against the
+//! manifest before it is written. Once all pages are imported, the state is restored from the
+//! stable memory and checked for consistency in steps; the result of the check is logged.
+
+use super::*;
+use candid::CandidType;
+
+pub const PAGE_SIZE: u64 = 1024 * 1024;
+
+// The number of pages hashed in one step.
+const PAGES_PER_STEP: u64 = 16;
+
+// The number of objects checked in one step of a consistency check.
+const OBJECTS_PER_STEP: u64 = 1000;
+
+#[derive(Clone, Debug, Default, PartialEq, CandidType, Serialize, Deserialize)]
+pub struct Manifest {
+    // The commit of the last executed release.
+    pub commit: String,
+    // The coordinates of the snapshot manifest in the stable memory.
+    pub heap_address: (u64, u64),
+    pub page_count: u64,
+    // Hex-encoded SHA-256 hashes of all pages; incomplete until all pages are hashed.
+    pub page_hashes: Vec<String>,
+}
+
+impl Manifest {
+    pub fn complete(&self) -> bool {
+        self.page_hashes.len() as u64 == self.page_count
+    }
+
+    // The memory size covered by the backup.
+    fn end(&self) -> u64 {
+        let (offset, len) = self.heap_address;
+        offset + len
+    }
+
+    fn page_len(&self, page: u64) -> u64 {
+        PAGE_SIZE.min(self.end().saturating_sub(page * PAGE_SIZE))
+    }
+}
+
+pub enum Backup {
+    Export(Manifest),
+    Import {
+        manifest: Manifest,
+        pages: BTreeSet<u64>,
+    },
+}
+
+fn hash(bytes: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(bytes);
+    format!("{:x}", hasher.finalize())
+}
+
+fn commit(state: &State) -> String {
+    (0..state.proposals.len() as u32)
+        .rev()
+        .filter_map(|id| state.proposals.get(&id))
+        .find_map(|proposal| match (&proposal.status, &proposal.payload) {
+            (Status::Executed, Payload::Release(release)) => Some(release.commit.clone()),
+            _ => None,
+        })
+        .unwrap_or_default()
+}
+
+/// Writes a snapshot of the state and freezes the memory containing it.
+pub fn start(state: &mut State) -> Result<Manifest, String> {
+    if state.backup.is_some() {
+        return Err("backup in progress".into());
+    }
+    memory::heap_to_stable(state);
+    let (offset, len) = state.memory.heap_address();
+    let end = offset + len;
+    let manifest = Manifest {
+        commit: commit(state),
+        heap_address: (offset, len),
+        page_count: end / PAGE_SIZE + (end % PAGE_SIZE > 0) as u64,
+        page_hashes: Default::default(),
+    };
+    state.memory.freeze(manifest.end());
+    state.backup = Some(Backup::Export(manifest.clone()));
+    state.logger.info(format!(
+        "Backup of `{}` pages started.",
+        manifest.page_count
+    ));
+    Ok(manifest)
+}
+
+/// Hashes the next pages of the running backup. Returns true if more pages need to be hashed.
+pub fn hash_pages(state: &mut State) -> bool {
+    let manifest = match state.backup.as_mut() {
+        Some(Backup::Export(manifest)) => manifest,
+        _ => return false,
+    };
+    let first = manifest.page_hashes.len() as u64;
+    for page in first..manifest.page_count.min(first + PAGES_PER_STEP) {
+        let bytes = state
+            .memory
+            .read_bytes(page * PAGE_SIZE, manifest.page_len(page));
+        manifest.page_hashes.push(hash(&bytes));
+    }
+    if manifest.complete() {
+        state.logger.info("Backup manifest completed.");
+    }
+    !manifest.complete()
+}
+
+pub fn manifest(state: &State) -> Result<Manifest, String> {
+    match &state.backup {
+        Some(Backup::Export(manifest)) if manifest.complete() => Ok(manifest.clone()),
+        Some(Backup::Export(_)) => Err("manifest incomplete".into()),
+        _ => Err("no backup in progress".into()),
+    }
+}
+
+pub fn page(state: &State, page: u64) -> Result<Vec<u8>, String> {
+    let manifest = match &state.backup {
+        Some(Backup::Export(manifest)) => manifest,
+        _ => return Err("no backup in progress".into()),
+    };
+    if page >= manifest.page_count {
+        return Err("page out of range".into());
+    }
+    Ok(state
+        .memory
+        .read_bytes(page * PAGE_SIZE, manifest.page_len(page)))
+}
+
+/// Finishes the running backup and unfreezes the memory.
+pub fn finish(state: &mut State) -> Result<(), String> {
+    if !matches!(state.backup, Some(Backup::Export(_))) {
+        return Err("no backup in progress".into());
+    }
+    state.memory.thaw()?;
+    state.backup = None;
+    state.logger.info("Backup finished.");
+    Ok(())
+}
+
+/// Starts the import of a backup. Only possible in a fresh canister.
+pub fn start_import(state: &mut State, manifest: Manifest) -> Result<(), String> {
+    if state.backup.is_some() {
+        return Err("backup in progress".into());
+    }
+    if !state.users.is_empty() || state.next_post_id > 0 {
+        return Err("backups can only be imported into a fresh canister".into());
+    }
+    if !manifest.complete() || manifest.page_count == 0 {
+        return Err("incomplete manifest".into());
+    }
+    state.backup = Some(Backup::Import {
+        manifest,
+        pages: Default::default(),
+    });
+    Ok(())
+}
+
+/// Verifies the page against the manifest and writes it to the stable memory.
+pub fn import_page(state: &mut State, page: u64, bytes: &[u8]) -> Result<(), String> {
+    let (manifest, pages) = match state.backup.as_mut() {
+        Some(Backup::Import { manifest, pages }) => (manifest, pages),
+        _ => return Err("no import in progress".into()),
+    };
+    let expected_hash = manifest
+        .page_hashes
+        .get(page as usize)
+        .ok_or("page out of range")?;
+    if bytes.len() as u64 != manifest.page_len(page) || &hash(bytes) != expected_hash {
+        return Err(format!("page {} is corrupted", page));
+    }
+    state.memory.write_bytes(page * PAGE_SIZE, bytes)?;
+    pages.insert(page);
+    Ok(())
+}
+
+/// Checks that all pages were imported and finishes the import. The state has to be restored
+/// from the stable memory afterwards.
+pub fn finish_import(state: &mut State) -> Result<(), String> {
+    match &state.backup {
+        Some(Backup::Import { manifest, pages }) => {
+            if (pages.len() as u64) < manifest.page_count {
+                return Err(format!(
+                    "`{}` pages missing",
+                    manifest.page_count - pages.len() as u64
+                ));
+            }
+            Ok(())
+        }
+        _ => Err("no import in progress".into()),
+    }
+}
+
+/// The progress of a running consistency check.
+#[derive(Default)]
+pub struct Check {
+    next_post_id: PostId,
+    // The principals left to check.
+    principals: Vec<Principal>,
+    next_transaction_id: u64,
+    // The balances recomputed from the checked transactions.
+    balances: HashMap<Account, Token>,
+}
+
+/// Starts a consistency check of the state.
+pub fn start_check(state: &mut State) -> Result<(), String> {
+    if state.check.is_some() {
+        return Err("check in progress".into());
+    }
+    state.check = Some(Check {
+        principals: state.principals.keys().cloned().collect(),
+        ..Default::default()
+    });
+    state.logger.info("State check started.");
+    Ok(())
+}
+
+/// Checks the next objects of the running consistency check and logs the result once the check
+/// is completed. Returns true if more objects need to be checked.
+pub fn check_step(state: &mut State) -> bool {
+    let mut check = match state.check.take() {
+        Some(check) => check,
+        None => return false,
+    };
+    match check_objects(state, &mut check) {
+        Ok(true) => {
+            state.check = Some(check);
+            true
+        }
+        Ok(false) => {
+            state.logger.info("State check passed.");
+            false
+        }
+        Err(err) => {
+            state
+                .logger
+                .error(format!("The state is inconsistent: {}", err));
+            false
+        }
+    }
+}
+
+// Checks up to `OBJECTS_PER_STEP` posts, principals and transactions. Returns true if more
+// objects need to be checked.
+fn check_objects(state: &State, check: &mut Check) -> Result<bool, String> {
+    let mut budget = OBJECTS_PER_STEP;
+
+    let end = state.next_post_id.min(check.next_post_id + budget);
+    for id in check.next_post_id..end {
+        match Post::get(state, &id) {
+            Some(post) if post.id == id => {}
+            Some(_) => return Err(format!("post {} has a wrong id", id)),
+            None => return Err(format!("post {} not found", id)),
+        }
+    }
+    budget -= end - check.next_post_id;
+    check.next_post_id = end;
+    if check.next_post_id < state.next_post_id {
+        return Ok(true);
+    }
+
+    let n = check.principals.len().min(budget as usize);
+    let rest = check.principals.len() - n;
+    for principal in check.principals.split_off(rest) {
+        // principals removed since the start of the check are skipped
+        let user_id = match state.principals.get(&principal) {
+            Some(user_id) => user_id,
+            None => continue,
+        };
+        if state.users.get(user_id).map(|user| user.principal) != Some(principal) {
+            return Err(format!("principal {} points to a wrong user", principal));
+        }
+    }
+    budget -= n as u64;
+    if !check.principals.is_empty() {
+        return Ok(true);
+    }
+
+    let len = state.ledger.len() as u64;
+    let end = len.min(check.next_transaction_id + budget);
+    for id in check.next_transaction_id..end {
+        let transaction = state.ledger.get(&id).ok_or("transaction not found")?;
+        token::apply_transaction(&mut check.balances, &transaction)?;
+    }
+    check.next_transaction_id = end;
+    if check.next_transaction_id < len {
+        return Ok(true);
+    }
+
+    let non_zero = |balances: &HashMap<Account, Token>| {
+        balances
+            .iter()
+            .filter(|(_, balance)| **balance > 0)
+            .map(|(account, balance)| (account.clone(), *balance))
+            .collect::<HashMap<_, _>>()
+    };
+    if non_zero(&check.balances) != non_zero(&state.balances) {
+        return Err("balances don't match the ledger".into());
+    }
+    Ok(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        env::memory::tests::set_test_api,
+        env::tests::{create_user, pr},
+        STATE,
+    };
+
+    #[test]
+    fn test_backup() {
+        STATE.with(|cell| {
+            cell.replace(Default::default());
+            let state = &mut *cell.borrow_mut();
+            set_test_api(&mut state.memory);
+            create_user(state, pr(0));
+            for i in 0..3 {
+                Post::create(
+                    state,
+                    format!("Post {}", i),
+                    &[],
+                    pr(0),
+                    0,
+                    None,
+                    None,
+                    None,
+                )
+                .unwrap();
+            }
+            state.memory.posts.insert(0, vec![1_u8; 8]).unwrap();
+            assert!(start_check(state).is_ok());
+            assert_eq!(start_check(state), Err("check in progress".into()));
+            assert!(!check_step(state));
+            assert!(state.check.is_none());
+            assert_eq!(
+                state.logger.events.last().unwrap().message,
+                "State check passed."
+            );
+            state.balances.insert(account(pr(9)), 5);
+            assert!(start_check(state).is_ok());
+            assert!(!check_step(state));
+            assert_eq!(
+                state.logger.events.last().unwrap().message,
+                "The state is inconsistent: balances don't match the ledger"
+            );
+            state.balances.remove(&account(pr(9)));
+
+            assert_eq!(manifest(state), Err("no backup in progress".into()));
+            let manifest = start(state).unwrap();
+            assert_eq!(start(state), Err("backup in progress".into()));
+            assert_eq!(manifest.page_count, 1);
+            assert_eq!(super::manifest(state), Err("manifest incomplete".into()));
+            assert!(!hash_pages(state));
+            let manifest = super::manifest(state).unwrap();
+            assert!(manifest.complete());
+            let bytes = page(state, 0).unwrap();
+            assert_eq!(bytes.len() as u64, manifest.end());
+            assert_eq!(hash(&bytes), manifest.page_hashes[0]);
+            assert_eq!(page(state, 1), Err("page out of range".into()));
+
+            // the frozen memory is not modified by allocations and deallocations
+            state.memory.posts.remove::<Vec<u8>>(&0).unwrap();
+            state.memory.posts.insert(1, vec![2_u8; 8]).unwrap();
+            assert_eq!(page(state, 0).unwrap(), bytes);
+
+            assert!(finish(state).is_ok());
+            assert_eq!(finish(state), Err("no backup in progress".into()));
+
+            // a backup can only be imported into a fresh canister
+            assert_eq!(
+                start_import(state, manifest.clone()),
+                Err("backups can only be imported into a fresh canister".into())
+            );
+            let mut state = State::default();
+            let mut incomplete = manifest.clone();
+            incomplete.page_hashes.clear();
+            assert_eq!(
+                start_import(&mut state, incomplete),
+                Err("incomplete manifest".into())
+            );
+            assert!(start_import(&mut state, manifest).is_ok());
+            assert_eq!(finish_import(&mut state), Err("`1` pages missing".into()));
+            let mut corrupted = bytes.clone();
+            corrupted[0] += 1;
+            assert_eq!(
+                import_page(&mut state, 0, &corrupted),
+                Err("page 0 is corrupted".into())
+            );
+            assert!(import_page(&mut state, 0, &bytes).is_ok());
+            assert!(finish_import(&mut state).is_ok());
+        })
+    }
+}
@@ -0,0 +1,248 @@
+//! Badges are attestations about users issued by realm controllers or the DAO.
+//!
+//! The badges of every user are certified: the hash of their JSON serialization is a part of
+//! the canister's certified data, so clients can verify them with the `badges` query against
+//! the certificate signed by the subnet.
+
+use super::user::UserId;
+use super::*;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Attestation {
+    // The user is an official account of the realm.
+    RealmOfficial(String),
+    // The user controls the domain.
+    DomainVerified(String),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Issuer {
+    RealmController(UserId),
+    // The id of the executed proposal.
+    Dao(u32),
+}
+
+// An attestation about a user issued by a realm controller or the DAO at the given time.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Badge {
+    pub attestation: Attestation,
+    pub issuer: Issuer,
+    pub timestamp: u64,
+}
+
+/// Returns the certified representation of the badges.
+pub fn certified_content(badges: &[Badge]) -> String {
+    serde_json::to_string(badges).expect("couldn't serialize badges")
+}
+
+/// Updates the certified hash of the user's badges.
+pub fn certify(state: &mut State, user_id: UserId) {
+    let hash: Option<[u8; 32]> = state
+        .users
+        .get(&user_id)
+        .filter(|user| !user.badges.is_empty())
+        .map(|user| Sha256::digest(certified_content(&user.badges)).into());
+    match hash {
+        Some(hash) => state.badge_hashes.insert(user_id, hash),
+        None => state.badge_hashes.remove(&user_id),
+    };
+    crate::assets::certify_badges(user_id, hash);
+}
+
+pub fn validate(state: &State, user_id: UserId, attestation: &Attestation) -> Result<(), String> {
+    let user = state.users.get(&user_id).ok_or("no user found")?;
+    if user
+        .badges
+        .iter()
+        .any(|badge| &badge.attestation == attestation)
+    {
+        return Err("badge exists".into());
+    }
+    match attestation {
+        Attestation::RealmOfficial(name) => {
+            let realm = state.realms.get(name).ok_or("no realm found")?;
+            if realm.archived {
+                return Err("realm is archived".into());
+            }
+        }
+        Attestation::DomainVerified(domain) => {
+            if domain.is_empty() || !domain.contains('.') || domain.contains(char::is_whitespace) {
+                return Err("invalid domain".into());
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn grant(
+    state: &mut State,
+    user_id: UserId,
+    attestation: Attestation,
+    issuer: Issuer,
+    timestamp: u64,
+) -> Result<(), String> {
+    validate(state, user_id, &attestation)?;
+    let user = state.users.get_mut(&user_id).expect("no user found");
+    user.notify(format!("You received the badge `{:?}`.", attestation));
+    let log = format!("@{} received the badge `{:?}`.", user.name, attestation);
+    user.badges.push(Badge {
+        attestation,
+        issuer,
+        timestamp,
+    });
+    state.logger.info(log);
+    certify(state, user_id);
+    Ok(())
+}
+
+pub fn revoke(state: &mut State, user_id: UserId, attestation: &Attestation) -> Result<(), String> {
+    let user = state.users.get_mut(&user_id).ok_or("no user found")?;
+    let len = user.badges.len();
+    user.badges
+        .retain(|badge| &badge.attestation != attestation);
+    if user.badges.len() == len {
+        return Err("no badge found".into());
+    }
+    let user_name = user.name.clone();
+    state.logger.info(format!(
+        "The badge `{:?}` of @{} was revoked.",
+        attestation, user_name
+    ));
+    certify(state, user_id);
+    Ok(())
+}
+
+fn realm_controller(state: &State, principal: Principal, realm: &str) -> Result<UserId, String> {
+    let user_id = state
+        .principal_to_user(principal)
+        .ok_or("no user found")?
+        .id;
+    if !state
+        .realms
+        .get(realm)
+        .ok_or("no realm found")?
+        .controllers
+        .contains(&user_id)
+    {
+        return Err("only realm controllers can manage realm badges".into());
+    }
+    Ok(user_id)
+}
+
+/// Lets a realm controller mark the user as an official account of the realm.
+pub fn issue_realm_badge(
+    state: &mut State,
+    principal: Principal,
+    user_id: UserId,
+    realm: String,
+    now: u64,
+) -> Result<(), String> {
+    let controller_id = realm_controller(state, principal, &realm)?;
+    grant(
+        state,
+        user_id,
+        Attestation::RealmOfficial(realm),
+        Issuer::RealmController(controller_id),
+        now,
+    )
+}
+
+/// Lets a realm controller revoke realm badges issued by any controller of the realm.
+pub fn revoke_realm_badge(
+    state: &mut State,
+    principal: Principal,
+    user_id: UserId,
+    realm: String,
+) -> Result<(), String> {
+    realm_controller(state, principal, &realm)?;
+    let attestation = Attestation::RealmOfficial(realm);
+    if state
+        .users
+        .get(&user_id)
+        .ok_or("no user found")?
+        .badges
+        .iter()
+        .any(|badge| badge.attestation == attestation && matches!(badge.issuer, Issuer::Dao(_)))
+    {
+        return Err("badges issued by the DAO can only be revoked by proposal".into());
+    }
+    revoke(state, user_id, &attestation)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        env::tests::{create_user, pr},
+        STATE,
+    };
+
+    #[test]
+    fn test_badges() {
+        STATE.with(|cell| {
+            cell.replace(Default::default());
+            let state = &mut *cell.borrow_mut();
+            let controller = create_user(state, pr(0));
+            let id = create_user(state, pr(1));
+            state.realms.insert(
+                "REALM".into(),
+                Realm {
+                    controllers: vec![controller].into_iter().collect(),
+                    ..Default::default()
+                },
+            );
+
+            assert_eq!(
+                issue_realm_badge(state, pr(1), id, "REALM".into(), 0),
+                Err("only realm controllers can manage realm badges".into())
+            );
+            assert!(issue_realm_badge(state, pr(0), id, "REALM".into(), 0).is_ok());
+            assert_eq!(
+                issue_realm_badge(state, pr(0), id, "REALM".into(), 0),
+                Err("badge exists".into())
+            );
+            let badge = state.users.get(&id).unwrap().badges[0].clone();
+            assert_eq!(badge.issuer, Issuer::RealmController(controller));
+
+            assert_eq!(
+                grant(
+                    state,
+                    id,
+                    Attestation::DomainVerified("example".into()),
+                    Issuer::Dao(0),
+                    0
+                ),
+                Err("invalid domain".into())
+            );
+            assert!(grant(
+                state,
+                id,
+                Attestation::DomainVerified("example.com".into()),
+                Issuer::Dao(0),
+                0
+            )
+            .is_ok());
+
+            assert!(revoke_realm_badge(state, pr(0), id, "REALM".into()).is_ok());
+            assert_eq!(
+                revoke_realm_badge(state, pr(0), id, "REALM".into()),
+                Err("no badge found".into())
+            );
+            assert_eq!(state.users.get(&id).unwrap().badges.len(), 1);
+
+            // the certified hash follows the badges
+            let badges = state.users.get(&id).unwrap().badges.clone();
+            let hash: [u8; 32] = Sha256::digest(certified_content(&badges)).into();
+            assert_eq!(state.badge_hashes.get(&id), Some(&hash));
+            assert!(revoke(
+                state,
+                id,
+                &Attestation::DomainVerified("example.com".into())
+            )
+            .is_ok());
+            assert!(state.badge_hashes.is_empty());
+        })
+    }
+}
@@ -43,14 +43,53 @@ pub struct Config {
     pub min_cycle_balance_main: u64,
 
     pub max_bucket_size: u64,
+    pub max_erased_blobs: usize,
+    pub replicate_blobs: bool,
+    pub max_verified_blobs: usize,
+    pub bucket_upgrade_stage_size: usize,
 
     pub max_posts_per_hour: u8,
     pub max_comments_per_hour: u8,
 
+    pub spam_min_words: usize,
+    pub spam_lookback_posts: usize,
+    pub spam_similarity_percentage: u64,
+    pub spam_review_score: u64,
+    pub spam_rejection_score: u64,
+    pub max_held_post_blobs_bytes: usize,
+    pub max_held_blobs_bytes: usize,
+
+    pub max_draft_blobs: usize,
+    pub max_draft_size_bytes: usize,
+    pub max_drafts_bytes: usize,
+    pub draft_expiration_hours: u64,
+
+    pub max_guardians: usize,
+    pub recovery_waiting_period_days: u64,
+    pub recovery_expiration_weeks: u64,
+
+    pub max_delegates: usize,
+    pub max_delegate_log_entries: usize,
+
+    pub account_deletion_cooling_off_days: u64,
+
+    pub max_compaction_moves: usize,
+    pub max_compaction_scanned: usize,
+
     pub feed_page_size: usize,
+    pub for_you_feed_candidates: usize,
+    pub for_you_feed_candidates_per_source: usize,
+    pub for_you_feed_scan_limit: usize,
+    pub export_page_size: usize,
 
     pub reporting_penalty_post: Cycles,
     pub reporting_penalty_misbehaviour: Cycles,
+    pub reporting_penalty_spam: Cycles,
+    pub reporting_penalty_abuse: Cycles,
+    pub reporting_penalty_illegal_content: Cycles,
+    pub reporting_penalty_impersonation: Cycles,
+    pub reporting_penalty_off_topic: Cycles,
+    pub report_appeal_deposit: Cycles,
 
     pub minimal_tip: Cycles,
 
@@ -73,6 +112,7 @@ pub struct Config {
     pub max_realm_logo_len: usize,
 
     pub realm_cleanup_penalty: Cycles,
+    pub realm_abandonment_weeks: u64,
 
     pub response_reward: Cycles,
 
@@ -86,16 +126,32 @@ pub struct Config {
     pub min_stalwart_activity_weeks: u8,
     pub min_stalwart_account_age_weeks: u8,
     pub stalwart_moderation_reward: Cycles,
+    pub stalwart_min_moderation_accuracy: u64,
+    pub stalwart_min_resolved_votes: u32,
+    pub stalwart_max_consecutive_abstentions: u32,
+    pub stalwart_moderation_window_weeks: u64,
 
     // percentage of stalwarts needed to confirm a report
     pub report_confirmation_percentage: u16,
 
     pub hot_post_engagement_percentage: f32,
+    pub hot_score_comment_weight: Karma,
+    pub hot_score_tip_weight: Karma,
+    pub hot_score_half_life_hours: u64,
 
     pub max_post_length: usize,
     pub max_tag_length: usize,
     pub max_user_info_length: usize,
     pub max_blob_size_bytes: usize,
+    pub max_media_size_bytes: usize,
+    pub max_image_dimension: u32,
+    pub max_decoded_image_dimension: u32,
+    pub max_image_decoding_bytes: u64,
+    pub thumbnail_size: u32,
+    pub max_bookmark_collections: usize,
+    pub max_bookmark_collection_name: usize,
+    pub max_bookmark_note_length: usize,
+    pub max_bookmarks_per_collection: usize,
 
     pub min_cycles_for_inviting: Cycles,
     pub invites_budget_cycles: Cycles,
@@ -214,8 +270,13 @@ pub const CONFIG: &Config = &Config {
     minimal_tip: 1,
 
     realm_cleanup_penalty: 10,
+    realm_abandonment_weeks: 12,
 
     max_bucket_size: 1024 * 1024 * 1024 * 48, // 48Gb
+    max_erased_blobs: 100,
+    replicate_blobs: true,
+    max_verified_blobs: 100,
+    bucket_upgrade_stage_size: 4,
 
     #[cfg(feature = "dev")]
     max_posts_per_hour: 15,
@@ -223,10 +284,45 @@ pub const CONFIG: &Config = &Config {
     max_posts_per_hour: 3,
     max_comments_per_hour: 15,
 
+    spam_min_words: 5,
+    spam_lookback_posts: 100,
+    spam_similarity_percentage: 80,
+    spam_review_score: 50,
+    spam_rejection_score: 100,
+    max_held_post_blobs_bytes: 2 * 1024 * 1024,
+    max_held_blobs_bytes: 64 * 1024 * 1024,
+
+    max_draft_blobs: 16,
+    max_draft_size_bytes: 64 * 1024 * 1024,
+    max_drafts_bytes: 512 * 1024 * 1024,
+    draft_expiration_hours: 2,
+
+    max_guardians: 10,
+    recovery_waiting_period_days: 7,
+    recovery_expiration_weeks: 4,
+
+    max_delegates: 10,
+    max_delegate_log_entries: 100,
+
+    account_deletion_cooling_off_days: 14,
+
+    max_compaction_moves: 1000,
+    max_compaction_scanned: 20000,
+
     feed_page_size: 30,
+    for_you_feed_candidates: 1000,
+    for_you_feed_candidates_per_source: 100,
+    for_you_feed_scan_limit: 10000,
+    export_page_size: 100,
 
     reporting_penalty_post: 200,
     reporting_penalty_misbehaviour: 1000,
+    reporting_penalty_spam: 200,
+    reporting_penalty_abuse: 500,
+    reporting_penalty_illegal_content: 2000,
+    reporting_penalty_impersonation: 1000,
+    reporting_penalty_off_topic: 50,
+    report_appeal_deposit: 100,
 
     min_cycles_for_inviting: 50,
     invites_budget_cycles: 300,
@@ -267,13 +363,29 @@ pub const CONFIG: &Config = &Config {
     min_stalwart_account_age_weeks: 26,
 
     stalwart_moderation_reward: 20,
+    stalwart_min_moderation_accuracy: 60,
+    stalwart_min_resolved_votes: 10,
+    stalwart_max_consecutive_abstentions: 20,
+    stalwart_moderation_window_weeks: 12,
 
     hot_post_engagement_percentage: 0.01,
+    hot_score_comment_weight: 3,
+    hot_score_tip_weight: 10,
+    hot_score_half_life_hours: 12,
 
     max_post_length: 15000,
     max_tag_length: 30,
     max_user_info_length: 500,
     max_blob_size_bytes: 460800,
+    max_media_size_bytes: 20 * 1024 * 1024,
+    max_image_dimension: 2560,
+    max_decoded_image_dimension: 8192,
+    max_image_decoding_bytes: 256 * 1024 * 1024,
+    thumbnail_size: 320,
+    max_bookmark_collections: 20,
+    max_bookmark_collection_name: 32,
+    max_bookmark_note_length: 500,
+    max_bookmarks_per_collection: 1000,
 
     online_activity_minutes: 10 * 60000000000_u64,
 
@@ -0,0 +1,349 @@
+use super::user::UserId;
+use super::*;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Permission {
+    Post,
+    Comment,
+    React,
+    Tip,
+}
+
+// A principal acting on behalf of a user within the granted permissions. All cycles spent by
+// the delegate count against its weekly limit, all ICP tipped (including fees) against its
+// weekly ICP limit.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct Delegate {
+    pub permissions: BTreeSet<Permission>,
+    pub weekly_cycles_limit: Cycles,
+    pub cycles_spent: Cycles,
+    #[serde(default)]
+    pub weekly_e8s_limit: u64,
+    #[serde(default)]
+    pub e8s_spent: u64,
+    pub expiry: Option<u64>,
+    pub realm: Option<String>,
+    // Audit log of actions performed by the delegate: (timestamp, cycles, action).
+    pub log: VecDeque<(u64, Cycles, String)>,
+}
+
+impl Delegate {
+    pub fn authorize(
+        &self,
+        permission: Permission,
+        realm: Option<&String>,
+        cycles: Cycles,
+        now: u64,
+    ) -> Result<(), String> {
+        if self.expiry.map(|expiry| expiry <= now).unwrap_or_default() {
+            return Err("delegation expired".into());
+        }
+        if !self.permissions.contains(&permission) {
+            return Err(format!("delegate has no {:?} permission", permission));
+        }
+        if self.realm.is_some() && self.realm.as_ref() != realm {
+            return Err("delegate is restricted to another realm".into());
+        }
+        if self.cycles_spent + cycles > self.weekly_cycles_limit {
+            return Err("weekly cycles limit of the delegate reached".into());
+        }
+        Ok(())
+    }
+}
+
+/// Adds a new delegate or replaces the settings of an existing one.
+#[allow(clippy::too_many_arguments)]
+pub fn set_delegate(
+    state: &mut State,
+    principal: Principal,
+    delegate_principal: String,
+    permissions: BTreeSet<Permission>,
+    weekly_cycles_limit: Cycles,
+    weekly_e8s_limit: u64,
+    expiry: Option<u64>,
+    realm: Option<String>,
+) -> Result<(), String> {
+    let user_id = state
+        .principal_to_user(principal)
+        .ok_or("no user found")?
+        .id;
+    let delegate = Principal::from_text(&delegate_principal).map_err(|e| e.to_string())?;
+    if state.principals.contains_key(&delegate) {
+        return Err("principal already controls a user".into());
+    }
+    if state.users.values().any(|user| {
+        user.id != user_id
+            && (user.controllers.contains(&delegate_principal)
+                || user.delegates.contains_key(&delegate_principal))
+    }) {
+        return Err("principal is already a delegate of another user".into());
+    }
+    if permissions.is_empty() {
+        return Err("no permissions specified".into());
+    }
+    if let Some(name) = &realm {
+        if !state.realms.contains_key(name) {
+            return Err("no realm found".into());
+        }
+    }
+    let user = state.users.get_mut(&user_id).expect("no user found");
+    if !user.delegates.contains_key(&delegate_principal)
+        && user.delegates.len() >= CONFIG.max_delegates
+    {
+        return Err("too many delegates".into());
+    }
+    let entry = user.delegates.entry(delegate_principal).or_default();
+    entry.permissions = permissions;
+    entry.weekly_cycles_limit = weekly_cycles_limit;
+    entry.weekly_e8s_limit = weekly_e8s_limit;
+    entry.expiry = expiry;
+    entry.realm = realm;
+    Ok(())
+}
+
+pub fn remove_delegate(
+    state: &mut State,
+    principal: Principal,
+    delegate_principal: &str,
+) -> Result<(), String> {
+    state
+        .principal_to_user_mut(principal)
+        .ok_or("no user found")?
+        .delegates
+        .remove(delegate_principal)
+        .map(|_| ())
+        .ok_or_else(|| "no delegate found".into())
+}
+
+/// Returns the user acting through the principal: either the user owning it or the delegator
+/// of a delegate authorized for the action.
+pub fn resolve(
+    state: &State,
+    principal: Principal,
+    permission: Permission,
+    realm: Option<&String>,
+    cycles: Cycles,
+    now: u64,
+) -> Result<UserId, String> {
+    if let Some(user) = state.principal_to_user(principal) {
+        return Ok(user.id);
+    }
+    let delegate_principal = principal.to_string();
+    let (user, delegate) = state
+        .users
+        .values()
+        .find_map(|user| {
+            user.delegates
+                .get(&delegate_principal)
+                .map(|delegate| (user, delegate))
+        })
+        .ok_or("no user found")?;
+    delegate.authorize(permission, realm, cycles, now)?;
+    Ok(user.id)
+}
+
+/// Records an action of the delegate in the audit log of its delegator. Does nothing if the
+/// principal is not a delegate of the user.
+pub fn record<T: ToString>(
+    state: &mut State,
+    user_id: UserId,
+    principal: Principal,
+    cycles: Cycles,
+    action: T,
+    now: u64,
+) {
+    if let Some(delegate) = state
+        .users
+        .get_mut(&user_id)
+        .and_then(|user| user.delegates.get_mut(&principal.to_string()))
+    {
+        delegate.cycles_spent += cycles;
+        delegate.log.push_front((now, cycles, action.to_string()));
+        delegate.log.truncate(CONFIG.max_delegate_log_entries);
+    }
+}
+
+/// Counts ICP spent by a delegate against its weekly ICP limit. Does nothing if the principal is
+/// not a delegate of the user.
+pub fn spend_e8s(
+    state: &mut State,
+    user_id: UserId,
+    principal: Principal,
+    e8s: u64,
+) -> Result<(), String> {
+    if let Some(delegate) = state
+        .users
+        .get_mut(&user_id)
+        .and_then(|user| user.delegates.get_mut(&principal.to_string()))
+    {
+        if delegate.e8s_spent.saturating_add(e8s) > delegate.weekly_e8s_limit {
+            return Err("weekly ICP limit of the delegate reached".into());
+        }
+        delegate.e8s_spent += e8s;
+    }
+    Ok(())
+}
+
+/// Returns ICP of a failed transfer to the weekly ICP limit of the delegate.
+pub fn refund_e8s(state: &mut State, user_id: UserId, principal: Principal, e8s: u64) {
+    if let Some(delegate) = state
+        .users
+        .get_mut(&user_id)
+        .and_then(|user| user.delegates.get_mut(&principal.to_string()))
+    {
+        delegate.e8s_spent = delegate.e8s_spent.saturating_sub(e8s);
+    }
+}
+
+/// Resets weekly spendings and removes expired delegates.
+pub fn clean_up(user: &mut User, now: u64) {
+    user.delegates.retain(|_, delegate| {
+        delegate.cycles_spent = 0;
+        delegate.e8s_spent = 0;
+        delegate.expiry.map(|expiry| expiry > now).unwrap_or(true)
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        env::tests::{create_user, pr},
+        STATE,
+    };
+
+    #[test]
+    fn test_delegates() {
+        STATE.with(|cell| {
+            cell.replace(Default::default());
+            let state = &mut *cell.borrow_mut();
+            let id = create_user(state, pr(0));
+            create_user(state, pr(1));
+            let delegate = pr(5);
+            let now = WEEK;
+            let post = |state: &mut State, parent: Option<PostId>| {
+                Post::create(
+                    state,
+                    "Hello".into(),
+                    &[],
+                    delegate,
+                    now,
+                    parent,
+                    None,
+                    None,
+                )
+            };
+
+            assert_eq!(
+                set_delegate(
+                    state,
+                    pr(0),
+                    pr(1).to_string(),
+                    vec![Permission::Post].into_iter().collect(),
+                    3,
+                    0,
+                    None,
+                    None
+                ),
+                Err("principal already controls a user".into())
+            );
+            assert_eq!(
+                set_delegate(
+                    state,
+                    pr(0),
+                    delegate.to_string(),
+                    vec![Permission::Post].into_iter().collect(),
+                    3,
+                    0,
+                    None,
+                    Some("REALM".into())
+                ),
+                Err("no realm found".into())
+            );
+            assert!(set_delegate(
+                state,
+                pr(0),
+                delegate.to_string(),
+                vec![Permission::Post].into_iter().collect(),
+                3,
+                0,
+                Some(now + DAY),
+                None
+            )
+            .is_ok());
+
+            // the delegate posts on behalf of the user
+            let post_id = post(state, None).unwrap();
+            assert_eq!(Post::get(state, &post_id).unwrap().user, id);
+            let user = state.users.get(&id).unwrap();
+            let log = &user.delegates.get(&delegate.to_string()).unwrap().log;
+            assert_eq!(log.len(), 1);
+            assert_eq!(log[0], (now, CONFIG.post_cost, format!("post {}", post_id)));
+
+            // but only within the granted permissions
+            assert_eq!(
+                post(state, Some(post_id)),
+                Err("delegate has no Comment permission".into())
+            );
+            assert_eq!(
+                state.react(delegate, post_id, 10, now),
+                Err("delegate has no React permission".into())
+            );
+
+            // and the weekly limit
+            assert_eq!(
+                post(state, None),
+                Err("weekly cycles limit of the delegate reached".into())
+            );
+            state.clean_up(now);
+            assert!(post(state, None).is_ok());
+
+            // ICP tips are limited separately
+            assert!(spend_e8s(state, id, delegate, 1).is_err());
+            assert!(set_delegate(
+                state,
+                pr(0),
+                delegate.to_string(),
+                vec![Permission::Post, Permission::Tip]
+                    .into_iter()
+                    .collect(),
+                3,
+                100,
+                Some(now + DAY),
+                None
+            )
+            .is_ok());
+            assert!(spend_e8s(state, id, delegate, 60).is_ok());
+            assert_eq!(
+                spend_e8s(state, id, delegate, 50),
+                Err("weekly ICP limit of the delegate reached".into())
+            );
+            refund_e8s(state, id, delegate, 60);
+            assert!(spend_e8s(state, id, delegate, 100).is_ok());
+            // the user's own spendings are not limited
+            assert!(spend_e8s(state, id, pr(0), 1000).is_ok());
+
+            // expired delegates can't act and get removed
+            assert_eq!(
+                Post::create(
+                    state,
+                    "Hello".into(),
+                    &[],
+                    delegate,
+                    now + DAY,
+                    None,
+                    None,
+                    None
+                ),
+                Err("delegation expired".into())
+            );
+            state.clean_up(now + DAY);
+            assert!(state.users.get(&id).unwrap().delegates.is_empty());
+            assert_eq!(
+                remove_delegate(state, pr(0), &delegate.to_string()),
+                Err("no delegate found".into())
+            );
+        })
+    }
+}
@@ -0,0 +1,262 @@
+//! Self-service account deletion.
+//!
+//! A user requests the deletion and can cancel it during the cooling-off period of
+//! `CONFIG.account_deletion_cooling_off_days`. After that period the account gets deleted by
+//! the daily chores according to the following rules:
+//!
+//! - all posts of the user are replaced by tombstones, the threads they belong to stay intact,
+//! - the profile, settings, drafts, inbox, bookmarks, filters and all relations to other users
+//!   and realms are wiped, the handle gets released,
+//! - the principal gets unlinked from the account and can create a new one,
+//! - remaining cycles and karma are forfeited and the cycles are burned,
+//! - remaining tokens are burned by a transfer to the minting account, balances not covering the
+//!   transaction fee stay untouched,
+//! - ICP deposited on the user's account is not touched, so it has to be withdrawn before the
+//!   cooling-off period ends.
+
+use super::post::PostId;
+use super::user::UserId;
+use super::*;
+
+pub fn request_deletion(state: &mut State, principal: Principal, now: u64) -> Result<(), String> {
+    if state.voted_on_pending_proposal(principal) {
+        return Err("pending proposal with the current principal as voter exists".into());
+    }
+    let user = state
+        .principal_to_user_mut(principal)
+        .ok_or("no user found")?;
+    if user.deletion_requested.is_some() {
+        return Err("deletion already requested".into());
+    }
+    user.deletion_requested = Some(now);
+    user.notify(format!(
+        "Your account will be deleted in {} days. You can cancel the deletion until then. \
+        Please withdraw your ICP before the deletion; remaining cycles and tokens will be burned.",
+        CONFIG.account_deletion_cooling_off_days
+    ));
+    Ok(())
+}
+
+pub fn cancel_deletion(state: &mut State, principal: Principal) -> Result<(), String> {
+    state
+        .principal_to_user_mut(principal)
+        .ok_or("no user found")?
+        .deletion_requested
+        .take()
+        .map(|_| ())
+        .ok_or_else(|| "no deletion requested".into())
+}
+
+/// Deletes all accounts with expired cooling-off periods.
+pub fn delete_accounts(state: &mut State, now: u64) {
+    let cooling_off = CONFIG.account_deletion_cooling_off_days * DAY;
+    for user_id in state
+        .users
+        .values()
+        .filter_map(|user| {
+            user.deletion_requested
+                .filter(|timestamp| timestamp + cooling_off <= now)
+                .map(|_| user.id)
+        })
+        .collect::<Vec<_>>()
+    {
+        if let Err(err) = delete_account(state, user_id) {
+            state.logger.error(format!(
+                "couldn't delete the account of user {}: {}",
+                user_id, err
+            ));
+        }
+    }
+}
+
+fn delete_account(state: &mut State, user_id: UserId) -> Result<(), String> {
+    let user = state.users.get(&user_id).ok_or("no user found")?;
+    let principal = user.principal;
+    if state.voted_on_pending_proposal(principal) {
+        return Err("pending proposal with the user as voter exists".into());
+    }
+    let name = user.name.clone();
+    let cycles = user.cycles();
+    let followees = user.followees.clone();
+    let followers = user.followers.clone();
+    let realms = user.realms.clone();
+    let muted = user.mutes.users.keys().copied().collect::<Vec<_>>();
+    let muted_by = user.muted_by.clone();
+    let post_ids = user
+        .posts(state)
+        .map(|post| post.id)
+        .collect::<Vec<PostId>>();
+
+    // Burning the tokens is the only step which can fail, so it comes before any other change
+    // and a failed deletion leaves the account intact for the next attempt.
+    let token_account = account(principal);
+    if state
+        .balances
+        .get(&token_account)
+        .copied()
+        .unwrap_or_default()
+        > CONFIG.transaction_fee
+    {
+        crate::token::move_funds(state, &token_account, account(Principal::anonymous()))
+            .map_err(|err| format!("couldn't burn tokens: {:?}", err))?;
+    }
+    if cycles > 0 {
+        state
+            .charge(user_id, cycles, "account deletion")
+            .expect("couldn't charge user");
+    }
+
+    for proposal in state
+        .proposals
+        .values_mut()
+        .filter(|p| p.proposer == user_id && p.status == Status::Open)
+    {
+        proposal.status = Status::Cancelled;
+    }
+    for post_id in post_ids {
+        state.hot_ranking.remove(&post_id);
+        state.pending_polls.remove(&post_id);
+        Post::mutate(state, &post_id, |post| {
+            // the hash of an empty string marks the post as deleted without keeping any trace
+            // of its content
+            post.delete(vec![String::new()]);
+            Ok(())
+        })
+        .expect("couldn't delete post");
+    }
+
+    for id in followees {
+        if let Some(user) = state.users.get_mut(&id) {
+            user.followers.remove(&user_id);
+        }
+    }
+    for id in followers {
+        if let Some(user) = state.users.get_mut(&id) {
+            user.followees.remove(&user_id);
+        }
+    }
+    for id in muted {
+        if let Some(user) = state.users.get_mut(&id) {
+            user.muted_by.remove(&user_id);
+        }
+    }
+    for id in muted_by {
+        if let Some(user) = state.users.get_mut(&id) {
+            user.mutes.users.remove(&user_id);
+        }
+    }
+    for user in state.users.values_mut() {
+        if user.guardians.remove(&user_id) {
+            user.guardian_threshold = user.guardian_threshold.min(user.guardians.len());
+        }
+    }
+    for name in realms {
+        if let Some(realm) = state.realms.get_mut(&name) {
+            realm.num_members = realm.num_members.saturating_sub(1);
+        }
+    }
+    for realm in state.realms.values_mut() {
+        realm.controllers.remove(&user_id);
+    }
+
+    state.principals.remove(&principal);
+    state.held_posts.remove(&user_id);
+    state.recoveries.remove(&user_id);
+    state
+        .users
+        .get_mut(&user_id)
+        .expect("no user found")
+        .anonymize();
+    badges::certify(state, user_id);
+    state
+        .logger
+        .info(format!("@{} deleted their account 👋", name));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        env::tests::{create_user, pr},
+        STATE,
+    };
+
+    #[test]
+    fn test_account_deletion() {
+        STATE.with(|cell| {
+            cell.replace(Default::default());
+            let state = &mut *cell.borrow_mut();
+            let id = create_user(state, pr(0));
+            let other = create_user(state, pr(1));
+            let name = state.users.get(&id).unwrap().name.clone();
+            assert!(state.toggle_following_user(pr(0), other));
+            assert!(state.toggle_following_user(pr(1), id));
+            let post_id =
+                Post::create(state, "Hello".into(), &[], pr(0), 0, None, None, None).unwrap();
+            let comment_id =
+                Post::create(state, "Hi".into(), &[], pr(1), 0, Some(post_id), None, None).unwrap();
+            crate::token::mint(state, account(pr(0)), 1000);
+            let now = WEEK;
+
+            // the deletion can be cancelled during the cooling-off period
+            assert!(request_deletion(state, pr(0), now).is_ok());
+            assert_eq!(
+                request_deletion(state, pr(0), now),
+                Err("deletion already requested".into())
+            );
+            assert!(cancel_deletion(state, pr(0)).is_ok());
+            assert_eq!(
+                cancel_deletion(state, pr(0)),
+                Err("no deletion requested".into())
+            );
+            assert!(request_deletion(state, pr(0), now).is_ok());
+            let deletion_time = now + CONFIG.account_deletion_cooling_off_days * DAY;
+            delete_accounts(state, deletion_time - 1);
+            assert!(state.principal_to_user(pr(0)).is_some());
+
+            let burned_cycles = state.burned_cycles;
+            delete_accounts(state, deletion_time);
+            assert!(state.principal_to_user(pr(0)).is_none());
+            assert!(state.burned_cycles > burned_cycles);
+            assert!(!state.balances.contains_key(&account(pr(0))));
+
+            // the profile is wiped and the handle is released
+            let user = state.users.get(&id).unwrap();
+            assert_eq!(user.cycles(), 0);
+            assert!(user.followees.is_empty());
+            assert!(user.inbox.is_empty());
+            assert_ne!(user.name, name);
+            assert!(state.validate_username(&name).is_ok());
+            let other_user = state.users.get(&other).unwrap();
+            assert!(!other_user.followers.contains(&id));
+            assert!(!other_user.followees.contains(&id));
+
+            // posts are replaced by tombstones and the thread stays intact
+            let post = Post::get(state, &post_id).unwrap();
+            assert!(post.is_deleted());
+            assert!(post.body.is_empty());
+            assert_eq!(post.children, vec![comment_id]);
+            assert_eq!(Post::get(state, &comment_id).unwrap().body, "Hi");
+
+            // the principal can create a new account
+            assert!(!state.principals.contains_key(&pr(0)));
+
+            // accounts without cycles get deleted too, token balances not covering the fee are
+            // left alone
+            let user = state.principal_to_user_mut(pr(1)).unwrap();
+            let cycles = user.cycles();
+            user.change_cycles(cycles, CyclesDelta::Minus, "").unwrap();
+            crate::token::mint(state, account(pr(1)), CONFIG.transaction_fee);
+            let ledger_len = state.ledger.len();
+            assert!(request_deletion(state, pr(1), now).is_ok());
+            delete_accounts(state, deletion_time);
+            assert!(state.principal_to_user(pr(1)).is_none());
+            assert_eq!(
+                state.balances.get(&account(pr(1))),
+                Some(&CONFIG.transaction_fee)
+            );
+            assert_eq!(state.ledger.len(), ledger_len);
+        })
+    }
+}
@@ -0,0 +1,271 @@
+//! Export of user data in a portable format.
+//!
+//! An export consists of pages numbered from 0. Every page is a JSON object with the following
+//! fields:
+//!
+//! - `schema_version`: the version of this schema; incremented on every incompatible change,
+//! - `instance`: the name of the exporting instance,
+//! - `page`: the page number,
+//! - `has_more`: true if more pages with posts or transactions follow,
+//! - `profile`: the profile of the user (only on page 0),
+//! - `posts`: up to `CONFIG.export_page_size` posts of the user from the newest to the oldest,
+//!   including archived posts, their edit patches and references to their blobs,
+//! - `transactions`: up to `CONFIG.export_page_size` token transactions involving the user
+//!   from the newest to the oldest.
+//!
+//! All timestamps are nanoseconds since the UNIX epoch. Principals are encoded as text,
+//! subaccounts and memos as hex strings.
+
+use super::post::{Extension, PostId};
+use super::token::Account;
+use super::user::{BookmarkCollection, UserId};
+use super::*;
+use serde::Serialize;
+
+pub const SCHEMA_VERSION: u32 = 1;
+
+#[derive(Serialize)]
+pub struct ExportedProfile {
+    pub id: UserId,
+    pub name: String,
+    pub previous_names: Vec<String>,
+    pub about: String,
+    pub principal: String,
+    pub account: String,
+    pub timestamp: u64,
+    pub karma: Karma,
+    pub cycles: Cycles,
+    pub bookmarks: Vec<PostId>,
+    pub bookmark_collections: BTreeMap<String, BookmarkCollection>,
+    pub feeds: Vec<BTreeSet<String>>,
+    pub followees: BTreeSet<UserId>,
+    pub realms: Vec<String>,
+    // (timestamp, type, amount, description)
+    pub accounting: Vec<(u64, String, i64, String)>,
+}
+
+#[derive(Serialize)]
+pub struct ExportedBlob {
+    pub id: String,
+    pub bucket: String,
+    pub offset: u64,
+    pub length: usize,
+}
+
+#[derive(Serialize)]
+pub struct ExportedPost {
+    pub id: PostId,
+    pub parent: Option<PostId>,
+    pub timestamp: u64,
+    pub body: String,
+    pub tags: BTreeSet<String>,
+    pub realm: Option<String>,
+    // (timestamp, patch), the body can be restored by applying the patches in reverse order
+    pub patches: Vec<(u64, String)>,
+    pub blobs: Vec<ExportedBlob>,
+    pub extension: Option<Extension>,
+    pub reactions: BTreeMap<u16, BTreeSet<UserId>>,
+    // (user id, e8s)
+    pub tips: Vec<(UserId, u64)>,
+    pub archived: bool,
+}
+
+#[derive(Serialize)]
+pub struct ExportedAccount {
+    pub owner: String,
+    pub subaccount: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct ExportedTransaction {
+    pub timestamp: u64,
+    pub from: ExportedAccount,
+    pub to: ExportedAccount,
+    pub amount: Token,
+    pub fee: Token,
+    pub memo: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct ExportPage {
+    pub schema_version: u32,
+    pub instance: String,
+    pub page: usize,
+    pub has_more: bool,
+    pub profile: Option<ExportedProfile>,
+    pub posts: Vec<ExportedPost>,
+    pub transactions: Vec<ExportedTransaction>,
+}
+
+fn account(account: &Account) -> ExportedAccount {
+    ExportedAccount {
+        owner: account.owner.to_string(),
+        subaccount: account.subaccount.as_ref().map(hex::encode),
+    }
+}
+
+fn post(post: &Post) -> ExportedPost {
+    ExportedPost {
+        id: post.id,
+        parent: post.parent,
+        timestamp: post.timestamp(),
+        body: post.body.clone(),
+        tags: post.tags.clone(),
+        realm: post.realm.clone(),
+        patches: post.patches.clone(),
+        blobs: post
+            .files
+            .iter()
+            .map(|(key, (offset, length))| {
+                let mut parts = key.split('@');
+                ExportedBlob {
+                    id: parts.next().unwrap_or_default().to_string(),
+                    bucket: parts.next().unwrap_or_default().to_string(),
+                    offset: *offset,
+                    length: *length,
+                }
+            })
+            .collect(),
+        extension: post.extension.clone(),
+        reactions: post.reactions.clone(),
+        tips: post.tips.clone(),
+        archived: post.archived,
+    }
+}
+
+/// Returns the given page of the caller's data export.
+pub fn export_user(state: &State, principal: Principal, page: usize) -> Result<ExportPage, String> {
+    let user = state.principal_to_user(principal).ok_or("no user found")?;
+    let page_size = CONFIG.export_page_size;
+
+    let mut posts = user
+        .posts(state)
+        .skip(page * page_size)
+        .take(page_size + 1)
+        .map(post)
+        .collect::<Vec<_>>();
+    let mut transactions = token::principal_transactions(state, user.principal)
+        .map(|(_, tx)| tx)
+        .skip(page * page_size)
+        .take(page_size + 1)
+        .map(|tx| ExportedTransaction {
+            timestamp: tx.timestamp,
+            from: account(&tx.from),
+            to: account(&tx.to),
+            amount: tx.amount,
+            fee: tx.fee,
+            memo: tx.memo.as_ref().map(hex::encode),
+        })
+        .collect::<Vec<_>>();
+    let has_more = posts.len() > page_size || transactions.len() > page_size;
+    posts.truncate(page_size);
+    transactions.truncate(page_size);
+
+    let profile = (page == 0).then(|| ExportedProfile {
+        id: user.id,
+        name: user.name.clone(),
+        previous_names: user.previous_names.clone(),
+        about: user.about.clone(),
+        principal: user.principal.to_string(),
+        account: user.account.clone(),
+        timestamp: user.timestamp,
+        karma: user.karma(),
+        cycles: user.cycles(),
+        bookmarks: user.bookmarks.iter().cloned().collect(),
+        bookmark_collections: user.bookmark_collections.clone(),
+        feeds: user.feeds.clone(),
+        followees: user.followees.clone(),
+        realms: user.realms.clone(),
+        accounting: user.accounting.iter().cloned().collect(),
+    });
+
+    Ok(ExportPage {
+        schema_version: SCHEMA_VERSION,
+        instance: CONFIG.name.to_string(),
+        page,
+        has_more,
+        profile,
+        posts,
+        transactions,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        env::tests::{create_user, pr},
+        STATE,
+    };
+
+    #[test]
+    fn test_export_user() {
+        STATE.with(|cell| {
+            cell.replace(Default::default());
+            let state = &mut *cell.borrow_mut();
+            let id = create_user(state, pr(0));
+            create_user(state, pr(1));
+            let page_size = CONFIG.export_page_size;
+            for i in 0..page_size + 1 {
+                Post::create(
+                    state,
+                    format!("Post {}", i),
+                    &[],
+                    pr(1),
+                    i as u64 * HOUR,
+                    None,
+                    None,
+                    None,
+                )
+                .unwrap();
+            }
+            for i in 0..2 {
+                Post::create(
+                    state,
+                    format!("Post {}", i),
+                    &[],
+                    pr(0),
+                    0,
+                    None,
+                    None,
+                    None,
+                )
+                .unwrap();
+            }
+            let post_id = state.next_post_id - 1;
+            Post::mutate(state, &post_id, |post| {
+                post.files.insert("blob@bucket".into(), (8, 16));
+                Ok(())
+            })
+            .unwrap();
+            state.users.get_mut(&id).unwrap().toggle_bookmark(post_id);
+
+            assert_eq!(
+                export_user(state, pr(2), 0).err(),
+                Some("no user found".into())
+            );
+
+            let first = export_user(state, pr(0), 0).unwrap();
+            assert_eq!(first.schema_version, SCHEMA_VERSION);
+            let profile = first.profile.unwrap();
+            assert_eq!(profile.id, id);
+            assert_eq!(profile.bookmarks, vec![post_id]);
+            assert_eq!(first.posts.len(), 2);
+            assert!(first.posts.iter().all(|post| post.body.starts_with("Post")));
+            assert!(!first.has_more);
+            let exported = first.posts.iter().find(|post| post.id == post_id).unwrap();
+            assert_eq!(exported.blobs.len(), 1);
+            assert_eq!(exported.blobs[0].id, "blob");
+            assert_eq!(exported.blobs[0].bucket, "bucket");
+
+            // the second user has more posts than fit on one page
+            let first = export_user(state, pr(1), 0).unwrap();
+            assert_eq!(first.posts.len(), page_size);
+            assert!(first.has_more);
+            let second = export_user(state, pr(1), 1).unwrap();
+            assert!(second.profile.is_none());
+            assert_eq!(second.posts.len(), 1);
+            assert!(!second.has_more);
+        })
+    }
+}
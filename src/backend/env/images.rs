@@ -0,0 +1,252 @@
+//! Processing of uploaded images.
+//!
+//! Uploaded blobs have to be JPEG, PNG, GIF or WebP images or MP4 or WebM videos; the format is
+//! detected by the magic bytes. Videos are stored unchanged. Images larger than
+//! `CONFIG.max_decoded_image_dimension` pixels on any side or needing more than
+//! `CONFIG.max_image_decoding_bytes` for decoding are rejected before they get decoded.
+//!
+//! Before a JPEG, PNG or WebP image is stored, it gets decoded and encoded again, which strips
+//! all metadata like EXIF and GPS tags; the EXIF orientation is applied to the pixels first.
+//! Images exceeding `CONFIG.max_image_dimension` pixels on any side are downscaled. Images with
+//! transparency are stored as PNG, all others as JPEG.
+//!
+//! GIFs are the exception: they are stored byte-for-byte to keep animations, so their comment
+//! and application extension blocks are kept as well. GIFs exceeding the maximal dimensions are
+//! rejected.
+//!
+//! Every stored image gets a thumbnail fitting into `CONFIG.thumbnail_size` pixels, which is
+//! created from the decoded image and stored as a separate post file with the id of the image
+//! followed by `THUMBNAIL_SUFFIX`.
+
+use super::config::CONFIG;
+use image::{
+    imageops::FilterType,
+    io::{Limits, Reader},
+    DynamicImage, ImageFormat, ImageOutputFormat,
+};
+use serde_bytes::ByteBuf;
+use std::io::Cursor;
+
+pub const THUMBNAIL_SUFFIX: &str = ".thumb";
+
+const JPEG_QUALITY: u8 = 85;
+
+/// Returns the format of a supported image.
+pub fn format(bytes: &[u8]) -> Result<ImageFormat, String> {
+    match image::guess_format(bytes) {
+        Ok(
+            format @ (ImageFormat::Jpeg | ImageFormat::Png | ImageFormat::Gif | ImageFormat::WebP),
+        ) => Ok(format),
+        _ => Err("unsupported file type".into()),
+    }
+}
+
+/// Returns true if the blob is an MP4 or WebM video.
+pub fn is_video(bytes: &[u8]) -> bool {
+    bytes.get(4..8) == Some(b"ftyp") || bytes.starts_with(&[0x1a, 0x45, 0xdf, 0xa3])
+}
+
+/// Checks that the blob is a supported image or video.
+pub fn check_type(bytes: &[u8]) -> Result<(), String> {
+    if is_video(bytes) {
+        return Ok(());
+    }
+    format(bytes).map(|_| ())
+}
+
+fn decode(bytes: &[u8]) -> Result<(ImageFormat, DynamicImage), String> {
+    let format = format(bytes)?;
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(CONFIG.max_decoded_image_dimension);
+    limits.max_image_height = Some(CONFIG.max_decoded_image_dimension);
+    limits.max_alloc = Some(CONFIG.max_image_decoding_bytes);
+    let mut reader = Reader::with_format(Cursor::new(bytes), format);
+    reader.limits(limits);
+    let image = reader
+        .decode()
+        .map_err(|err| format!("couldn't decode the image: {}", err))?;
+    Ok((format, image))
+}
+
+fn encode(image: DynamicImage) -> Result<ByteBuf, String> {
+    let mut bytes = Vec::new();
+    let result = if image.color().has_alpha() {
+        image.write_to(&mut Cursor::new(&mut bytes), ImageOutputFormat::Png)
+    } else {
+        DynamicImage::ImageRgb8(image.to_rgb8()).write_to(
+            &mut Cursor::new(&mut bytes),
+            ImageOutputFormat::Jpeg(JPEG_QUALITY),
+        )
+    };
+    result.map_err(|err| format!("couldn't encode the image: {}", err))?;
+    Ok(ByteBuf::from(bytes))
+}
+
+/// Validates the image, strips its metadata and downscales it if needed. Returns the processed
+/// image and its thumbnail.
+pub fn process(bytes: &[u8]) -> Result<(ByteBuf, ByteBuf), String> {
+    let (format, image) = decode(bytes)?;
+    let max = CONFIG.max_image_dimension;
+    let oversized = image.width() > max || image.height() > max;
+    let thumbnail = |image: &DynamicImage| {
+        encode(image.thumbnail(CONFIG.thumbnail_size, CONFIG.thumbnail_size))
+    };
+    if format == ImageFormat::Gif {
+        if oversized {
+            return Err("image too large".into());
+        }
+        return Ok((ByteBuf::from(bytes.to_vec()), thumbnail(&image)?));
+    }
+    let image = match (format, exif_orientation(bytes)) {
+        (ImageFormat::Jpeg, Some(orientation)) => orient(image, orientation),
+        _ => image,
+    };
+    let image = if oversized {
+        image.resize(max, max, FilterType::Triangle)
+    } else {
+        image
+    };
+    let thumbnail = thumbnail(&image)?;
+    Ok((encode(image)?, thumbnail))
+}
+
+fn orient(image: DynamicImage, orientation: u16) -> DynamicImage {
+    match orientation {
+        2 => image.fliph(),
+        3 => image.rotate180(),
+        4 => image.flipv(),
+        5 => image.rotate90().fliph(),
+        6 => image.rotate90(),
+        7 => image.rotate270().fliph(),
+        8 => image.rotate270(),
+        _ => image,
+    }
+}
+
+// Reads the orientation tag from the EXIF segment of a JPEG image.
+fn exif_orientation(bytes: &[u8]) -> Option<u16> {
+    // find the APP1 segment with EXIF data among the segments preceding the image data
+    let mut pos = 2;
+    let exif = loop {
+        let marker = *bytes.get(pos + 1)?;
+        let len = u16::from_be_bytes([*bytes.get(pos + 2)?, *bytes.get(pos + 3)?]) as usize;
+        let segment = bytes.get(pos + 4..pos + 2 + len)?;
+        if marker == 0xe1 && segment.starts_with(b"Exif\0\0") {
+            break &segment[6..];
+        }
+        if marker == 0xda {
+            return None;
+        }
+        pos += 2 + len;
+    };
+    let big_endian = match exif.get(..2)? {
+        b"MM" => true,
+        b"II" => false,
+        _ => return None,
+    };
+    let u16_at = |pos: usize| {
+        let bytes = [*exif.get(pos)?, *exif.get(pos + 1)?];
+        Some(if big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        })
+    };
+    let u32_at = |pos: usize| {
+        let (high, low) = (u16_at(pos)? as u32, u16_at(pos + 2)? as u32);
+        Some(if big_endian {
+            high << 16 | low
+        } else {
+            low << 16 | high
+        })
+    };
+    let ifd = u32_at(4)? as usize;
+    (0..u16_at(ifd)? as usize)
+        .map(|i| ifd + 2 + i * 12)
+        .find(|entry| u16_at(*entry) == Some(0x0112))
+        .and_then(|entry| u16_at(entry + 8))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageBuffer, Rgb, Rgba};
+
+    fn jpeg(width: u32, height: u32) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        DynamicImage::ImageRgb8(ImageBuffer::from_pixel(width, height, Rgb([200, 10, 10])))
+            .write_to(&mut Cursor::new(&mut bytes), ImageOutputFormat::Jpeg(90))
+            .unwrap();
+        bytes
+    }
+
+    // Inserts an EXIF segment with the given orientation after the start marker.
+    fn with_exif(jpeg: Vec<u8>, orientation: u16) -> Vec<u8> {
+        let mut exif = b"Exif\0\0MM\0\x2a\0\0\0\x08\0\x01\x01\x12\0\x03\0\0\0\x01".to_vec();
+        exif.extend_from_slice(&orientation.to_be_bytes());
+        exif.extend_from_slice(&[0; 6]);
+        exif.extend_from_slice(b"GPS data");
+        let mut bytes = jpeg[..2].to_vec();
+        bytes.extend_from_slice(&[0xff, 0xe1]);
+        bytes.extend_from_slice(&(exif.len() as u16 + 2).to_be_bytes());
+        bytes.extend_from_slice(&exif);
+        bytes.extend_from_slice(&jpeg[2..]);
+        bytes
+    }
+
+    fn dimensions(bytes: &[u8]) -> (u32, u32) {
+        let image = image::load_from_memory(bytes).unwrap();
+        (image.width(), image.height())
+    }
+
+    #[test]
+    fn test_image_processing() {
+        // only valid images of supported formats are accepted
+        assert!(process(b"GIF89a but not really").is_err());
+        assert_eq!(
+            process(&[0, 1, 2, 3, 4, 5, 6, 7]),
+            Err("unsupported file type".into())
+        );
+
+        // metadata is stripped and the orientation is applied
+        let image = with_exif(jpeg(20, 10), 6);
+        assert_eq!(exif_orientation(&image), Some(6));
+        let (processed, _) = process(&image).unwrap();
+        assert_eq!(format(&processed), Ok(ImageFormat::Jpeg));
+        assert_eq!(exif_orientation(&processed), None);
+        assert!(!processed.windows(8).any(|window| window == b"GPS data"));
+        assert_eq!(dimensions(&processed), (10, 20));
+
+        // large images are downscaled
+        let max = CONFIG.max_image_dimension;
+        assert_eq!(
+            dimensions(&process(&jpeg(max * 2, max)).unwrap().0),
+            (max, max / 2)
+        );
+
+        // images above the decoding limits are rejected before being decoded
+        let result = process(&jpeg(CONFIG.max_decoded_image_dimension + 1, 1));
+        assert!(matches!(result, Err(err) if err.starts_with("couldn't decode the image")));
+
+        // transparent images are stored as PNG
+        let mut png = Vec::new();
+        DynamicImage::ImageRgba8(ImageBuffer::from_pixel(800, 400, Rgba([0, 0, 0, 0])))
+            .write_to(&mut Cursor::new(&mut png), ImageOutputFormat::Png)
+            .unwrap();
+        let (processed, thumbnail) = process(&png).unwrap();
+        assert_eq!(format(&processed), Ok(ImageFormat::Png));
+
+        // thumbnails fit into the thumbnail size
+        let size = CONFIG.thumbnail_size;
+        assert_eq!(dimensions(&thumbnail), (size, size / 2));
+
+        // videos are accepted without processing
+        let mp4 = b"\0\0\0\x18ftypmp42";
+        assert!(is_video(mp4));
+        assert_eq!(check_type(mp4), Ok(()));
+        assert_eq!(
+            check_type(&[0, 1, 2, 3]),
+            Err("unsupported file type".into())
+        );
+    }
+}
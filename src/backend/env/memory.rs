@@ -1,8 +1,18 @@
 use ic_cdk::api::stable::{stable64_grow, stable64_read, stable64_size, stable64_write};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
-use std::{cell::RefCell, collections::BTreeMap, fmt::Display, rc::Rc};
+use sha2::{Digest, Sha256};
+use std::{
+    borrow::Cow,
+    cell::RefCell,
+    collections::{BTreeMap, BTreeSet},
+    convert::TryFrom,
+    fmt::Display,
+    rc::Rc,
+};
 
 use super::post::PostId;
+use super::proposals::Proposal;
+use super::State;
 
 #[derive(Serialize, Deserialize)]
 pub struct Api {
@@ -34,17 +44,88 @@ impl Default for Api {
     }
 }
 
-#[derive(Default, Serialize, Deserialize)]
+thread_local! {
+    // The API shared by all object managers, so that they all use the same allocator.
+    static API: Rc<RefCell<Api>> = Default::default();
+}
+
+fn api() -> Rc<RefCell<Api>> {
+    API.with(Rc::clone)
+}
+
+#[derive(Serialize, Deserialize)]
 pub struct Memory {
     api: Api,
     pub posts: ObjectManager<PostId>,
-    #[serde(skip)]
+    #[serde(skip, default = "api")]
     api_ref: Rc<RefCell<Api>>,
+    // The manifest of the last snapshot, used to skip unchanged chunks in the next one.
+    #[serde(skip)]
+    snapshot: Snapshot,
+    // Posts in the heap modified since the last snapshot.
+    #[serde(skip)]
+    dirty_posts: BTreeSet<PostId>,
+}
+
+impl Default for Memory {
+    fn default() -> Self {
+        Self {
+            api: Default::default(),
+            posts: Default::default(),
+            api_ref: api(),
+            snapshot: Default::default(),
+            dirty_posts: Default::default(),
+        }
+    }
 }
 
 // We leave the first 16 bytes recerved for the heap coordinates (offset + length)
 const INITIAL_OFFSET: u64 = 16;
 
+// Prefix of the snapshot manifest distinguishing it from the legacy heap dump, which is a CBOR
+// encoded map.
+const SNAPSHOT_MAGIC: &[u8] = b"SNAPSHOT";
+
+const SNAPSHOT_VERSION: u32 = 1;
+
+// The number of entries serialized into one chunk of a section.
+const CHUNK_SIZE: u64 = 1000;
+
+// Sections of the state serialized separately, with the versions of their formats.
+const USERS: (&str, u32) = ("users", 1);
+const POSTS: (&str, u32) = ("posts", 1);
+const LEDGER: (&str, u32) = ("ledger", 1);
+const PROPOSALS: (&str, u32) = ("proposals", 1);
+const REALMS: (&str, u32) = ("realms", 1);
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+struct Chunk {
+    offset: u64,
+    len: u64,
+    hash: String,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+struct Section {
+    version: u32,
+    chunks: BTreeMap<u64, Chunk>,
+}
+
+// The state snapshot consists of sections written in chunks into allocated segments of the
+// stable memory and of the remaining core state written behind the allocator boundary, followed
+// by this manifest. Only chunks containing objects modified since the last snapshot get
+// serialized and rewritten. This keeps the `pre_upgrade` hook cheap, because most of the state is
+// usually already persisted by the hourly snapshot.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+struct Snapshot {
+    version: u32,
+    // The schema version of the state, see the `migrations` module.
+    #[serde(default)]
+    schema_version: u32,
+    core: (u64, u64),
+    sections: BTreeMap<String, Section>,
+}
+
 impl Api {
     pub fn write<T: Serialize>(&mut self, value: &T) -> Result<(u64, u64), String> {
         let buffer: Vec<u8> = serde_cbor::to_vec(value).expect("couldn't serialize");
@@ -58,18 +139,131 @@ impl Api {
     }
 
     pub fn read<T: DeserializeOwned>(&self, offset: u64, len: u64) -> T {
+        serde_cbor::from_slice(&self.read_raw(offset, len)).expect("couldn't deserialize")
+    }
+
+    fn read_raw(&self, offset: u64, len: u64) -> Vec<u8> {
         let mut bytes = Vec::with_capacity(len as usize);
         bytes.spare_capacity_mut();
         unsafe {
             bytes.set_len(len as usize);
         }
         (self.read_bytes.as_ref().expect("no reader"))(offset, &mut bytes);
-        serde_cbor::from_slice(&bytes).expect("couldn't deserialize")
+        bytes
+    }
+
+    // Writes bytes to unallocated memory, growing it if needed.
+    fn write_raw(&mut self, offset: u64, bytes: &[u8]) -> Result<(), String> {
+        let end = offset + bytes.len() as u64;
+        let mem_size = (self.allocator.mem_size.as_ref().expect("no mem_size"))();
+        if end > mem_size {
+            (self.allocator.mem_grow.as_mut().expect("no mem_grow"))(end - mem_size)?;
+        }
+        (self.write_bytes.as_ref().expect("no writer"))(offset, bytes);
+        Ok(())
+    }
+
+    // Writes the chunks of a section into the given sections. Only the `dirty` chunks get
+    // serialized and rewritten; all other chunks of the previous version of the section are
+    // reused. If `dirty` is `None` or the section changed its version, all chunks listed by
+    // `indexes` get written. `items` returns the items of a chunk; dirty chunks without items
+    // are dropped.
+    fn write_section<T: Serialize>(
+        &mut self,
+        (name, version): (&str, u32),
+        sections: &mut BTreeMap<String, Section>,
+        dirty: Option<BTreeSet<u64>>,
+        indexes: impl Iterator<Item = u64>,
+        items: impl Fn(u64) -> Vec<T>,
+    ) -> Result<(), String> {
+        let mut section = match sections.remove(name) {
+            Some(section) if section.version == version => section,
+            previous => {
+                for chunk in previous
+                    .map(|section| section.chunks)
+                    .unwrap_or_default()
+                    .into_values()
+                {
+                    self.remove(chunk.offset, chunk.len)?;
+                }
+                Section {
+                    version,
+                    ..Default::default()
+                }
+            }
+        };
+        let dirty = match dirty {
+            Some(dirty) if section.version == version && !section.chunks.is_empty() => dirty,
+            _ => {
+                // all chunks get rewritten, including the ones which don't exist anymore
+                let mut all = indexes.collect::<BTreeSet<_>>();
+                all.extend(section.chunks.keys());
+                all
+            }
+        };
+        for index in dirty {
+            if let Some(chunk) = section.chunks.remove(&index) {
+                self.remove(chunk.offset, chunk.len)?;
+            }
+            let items = items(index);
+            if items.is_empty() {
+                continue;
+            }
+            let bytes = serde_cbor::to_vec(&items).expect("couldn't serialize");
+            let mut hasher = Sha256::new();
+            hasher.update(&bytes);
+            let offset = self.allocator.alloc(bytes.len() as u64)?;
+            (self.write_bytes.as_ref().expect("no writer"))(offset, &bytes);
+            section.chunks.insert(
+                index,
+                Chunk {
+                    offset,
+                    len: bytes.len() as u64,
+                    hash: format!("{:x}", hasher.finalize()),
+                },
+            );
+        }
+        sections.insert(name.to_string(), section);
+        Ok(())
+    }
+
+    fn read_section<T: DeserializeOwned>(
+        &self,
+        (name, version): (&str, u32),
+        snapshot: &Snapshot,
+    ) -> Vec<T> {
+        let section = snapshot
+            .sections
+            .get(name)
+            .unwrap_or_else(|| panic!("no section {} found", name));
+        if section.version != version {
+            panic!(
+                "unsupported version {} of section {}",
+                section.version, name
+            );
+        }
+        section
+            .chunks
+            .values()
+            .flat_map(|chunk| self.read::<Vec<T>>(chunk.offset, chunk.len))
+            .collect()
     }
 
     pub fn boundary(&self) -> u64 {
         self.allocator.boundary
     }
+
+    // Moves the object into a free segment below its current offset, if such a segment exists.
+    fn relocate(&mut self, offset: u64, len: u64) -> Result<Option<u64>, String> {
+        let new_offset = match self.allocator.alloc_below(len, offset) {
+            Some(new_offset) => new_offset,
+            None => return Ok(None),
+        };
+        let bytes = self.read_raw(offset, len);
+        (self.write_bytes.as_ref().expect("no writer"))(new_offset, &bytes);
+        self.remove(offset, len)?;
+        Ok(Some(new_offset))
+    }
 }
 
 impl Memory {
@@ -77,13 +271,59 @@ impl Memory {
         self.api_ref.as_ref().borrow().allocator.health(unit)
     }
 
+    /// Returns the share of free space in the allocated memory.
+    pub fn fragmentation(&self) -> f64 {
+        self.api_ref.as_ref().borrow().allocator.fragmentation()
+    }
+
+    pub fn schema_version(&self) -> u32 {
+        self.snapshot.schema_version
+    }
+
+    pub fn set_schema_version(&mut self, version: u32) {
+        self.snapshot.schema_version = version
+    }
+
+    /// Marks the post in the heap as modified, so that the next snapshot rewrites its chunk.
+    pub fn mark_dirty_post(&mut self, id: PostId) {
+        self.dirty_posts.insert(id);
+    }
+
+    /// Returns the coordinates of the snapshot manifest stored in the header.
+    pub fn heap_address(&self) -> (u64, u64) {
+        let bytes = self.api_ref.borrow().read_raw(0, 16);
+        let mut offset = [0; 8];
+        let mut len = [0; 8];
+        offset.copy_from_slice(&bytes[..8]);
+        len.copy_from_slice(&bytes[8..]);
+        (u64::from_be_bytes(offset), u64::from_be_bytes(len))
+    }
+
+    /// Prevents any writes to the memory below `end` until the memory is thawed: objects get
+    /// allocated above `end` and segments freed below it are only released after thawing.
+    pub fn freeze(&mut self, end: u64) {
+        self.api_ref.borrow_mut().allocator.freeze(end)
+    }
+
+    pub fn thaw(&mut self) -> Result<(), String> {
+        self.api_ref.borrow_mut().allocator.thaw()
+    }
+
+    pub fn read_bytes(&self, offset: u64, len: u64) -> Vec<u8> {
+        self.api_ref.borrow().read_raw(offset, len)
+    }
+
+    /// Writes the bytes to the memory bypassing the allocator; only used to import backups.
+    pub fn write_bytes(&mut self, offset: u64, bytes: &[u8]) -> Result<(), String> {
+        self.api_ref.borrow_mut().write_raw(offset, bytes)
+    }
+
     fn pack(&mut self) {
         self.api = (*self.api_ref.as_ref().borrow()).clone();
     }
 
     fn unpack(&mut self) {
-        self.api_ref = Rc::new(RefCell::new(self.api.clone()));
-        self.posts.api = Rc::clone(&self.api_ref);
+        *self.api_ref.borrow_mut() = self.api.clone();
     }
 
     #[allow(clippy::type_complexity)]
@@ -100,28 +340,155 @@ impl Memory {
             mem_grow: Some(mem_grow),
             mem_size: Some(mem_end),
             boundary: 16,
+            frozen: None,
         };
         let test_api = Api {
             allocator,
             write_bytes: Some(write_bytes),
             read_bytes: Some(read_bytes),
         };
-        self.api_ref = Rc::new(RefCell::new(test_api));
-        self.posts.api = Rc::clone(&self.api_ref);
+        *self.api_ref.borrow_mut() = test_api;
     }
 }
 
-pub fn heap_to_stable(state: &mut super::State) {
+/// Moves up to `max_moves` objects of all object managers into free segments closer to the
+/// beginning of the memory and releases the free space at the end. At most `max_scanned` index
+/// entries are inspected; the following run continues where this one stopped. Returns the
+/// number of moved objects.
+pub fn compact(state: &mut State, max_moves: usize, max_scanned: usize) -> Result<usize, String> {
+    let (mut moves, mut scanned) = state.memory.posts.compact(max_moves, max_scanned)?;
+    let (m, s) = state
+        .users
+        .cold
+        .compact(max_moves - moves, max_scanned - scanned)?;
+    moves += m;
+    scanned += s;
+    let (m, s) = state
+        .proposals
+        .cold
+        .compact(max_moves - moves, max_scanned - scanned)?;
+    moves += m;
+    scanned += s;
+    let (m, _) = state
+        .ledger
+        .cold
+        .compact(max_moves - moves, max_scanned - scanned)?;
+    moves += m;
+    state.memory.api_ref.borrow_mut().allocator.trim();
+    Ok(moves)
+}
+
+pub fn heap_to_stable(state: &mut State) {
+    // A new snapshot overwrites the frozen memory, so a running backup gets aborted.
+    if state.memory.api_ref.borrow().allocator.frozen.is_some() {
+        state.memory.thaw().expect("couldn't thaw the memory");
+        state.backup = None;
+    }
+    // Only the objects in the heap are written; archived objects are already in the stable memory.
+    let users = std::mem::take(&mut state.users.hot);
+    let posts = std::mem::take(&mut state.posts);
+    let ledger = std::mem::take(&mut state.ledger.hot);
+    let proposals = std::mem::take(&mut state.proposals.hot);
+    let realms = std::mem::take(&mut state.realms);
+
+    let mut sections = std::mem::take(&mut state.memory.snapshot.sections);
+    let chunks = |ids: BTreeSet<u64>| ids.into_iter().map(|id| id / CHUNK_SIZE).collect();
+    let dirty_users = state.users.take_dirty().map(chunks);
+    let dirty_posts = Some(chunks(std::mem::take(&mut state.memory.dirty_posts)));
+    let dirty_ledger = state.ledger.take_dirty().map(chunks);
+    let dirty_proposals = state
+        .proposals
+        .take_dirty()
+        .map(|ids| ids.into_iter().map(|id| id as u64 / CHUNK_SIZE).collect());
+    let range = |index: u64| index * CHUNK_SIZE..(index + 1) * CHUNK_SIZE;
+    let api_ref = Rc::clone(&state.memory.api_ref);
+    let mut api = api_ref.borrow_mut();
+    api.write_section(
+        USERS,
+        &mut sections,
+        dirty_users,
+        users.keys().map(|id| id / CHUNK_SIZE),
+        |index| users.range(range(index)).collect(),
+    )
+    .expect("couldn't write users");
+    api.write_section(
+        POSTS,
+        &mut sections,
+        dirty_posts,
+        posts.keys().map(|id| id / CHUNK_SIZE),
+        |index| posts.range(range(index)).collect(),
+    )
+    .expect("couldn't write posts");
+    api.write_section(
+        LEDGER,
+        &mut sections,
+        dirty_ledger,
+        ledger.keys().map(|id| id / CHUNK_SIZE),
+        |index| ledger.range(range(index)).map(|(_, tx)| tx).collect(),
+    )
+    .expect("couldn't write the ledger");
+    api.write_section(
+        PROPOSALS,
+        &mut sections,
+        dirty_proposals,
+        proposals.keys().map(|id| *id as u64 / CHUNK_SIZE),
+        |index| {
+            let range = range(index);
+            proposals
+                .range(range.start as u32..range.end as u32)
+                .map(|(_, proposal)| proposal)
+                .collect()
+        },
+    )
+    .expect("couldn't write proposals");
+    // Realms are few, so they are always rewritten.
+    let realms_list = realms.iter().collect::<Vec<_>>();
+    api.write_section(
+        REALMS,
+        &mut sections,
+        None,
+        (0..realms_list.len() as u64).map(|i| i / CHUNK_SIZE),
+        |index| {
+            realms_list
+                .iter()
+                .skip((index * CHUNK_SIZE) as usize)
+                .take(CHUNK_SIZE as usize)
+                .collect()
+        },
+    )
+    .expect("couldn't write realms");
+    drop(api);
+
+    // The core is serialized after all sections were written, so that it contains the final
+    // state of the allocator.
     state.memory.pack();
-    let offset = state.memory.api.boundary();
-    let bytes = serde_cbor::to_vec(&state).expect("couldn't serialize the state");
-    let len = bytes.len() as u64;
-    if offset + len > (stable64_size() << 16) {
-        stable64_grow((len >> 16) + 1).expect("couldn't grow memory");
-    }
-    stable64_write(offset, &bytes);
-    stable64_write(0, &offset.to_be_bytes());
-    stable64_write(8, &len.to_be_bytes());
+    let core = serde_cbor::to_vec(&state).expect("couldn't serialize the state");
+    state.users.hot = users;
+    state.posts = posts;
+    state.ledger.hot = ledger;
+    state.proposals.hot = proposals;
+    state.realms = realms;
+
+    let mut api = api_ref.borrow_mut();
+    let offset = api.boundary();
+    api.write_raw(offset, &core)
+        .expect("couldn't write the core state");
+    let snapshot = Snapshot {
+        version: SNAPSHOT_VERSION,
+        schema_version: state.memory.snapshot.schema_version,
+        core: (offset, core.len() as u64),
+        sections,
+    };
+    let mut manifest = SNAPSHOT_MAGIC.to_vec();
+    manifest.extend(serde_cbor::to_vec(&snapshot).expect("couldn't serialize the manifest"));
+    let manifest_offset = offset + core.len() as u64;
+    api.write_raw(manifest_offset, &manifest)
+        .expect("couldn't write the manifest");
+    api.write_raw(0, &manifest_offset.to_be_bytes())
+        .expect("couldn't write the heap offset");
+    api.write_raw(8, &(manifest.len() as u64).to_be_bytes())
+        .expect("couldn't write the heap length");
+    state.memory.snapshot = snapshot;
 }
 
 pub fn heap_address() -> (u64, u64) {
@@ -134,20 +501,65 @@ pub fn heap_address() -> (u64, u64) {
     (offset, len)
 }
 
-pub fn stable_to_heap() -> super::State {
+pub fn stable_to_heap() -> State {
     let (offset, len) = heap_address();
     ic_cdk::println!("Reading heap from coordinates: {:?}", (offset, len),);
-    let api = Api::default();
-    let mut state: super::State = api.read(offset, len);
+    let mut state = restore(&Api::default(), offset, len);
     state.memory.unpack();
     state
 }
 
+// Restores the state from a snapshot or from a legacy heap dump.
+fn restore(api: &Api, offset: u64, len: u64) -> State {
+    let bytes = api.read_raw(offset, len);
+    if !bytes.starts_with(SNAPSHOT_MAGIC) {
+        return serde_cbor::from_slice(&bytes).expect("couldn't deserialize the state");
+    }
+    let snapshot: Snapshot = serde_cbor::from_slice(&bytes[SNAPSHOT_MAGIC.len()..])
+        .expect("couldn't deserialize the manifest");
+    if snapshot.version != SNAPSHOT_VERSION {
+        panic!("unsupported snapshot version {}", snapshot.version);
+    }
+    let (core_offset, core_len) = snapshot.core;
+    let mut state: State = api.read(core_offset, core_len);
+    state.users.hot = api.read_section(USERS, &snapshot).into_iter().collect();
+    state.posts = api.read_section(POSTS, &snapshot).into_iter().collect();
+    // Transactions are archived from the oldest ones, so the ids of the transactions in the heap
+    // continue the ids of the archived ones.
+    let archived_transactions = state.ledger.cold.len() as u64;
+    state.ledger.hot = api
+        .read_section(LEDGER, &snapshot)
+        .into_iter()
+        .enumerate()
+        .map(|(i, tx)| (archived_transactions + i as u64, tx))
+        .collect();
+    state.proposals.hot = api
+        .read_section::<Proposal>(PROPOSALS, &snapshot)
+        .into_iter()
+        .map(|proposal| (proposal.id, proposal))
+        .collect();
+    state.realms = api.read_section(REALMS, &snapshot).into_iter().collect();
+    state.memory.snapshot = snapshot;
+    state
+}
+
+// The frozen part of the memory, see `Memory::freeze`.
+#[derive(Default)]
+struct Frozen {
+    end: u64,
+    // The boundary before freezing.
+    boundary: u64,
+    // Segments freed below `end`.
+    segments: Vec<(u64, u64)>,
+}
+
 #[derive(Serialize, Deserialize)]
 struct Allocator {
     segments: BTreeMap<u64, u64>,
     boundary: u64,
     #[serde(skip)]
+    frozen: Option<Frozen>,
+    #[serde(skip)]
     #[allow(clippy::type_complexity)]
     mem_grow: Option<Box<dyn FnMut(u64) -> Result<u64, String>>>,
     #[serde(skip)]
@@ -169,6 +581,7 @@ impl Default for Allocator {
         Self {
             segments: Default::default(),
             boundary: INITIAL_OFFSET,
+            frozen: None,
             mem_size: Some(Box::new(|| stable64_size() << 16)),
             mem_grow: Some(Box::new(|n| {
                 stable64_grow((n >> 16) + 1)
@@ -182,7 +595,12 @@ impl Allocator {
     fn alloc(&mut self, n: u64) -> Result<u64, String> {
         // find all segments that are big enough
         let mut candidates = BTreeMap::new();
-        for (start, size) in self.segments.iter() {
+        let frozen_end = self
+            .frozen
+            .as_ref()
+            .map(|frozen| frozen.end)
+            .unwrap_or_default();
+        for (start, size) in self.segments.range(frozen_end..) {
             if size >= &n {
                 candidates.insert(size, start);
             }
@@ -215,6 +633,12 @@ impl Allocator {
     }
 
     fn free(&mut self, offset: u64, size: u64) -> Result<(), String> {
+        if let Some(frozen) = self.frozen.as_mut() {
+            if offset < frozen.end {
+                frozen.segments.push((offset, size));
+                return Ok(());
+            }
+        }
         let left_segment = self.segments.range(..offset).last().map(|(a, b)| (*a, *b));
         let right_segment = self
             .segments
@@ -265,6 +689,65 @@ impl Allocator {
         Ok(())
     }
 
+    // Allocates `n` bytes in the smallest free segment fitting below `limit`.
+    fn alloc_below(&mut self, n: u64, limit: u64) -> Option<u64> {
+        if self.frozen.is_some() {
+            return None;
+        }
+        let (start, size) = self
+            .segments
+            .range(..limit)
+            .filter(|(_, size)| **size >= n)
+            .min_by_key(|(_, size)| **size)
+            .map(|(start, size)| (*start, *size))?;
+        self.segments.remove(&start);
+        if n < size {
+            self.segments.insert(start + n, size - n);
+        }
+        Some(start)
+    }
+
+    fn freeze(&mut self, end: u64) {
+        self.frozen = Some(Frozen {
+            end,
+            boundary: self.boundary,
+            segments: Default::default(),
+        });
+        self.boundary = self.boundary.max(end);
+    }
+
+    // Releases all segments freed since freezing and the space between the previous boundary and
+    // the end of the frozen memory.
+    fn thaw(&mut self) -> Result<(), String> {
+        let frozen = self.frozen.take().ok_or("memory is not frozen")?;
+        if frozen.end > frozen.boundary {
+            self.free(frozen.boundary, frozen.end - frozen.boundary)?;
+        }
+        for (offset, size) in frozen.segments {
+            self.free(offset, size)?;
+        }
+        self.trim();
+        Ok(())
+    }
+
+    // Removes the free segment at the end of the allocated memory and shrinks the boundary.
+    fn trim(&mut self) {
+        if let Some((start, size)) = self.segments.last_key_value().map(|(a, b)| (*a, *b)) {
+            if start + size == self.boundary {
+                self.segments.remove(&start);
+                self.boundary = start;
+            }
+        }
+    }
+
+    fn fragmentation(&self) -> f64 {
+        let allocated = self.boundary - INITIAL_OFFSET;
+        if allocated == 0 {
+            return 0.0;
+        }
+        self.segments.values().sum::<u64>() as f64 / allocated as f64
+    }
+
     fn health(&self, unit: &str) -> String {
         let divisor = match unit {
             "KB" => 1024,
@@ -291,11 +774,24 @@ impl Allocator {
     }
 }
 
-#[derive(Default, Serialize, Deserialize)]
+#[derive(Serialize, Deserialize)]
 pub struct ObjectManager<K: Ord + Eq> {
     index: BTreeMap<K, (u64, u64)>,
-    #[serde(skip)]
+    #[serde(skip, default = "api")]
     api: Rc<RefCell<Api>>,
+    // The last index entry inspected by the compaction.
+    #[serde(default = "Option::default")]
+    compaction_cursor: Option<K>,
+}
+
+impl<K: Ord + Eq> Default for ObjectManager<K> {
+    fn default() -> Self {
+        Self {
+            index: Default::default(),
+            api: api(),
+            compaction_cursor: None,
+        }
+    }
 }
 
 impl<K: Eq + Ord + Clone + Display> ObjectManager<K> {
@@ -320,11 +816,499 @@ impl<K: Eq + Ord + Clone + Display> ObjectManager<K> {
         self.api.borrow_mut().remove(offset, len)?;
         Ok(value)
     }
+
+    // Inspects up to `max_scanned` index entries following the compaction cursor and relocates
+    // up to `max_moves` of them, starting with the highest offsets, into free segments below
+    // them. Returns the numbers of moved and inspected objects.
+    fn compact(&mut self, max_moves: usize, max_scanned: usize) -> Result<(usize, usize), String> {
+        use std::ops::Bound::{Excluded, Unbounded};
+        // without a budget the cursor stays where the last run stopped
+        if max_moves == 0 || max_scanned == 0 {
+            return Ok((0, 0));
+        }
+        let start = match self.compaction_cursor.take() {
+            Some(cursor) => Excluded(cursor),
+            None => Unbounded,
+        };
+        let mut entries = self
+            .index
+            .range((start, Unbounded))
+            .take(max_scanned)
+            .map(|(id, (offset, len))| (*offset, *len, id.clone()))
+            .collect::<Vec<_>>();
+        let scanned = entries.len();
+        // once the end of the index is reached, the next run starts from the beginning
+        if scanned == max_scanned {
+            self.compaction_cursor = entries.last().map(|(_, _, id)| id.clone());
+        }
+        entries.sort_unstable_by_key(|(offset, _, _)| std::cmp::Reverse(*offset));
+        let mut api = self.api.borrow_mut();
+        let mut moves = 0;
+        for (offset, len, id) in entries {
+            if moves >= max_moves || api.allocator.segments.range(..offset).next().is_none() {
+                break;
+            }
+            if let Some(new_offset) = api.relocate(offset, len)? {
+                self.index.insert(id, (new_offset, len));
+                moves += 1;
+            }
+        }
+        Ok((moves, scanned))
+    }
+}
+
+/// A map keeping frequently used objects in the heap and archived objects in the stable memory.
+/// Archived objects are loaded transparently: read accesses return them by value, mutable
+/// accesses move them back to the heap. Bulk accesses like `values` or `iter` only visit the
+/// objects in the heap.
+#[derive(Serialize, Deserialize)]
+#[serde(
+    from = "Stored<K, T>",
+    bound(deserialize = "K: Ord + TryFrom<usize> + Deserialize<'de>, T: Deserialize<'de>")
+)]
+pub struct Archive<K: Ord, T> {
+    hot: BTreeMap<K, T>,
+    cold: ObjectManager<K>,
+    // Lookup keys of archived objects, e.g. the names of archived users.
+    labels: BTreeMap<String, K>,
+    // Keys of objects modified in the heap since the last snapshot; `None` if all objects in the
+    // heap could have been modified.
+    #[serde(skip)]
+    dirty: Option<BTreeSet<K>>,
+}
+
+// Archives are stored as structs; the variants for maps and lists are only read for the
+// migration of plain maps and lists to archives.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Stored<K: Ord, T> {
+    Map(BTreeMap<K, T>),
+    List(Vec<T>),
+    Archive {
+        hot: BTreeMap<K, T>,
+        cold: ObjectManager<K>,
+        labels: BTreeMap<String, K>,
+    },
+}
+
+impl<K: Ord + TryFrom<usize>, T> From<Stored<K, T>> for Archive<K, T> {
+    fn from(stored: Stored<K, T>) -> Self {
+        let (hot, cold, labels) = match stored {
+            Stored::Map(hot) => (hot, Default::default(), Default::default()),
+            Stored::List(values) => (
+                values
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, value)| {
+                        (
+                            K::try_from(i).unwrap_or_else(|_| panic!("invalid key {}", i)),
+                            value,
+                        )
+                    })
+                    .collect(),
+                Default::default(),
+                Default::default(),
+            ),
+            Stored::Archive { hot, cold, labels } => (hot, cold, labels),
+        };
+        Self {
+            hot,
+            cold,
+            labels,
+            dirty: Some(Default::default()),
+        }
+    }
+}
+
+impl<K: Ord, T> Default for Archive<K, T> {
+    fn default() -> Self {
+        Self {
+            hot: Default::default(),
+            cold: Default::default(),
+            labels: Default::default(),
+            dirty: Some(Default::default()),
+        }
+    }
+}
+
+impl<K: Eq + Ord + Clone + Display, T: Clone + Serialize + DeserializeOwned> Archive<K, T> {
+    pub fn len(&self) -> usize {
+        self.hot.len() + self.cold.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the number of archived objects.
+    pub fn archived(&self) -> usize {
+        self.cold.len()
+    }
+
+    pub fn contains_key(&self, id: &K) -> bool {
+        self.hot.contains_key(id) || self.cold.index.contains_key(id)
+    }
+
+    /// Returns the object; archived objects are deserialized from the stable memory.
+    pub fn get(&self, id: &K) -> Option<Cow<'_, T>> {
+        if let Some(value) = self.hot.get(id) {
+            return Some(Cow::Borrowed(value));
+        }
+        self.cold.get(id).map(Cow::Owned)
+    }
+
+    /// Returns the archived object with the given label.
+    pub fn find(&self, label: &str) -> Option<Cow<'_, T>> {
+        self.labels.get(label).and_then(|id| self.get(id))
+    }
+
+    /// Returns a mutable reference to the object and moves it to the heap if it was archived.
+    pub fn get_mut(&mut self, id: &K) -> Option<&mut T> {
+        if self.cold.index.contains_key(id) {
+            let value = self
+                .cold
+                .remove(id)
+                .expect("couldn't restore archived object");
+            self.labels.retain(|_, key| key != id);
+            self.hot.insert(id.clone(), value);
+        }
+        self.mark_dirty(id);
+        self.hot.get_mut(id)
+    }
+
+    pub fn insert(&mut self, id: K, value: T) -> Option<T> {
+        let previous = self.remove(&id);
+        self.mark_dirty(&id);
+        self.hot.insert(id, value);
+        previous
+    }
+
+    pub fn remove(&mut self, id: &K) -> Option<T> {
+        self.mark_dirty(id);
+        if self.cold.index.contains_key(id) {
+            self.labels.retain(|_, key| key != id);
+            return self.cold.remove(id).ok();
+        }
+        self.hot.remove(id)
+    }
+
+    /// Moves the object from the heap to the stable memory.
+    pub fn archive<I: IntoIterator<Item = String>>(
+        &mut self,
+        id: &K,
+        labels: I,
+    ) -> Result<(), String> {
+        let value = self.hot.remove(id).ok_or("not found")?;
+        self.mark_dirty(id);
+        if let Err(err) = self.cold.insert(id.clone(), &value) {
+            self.hot.insert(id.clone(), value);
+            return Err(err);
+        }
+        for label in labels {
+            self.labels.insert(label, id.clone());
+        }
+        Ok(())
+    }
+
+    pub fn iter(&self) -> std::collections::btree_map::Iter<'_, K, T> {
+        self.hot.iter()
+    }
+
+    pub fn values(&self) -> std::collections::btree_map::Values<'_, K, T> {
+        self.hot.values()
+    }
+
+    pub fn values_mut(&mut self) -> std::collections::btree_map::ValuesMut<'_, K, T> {
+        self.dirty = None;
+        self.hot.values_mut()
+    }
+
+    fn mark_dirty(&mut self, id: &K) {
+        if let Some(dirty) = self.dirty.as_mut() {
+            dirty.insert(id.clone());
+        }
+    }
+
+    // Returns the keys of objects modified since the last call, or `None` if all objects in the
+    // heap could have been modified.
+    fn take_dirty(&mut self) -> Option<BTreeSet<K>> {
+        self.dirty.replace(Default::default())
+    }
 }
 
 #[cfg(test)]
 pub(crate) mod tests {
     use super::*;
+    use crate::{
+        env::tests::{create_user, pr},
+        env::token::account,
+        STATE,
+    };
+
+    thread_local! {
+        static MEM_END: RefCell<u64> = const { RefCell::new(16) };
+        static MEMORY: RefCell<Vec<u8>> = RefCell::new(vec![0; 1024 * 1024]);
+    }
+
+    pub(crate) fn set_test_api(memory: &mut Memory) {
+        let mem_grow = |n| {
+            MEM_END.with(|end| *end.borrow_mut() += n);
+            Ok(0)
+        };
+        let mem_end = || MEM_END.with(|end| *end.borrow());
+        let writer = |offset: u64, buf: &[u8]| {
+            MEMORY.with(|memory| {
+                let offset = offset as usize;
+                memory.borrow_mut()[offset..offset + buf.len()].copy_from_slice(buf)
+            })
+        };
+        let reader = |offset: u64, buf: &mut [u8]| {
+            MEMORY.with(|memory| {
+                let offset = offset as usize;
+                buf.copy_from_slice(&memory.borrow()[offset..offset + buf.len()])
+            })
+        };
+        memory.set_test_api(
+            Box::new(mem_grow),
+            Box::new(mem_end),
+            Box::new(writer),
+            Box::new(reader),
+        );
+    }
+
+    #[test]
+    fn test_compaction() {
+        STATE.with(|cell| {
+            cell.replace(Default::default());
+            let state = &mut *cell.borrow_mut();
+            set_test_api(&mut state.memory);
+            let value = |i: u8| vec![i; 8];
+            for i in 0..5 {
+                state.memory.posts.insert(i, value(i as u8)).unwrap();
+            }
+            let len = state.memory.posts.index.get(&0).unwrap().1;
+            state.memory.posts.remove::<Vec<u8>>(&1).unwrap();
+            state.memory.posts.remove::<Vec<u8>>(&3).unwrap();
+            // |oooooooooooooooo|0|.|2|.|4|
+            assert_eq!(state.memory.fragmentation(), 2.0 / 5.0);
+
+            // the number of moves is bounded
+            assert_eq!(compact(state, 0, 10), Ok(0));
+            assert_eq!(state.memory.fragmentation(), 2.0 / 5.0);
+
+            // and so is the number of inspected objects: only the first object is inspected,
+            // which can't be moved further down
+            assert_eq!(compact(state, 10, 1), Ok(0));
+            assert_eq!(state.memory.fragmentation(), 2.0 / 5.0);
+
+            // the next run continues after the inspected object
+            assert_eq!(compact(state, 10, 10), Ok(1));
+            // |oooooooooooooooo|0|4|2|
+            let posts = &state.memory.posts;
+            assert_eq!(posts.index.get(&4), Some(&(16 + len, len)));
+            assert_eq!(posts.get::<Vec<u8>>(&4), Some(value(4)));
+            assert_eq!(posts.get::<Vec<u8>>(&2), Some(value(2)));
+            assert_eq!(state.memory.fragmentation(), 0.0);
+            assert_eq!(
+                state.memory.health("B"),
+                format!(
+                    "boundary={}B, mem_size={}B, segments=0",
+                    16 + 3 * len,
+                    16 + 5 * len
+                )
+            );
+        })
+    }
+
+    #[test]
+    fn test_compaction_of_several_managers() {
+        STATE.with(|cell| {
+            cell.replace(Default::default());
+            let state = &mut *cell.borrow_mut();
+            set_test_api(&mut state.memory);
+            for i in 0..3 {
+                state.memory.posts.insert(i, vec![i as u8; 8]).unwrap();
+            }
+            for i in 0..3 {
+                let id = create_user(state, pr(i));
+                state.users.archive(&id, Vec::new()).unwrap();
+            }
+
+            // the posts use a part of the budget, the users the rest
+            compact(state, 10, 4).unwrap();
+            assert_eq!(state.memory.posts.compaction_cursor, None);
+            assert_eq!(state.users.cold.compaction_cursor, Some(0));
+
+            // the posts use the whole budget, so the users continue later where they stopped
+            compact(state, 10, 3).unwrap();
+            assert_eq!(state.memory.posts.compaction_cursor, Some(2));
+            assert_eq!(state.users.cold.compaction_cursor, Some(0));
+            // the posts reach the end of the index, the users continue after the first user and
+            // reach the end too
+            compact(state, 10, 3).unwrap();
+            assert_eq!(state.memory.posts.compaction_cursor, None);
+            assert_eq!(state.users.cold.compaction_cursor, None);
+        })
+    }
+
+    #[test]
+    fn test_snapshots() {
+        STATE.with(|cell| {
+            cell.replace(Default::default());
+            let state = &mut *cell.borrow_mut();
+            set_test_api(&mut state.memory);
+            let id = create_user(state, pr(0));
+            create_user(state, pr(1));
+            super::super::Post::create(state, "Hello".into(), &[], pr(0), 0, None, None, None)
+                .unwrap();
+            crate::token::mint(state, account(pr(0)), 1000);
+            state.realms.insert("REALM".into(), Default::default());
+
+            heap_to_stable(state);
+            let (offset, len) = state.memory.heap_address();
+            let restored = restore(&state.memory.api_ref.borrow(), offset, len);
+            assert_eq!(restored.users.len(), 2);
+            assert_eq!(
+                restored.users.get(&id).unwrap().name,
+                state.users.get(&id).unwrap().name
+            );
+            assert_eq!(restored.posts.len(), 1);
+            assert_eq!(restored.ledger.len(), state.ledger.len());
+            assert!(restored.realms.contains_key("REALM"));
+            assert_eq!(restored.memory.snapshot, state.memory.snapshot);
+            assert_eq!(restored.memory.snapshot.sections.len(), 5);
+
+            // unchanged sections are not rewritten
+            let snapshot = state.memory.snapshot.clone();
+            heap_to_stable(state);
+            assert_eq!(state.memory.snapshot.sections, snapshot.sections);
+
+            // only the chunks of modified sections are rewritten
+            state.users.get_mut(&id).unwrap().about = "changed".into();
+            heap_to_stable(state);
+            let sections = &state.memory.snapshot.sections;
+            assert_ne!(sections.get("users"), snapshot.sections.get("users"));
+            for name in ["posts", "ledger", "proposals", "realms"] {
+                assert_eq!(sections.get(name), snapshot.sections.get(name));
+            }
+            let (offset, len) = state.memory.heap_address();
+            let restored = restore(&state.memory.api_ref.borrow(), offset, len);
+            assert_eq!(restored.users.get(&id).unwrap().about, "changed");
+
+            // modified posts are tracked as well
+            let snapshot = state.memory.snapshot.clone();
+            super::super::Post::mutate(state, &0, |post| {
+                post.body = "Edited".into();
+                Ok(())
+            })
+            .unwrap();
+            heap_to_stable(state);
+            let sections = &state.memory.snapshot.sections;
+            assert_ne!(sections.get("posts"), snapshot.sections.get("posts"));
+            assert_eq!(sections.get("users"), snapshot.sections.get("users"));
+            let (offset, len) = state.memory.heap_address();
+            let restored = restore(&state.memory.api_ref.borrow(), offset, len);
+            assert_eq!(restored.posts.get(&0).unwrap().body, "Edited");
+
+            // legacy heap dumps can still be restored
+            let bytes = serde_cbor::to_vec(&state).unwrap();
+            let offset = state.memory.api_ref.borrow().boundary();
+            state
+                .memory
+                .api_ref
+                .borrow_mut()
+                .write_raw(offset, &bytes)
+                .unwrap();
+            let restored = restore(&state.memory.api_ref.borrow(), offset, bytes.len() as u64);
+            assert_eq!(restored.users.len(), 2);
+            assert_eq!(restored.memory.snapshot, Snapshot::default());
+        })
+    }
+
+    #[test]
+    fn test_archiving() {
+        use super::super::{config::CONFIG, proposals::Status, user::CyclesDelta, WEEK};
+
+        STATE.with(|cell| {
+            cell.replace(Default::default());
+            let state = &mut *cell.borrow_mut();
+            set_test_api(&mut state.memory);
+            for i in 0..3 {
+                create_user(state, pr(i));
+            }
+            let user = state.users.get_mut(&1).unwrap();
+            user.change_karma(-user.karma(), "");
+            user.apply_rewards();
+            user.change_cycles(user.cycles(), CyclesDelta::Minus, "")
+                .unwrap();
+            user.previous_names.push("OldName".into());
+            let name = user.name.clone();
+            let now = (CONFIG.inactivity_duration_weeks + 1) * WEEK;
+
+            // only inactive users without karma and cycles get archived
+            super::super::user::archive_inactive_users(state, 1, now);
+            assert_eq!(state.users.archived(), 1);
+            assert_eq!(state.users.len(), 3);
+            assert_eq!(state.users.values().count(), 2);
+            assert_eq!(state.principal_to_user(pr(1)).unwrap().id, 1);
+            assert_eq!(state.user(&name).unwrap().id, 1);
+            assert_eq!(state.user("oldname").unwrap().id, 1);
+            assert_eq!(state.validate_username(&name), Err("taken".into()));
+
+            // mutable accesses restore the user
+            state.users.get_mut(&1).unwrap().about = "back".into();
+            assert_eq!(state.users.archived(), 0);
+            assert_eq!(state.users.values().count(), 3);
+            super::super::user::archive_inactive_users(state, 1, now);
+            assert_eq!(state.users.archived(), 1);
+
+            // open proposals stay in the heap
+            for (id, status) in [Status::Executed, Status::Open, Status::Rejected]
+                .iter()
+                .cloned()
+                .enumerate()
+            {
+                let id = id as u32;
+                state.proposals.insert(
+                    id,
+                    Proposal {
+                        id,
+                        status,
+                        ..Default::default()
+                    },
+                );
+            }
+            // the number of archived objects per run is bounded by the heap budget
+            super::super::proposals::archive_concluded_proposals(state, 1);
+            assert_eq!(state.proposals.archived(), 1);
+            super::super::proposals::archive_concluded_proposals(state, 1);
+            assert_eq!(state.proposals.archived(), 2);
+            assert_eq!(state.proposals.get(&2).unwrap().status, Status::Rejected);
+            assert_eq!(state.proposals.values().next().unwrap().id, 1);
+
+            for i in 0..3 {
+                crate::token::mint(state, account(pr(0)), 100 * (i + 1));
+            }
+            super::super::token::archive_transactions(state, 1);
+            super::super::token::archive_transactions(state, 1);
+            assert_eq!(state.ledger.archived(), 2);
+            assert_eq!(state.ledger.get(&1).unwrap().amount, 200);
+
+            // archived objects survive upgrades
+            heap_to_stable(state);
+            let (offset, len) = state.memory.heap_address();
+            let restored = restore(&state.memory.api_ref.borrow(), offset, len);
+            assert_eq!(restored.users.len(), 3);
+            assert_eq!(restored.users.get(&1).unwrap().about, "back");
+            assert_eq!(restored.user("oldname").unwrap().id, 1);
+            assert_eq!(restored.proposals.len(), 3);
+            assert_eq!(restored.proposals.get(&0).unwrap().status, Status::Executed);
+            assert_eq!(restored.ledger.len(), 3);
+            for id in 0..3 {
+                assert_eq!(restored.ledger.get(&id).unwrap().amount, 100 * (id + 1));
+            }
+        })
+    }
 
     #[test]
     fn test_allocator() {
@@ -341,6 +1325,7 @@ pub(crate) mod tests {
             mem_grow: Some(Box::new(mem_grow)),
             mem_size: Some(Box::new(mem_end)),
             boundary: 16,
+            frozen: None,
         };
 
         // |oooooooooooooooo|...
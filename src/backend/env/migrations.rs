@@ -0,0 +1,261 @@
+//! Schema migrations of the state.
+//!
+//! The schema version of the state is stored in the snapshot manifest next to the heap
+//! coordinates. Every upgrade runs all registered migrations newer than the stored version in
+//! their order; a migration at index `i` of the registry migrates the state from version `i` to
+//! version `i + 1`. Long migrations run in steps across timer ticks: a step receives the cursor
+//! returned by the previous step and returns the next cursor, or `None` once the migration is
+//! completed. The cursor is persisted in the state, so that a migration interrupted by an
+//! upgrade resumes where it stopped. A failed migration stops all following ones until the next
+//! upgrade.
+
+use super::*;
+
+pub struct Migration {
+    pub description: &'static str,
+    pub step: fn(&mut State, u64) -> Result<Option<u64>, String>,
+}
+
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        description: "rank the hot list",
+        step: rank_hot_list,
+    },
+    Migration {
+        description: "index open post reports",
+        step: index_open_post_reports,
+    },
+    Migration {
+        description: "index user mutes",
+        step: index_user_mutes,
+    },
+    Migration {
+        description: "index transactions by principal",
+        step: index_principal_transactions,
+    },
+];
+
+// The number of items a migration step should process.
+const STEP_SIZE: u64 = 1000;
+
+/// The schema version of the current code.
+pub fn schema_version() -> u32 {
+    MIGRATIONS.len() as u32
+}
+
+/// Runs one step of the next pending migration. Returns true if more steps are pending.
+pub fn step(state: &mut State) -> bool {
+    run_step(state, MIGRATIONS)
+}
+
+fn run_step(state: &mut State, migrations: &[Migration]) -> bool {
+    let version = state.memory.schema_version();
+    let migration = match migrations.get(version as usize) {
+        Some(migration) => migration,
+        None => return false,
+    };
+    let cursor = state.migration_cursor;
+    match (migration.step)(state, cursor) {
+        Ok(Some(next)) => {
+            state.migration_cursor = next;
+            state.logger.info(format!(
+                "Migration to schema version {} (`{}`): processed up to {}.",
+                version + 1,
+                migration.description,
+                next
+            ));
+        }
+        Ok(None) => {
+            state.migration_cursor = 0;
+            state.memory.set_schema_version(version + 1);
+            state.logger.info(format!(
+                "Migration to schema version {} (`{}`) completed.",
+                version + 1,
+                migration.description,
+            ));
+        }
+        Err(err) => {
+            state.logger.error(format!(
+                "Migration to schema version {} (`{}`) failed at {}: {}",
+                version + 1,
+                migration.description,
+                cursor,
+                err
+            ));
+            return false;
+        }
+    }
+    (state.memory.schema_version() as usize) < migrations.len()
+}
+
+// Inserts posts of the hot list used before the introduction of the ranking into the ranking.
+fn rank_hot_list(state: &mut State, cursor: u64) -> Result<Option<u64>, String> {
+    let users_len = state.users.len();
+    let mut processed = 0;
+    while let Some(post_id) = state.hot.pop_front() {
+        if let Some(post) = Post::get(state, &post_id) {
+            let post = post.clone();
+            state.hot_ranking.update(&post, users_len);
+        }
+        processed += 1;
+        if processed == STEP_SIZE {
+            break;
+        }
+    }
+    Ok((!state.hot.is_empty()).then_some(cursor + processed))
+}
+
+// Adds posts with reports opened before the introduction of the report index to the index.
+fn index_open_post_reports(state: &mut State, cursor: u64) -> Result<Option<u64>, String> {
+    let end = state.next_post_id.min(cursor + STEP_SIZE);
+    for id in cursor..end {
+        if Post::get(state, &id)
+            .and_then(|post| post.report.as_ref())
+            .map(|report| !report.closed)
+            .unwrap_or_default()
+        {
+            state.open_post_reports.insert(id);
+        }
+    }
+    Ok((end < state.next_post_id).then_some(end))
+}
+
+// Builds the reverse index of user mutes on the muted users.
+fn index_user_mutes(state: &mut State, cursor: u64) -> Result<Option<u64>, String> {
+    let end = state.next_user_id.min(cursor + STEP_SIZE);
+    for id in cursor..end {
+        let muted = match state.users.get(&id) {
+            Some(user) => user.mutes.users.keys().copied().collect::<Vec<_>>(),
+            None => continue,
+        };
+        for muted_id in muted {
+            if let Some(user) = state.users.get_mut(&muted_id) {
+                user.muted_by.insert(id);
+            }
+        }
+    }
+    Ok((end < state.next_user_id).then_some(end))
+}
+
+// Builds the index of ledger transactions by their senders and recipients.
+fn index_principal_transactions(state: &mut State, cursor: u64) -> Result<Option<u64>, String> {
+    let len = state.ledger.len() as u64;
+    let end = len.min(cursor + STEP_SIZE);
+    for id in cursor..end {
+        let (from, to) = match state.ledger.get(&id) {
+            Some(tx) => (tx.from.clone(), tx.to.clone()),
+            None => continue,
+        };
+        token::index_transaction(state, id, &from, &to);
+    }
+    Ok((end < len).then_some(end))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::STATE;
+
+    fn count(state: &mut State, cursor: u64) -> Result<Option<u64>, String> {
+        state.next_post_id += 1;
+        Ok((cursor < 2).then_some(cursor + 1))
+    }
+
+    fn fail(_: &mut State, _: u64) -> Result<Option<u64>, String> {
+        Err("broken".into())
+    }
+
+    #[test]
+    fn test_migrations() {
+        STATE.with(|cell| {
+            cell.replace(Default::default());
+            let state = &mut *cell.borrow_mut();
+            let migrations = &[
+                Migration {
+                    description: "count",
+                    step: count,
+                },
+                Migration {
+                    description: "fail",
+                    step: fail,
+                },
+            ];
+
+            // the first migration runs in three steps
+            assert!(run_step(state, migrations));
+            assert_eq!(state.migration_cursor, 1);
+            assert!(run_step(state, migrations));
+            assert!(run_step(state, migrations));
+            assert_eq!(state.memory.schema_version(), 1);
+            assert_eq!(state.migration_cursor, 0);
+            assert_eq!(state.next_post_id, 3);
+            assert!(state
+                .logger
+                .events
+                .last()
+                .unwrap()
+                .message
+                .contains("completed"));
+
+            // a failed migration stops and gets retried
+            assert!(!run_step(state, migrations));
+            assert_eq!(state.memory.schema_version(), 1);
+            let event = state.logger.events.last().unwrap();
+            assert_eq!(event.level, "ERROR");
+            assert!(event.message.contains("broken"));
+
+            // nothing happens if the state is up to date
+            state.memory.set_schema_version(2);
+            assert!(!run_step(state, migrations));
+        })
+    }
+
+    #[test]
+    fn test_hot_list_persistence() {
+        let state = State {
+            hot: vec![3, 2, 1].into_iter().collect(),
+            ..Default::default()
+        };
+        // the pending hot list survives snapshots until the migration drains it
+        let bytes = serde_cbor::to_vec(&state).unwrap();
+        let mut restored: State = serde_cbor::from_slice(&bytes).unwrap();
+        assert_eq!(restored.hot, state.hot);
+        assert_eq!(rank_hot_list(&mut restored, 0), Ok(None));
+        assert!(restored.hot.is_empty());
+    }
+
+    #[test]
+    fn test_index_open_post_reports() {
+        STATE.with(|cell| {
+            cell.replace(Default::default());
+            let state = &mut *cell.borrow_mut();
+            crate::env::tests::create_user(state, crate::env::tests::pr(0));
+            for i in 0..3 {
+                let id = Post::create(
+                    state,
+                    format!("Post {}", i),
+                    &[],
+                    crate::env::tests::pr(0),
+                    0,
+                    None,
+                    None,
+                    None,
+                )
+                .unwrap();
+                Post::mutate(state, &id, |post| {
+                    post.report = Some(Report {
+                        closed: id == 1,
+                        ..Default::default()
+                    });
+                    Ok(())
+                })
+                .unwrap();
+            }
+            assert_eq!(index_open_post_reports(state, 0), Ok(None));
+            assert_eq!(
+                state.open_post_reports,
+                vec![0, 2].into_iter().collect::<BTreeSet<_>>()
+            );
+        })
+    }
+}
@@ -1,10 +1,10 @@
 use self::canisters::{upgrade_main_canister, NNSVote};
 use self::invoices::{parse_account, user_icp_account, Invoice, USER_ICP_SUBACCOUNT};
-use self::post::{archive_cold_posts, Extension, Poll, Post, PostId};
+use self::post::{archive_cold_posts, Extension, HotRanking, Poll, Post, PostId};
 use self::proposals::{Payload, Status};
-use self::reports::Report;
+use self::reports::{Report, ReportCategory};
 use self::token::account;
-use self::user::{Filters, Notification, Predicate};
+use self::user::{Draft, Filters, Notification, Predicate};
 use crate::env::invoices::principal_to_subaccount;
 use crate::env::user::CyclesDelta;
 use crate::proposals::Proposal;
@@ -20,16 +20,26 @@ use invoices::Invoices;
 use serde::{Deserialize, Serialize};
 use serde_bytes::ByteBuf;
 use sha2::{Digest, Sha256};
+use std::borrow::Cow;
 use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
 use user::{User, UserId};
 
+pub mod backup;
+pub mod badges;
 pub mod canisters;
 pub mod config;
+pub mod delegates;
+pub mod deletion;
+pub mod export;
+pub mod images;
 pub mod invoices;
 pub mod memory;
+pub mod migrations;
 pub mod post;
 pub mod proposals;
+pub mod recovery;
 pub mod reports;
+pub mod spam;
 pub mod storage;
 pub mod token;
 pub mod user;
@@ -52,6 +62,12 @@ pub struct NNSProposal {
     pub summary: String,
 }
 
+enum MuteKey {
+    User(UserId),
+    Tag(String),
+    Thread(PostId),
+}
+
 #[derive(Default, Deserialize, Serialize)]
 pub struct SearchResult {
     pub id: PostId,
@@ -90,16 +106,20 @@ pub struct Stats {
     last_daily_chores: u64,
     last_hourly_chores: u64,
     stalwarts: Vec<UserId>,
+    // (stalwart id, accuracy, average response time)
+    moderation: Vec<(UserId, Option<u64>, u64)>,
     bots: Vec<UserId>,
     state_size: u64,
     active_users: usize,
     invited_users: usize,
-    buckets: Vec<(String, u64)>,
+    // (id, size, WASM module hash or upgrade state)
+    buckets: Vec<(String, u64, String)>,
     users_online: usize,
     last_upgrade: u64,
     module_hash: String,
     canister_id: Principal,
     circulating_supply: u64,
+    fragmentation: f64,
     meta: String,
 }
 
@@ -114,6 +134,10 @@ pub struct Realm {
     pub num_members: u64,
     #[serde(default)]
     pub last_update: u64,
+    #[serde(default)]
+    pub archived: bool,
+    #[serde(default)]
+    pub pending_transfer: Option<UserId>,
 }
 
 #[derive(Default, Serialize, Deserialize)]
@@ -121,7 +145,7 @@ pub struct State {
     pub burned_cycles: i64,
     pub burned_cycles_total: Cycles,
     pub posts: BTreeMap<PostId, Post>,
-    pub users: BTreeMap<UserId, User>,
+    pub users: memory::Archive<UserId, User>,
     pub principals: HashMap<Principal, UserId>,
     pub next_post_id: PostId,
     pub next_user_id: UserId,
@@ -131,18 +155,21 @@ pub struct State {
     pub last_daily_chores: u64,
     pub last_hourly_chores: u64,
     pub logger: Logger,
+    // The hot list before the introduction of the ranking. It is drained by the migration and
+    // persisted until then, so that an interrupted migration can resume.
+    #[serde(default)]
     pub hot: VecDeque<PostId>,
     pub invites: BTreeMap<String, (UserId, Cycles)>,
     pub realms: BTreeMap<String, Realm>,
 
-    #[serde(skip)]
+    #[serde(default)]
     pub balances: HashMap<Account, Token>,
 
     total_revenue_shared: u64,
     total_rewards_shared: u64,
 
-    pub proposals: Vec<Proposal>,
-    pub ledger: Vec<Transaction>,
+    pub proposals: memory::Archive<u32, Proposal>,
+    pub ledger: memory::Archive<u64, Transaction>,
 
     pub team_tokens: HashMap<UserId, Token>,
 
@@ -170,6 +197,36 @@ pub struct State {
 
     #[serde(default)]
     last_revenues: VecDeque<u64>,
+
+    #[serde(default)]
+    pub open_post_reports: BTreeSet<PostId>,
+
+    #[serde(default)]
+    pub held_posts: BTreeMap<UserId, spam::HeldPost>,
+
+    #[serde(default)]
+    pub hot_ranking: HotRanking,
+
+    #[serde(default)]
+    pub recoveries: BTreeMap<UserId, recovery::Recovery>,
+
+    // Ids of the ledger transactions sent or received by a principal.
+    #[serde(default)]
+    pub principal_transactions: BTreeMap<Principal, BTreeSet<u64>>,
+
+    // Certified hashes of the user badges.
+    #[serde(default)]
+    pub badge_hashes: BTreeMap<UserId, [u8; 32]>,
+
+    // The progress of the pending schema migration.
+    #[serde(default)]
+    pub migration_cursor: u64,
+
+    #[serde(skip)]
+    pub backup: Option<backup::Backup>,
+
+    #[serde(skip)]
+    pub check: Option<backup::Check>,
 }
 
 #[derive(Default, Deserialize, Serialize)]
@@ -206,7 +263,7 @@ pub enum Destination {
 impl State {
     pub fn voted_on_pending_proposal(&self, principal: Principal) -> bool {
         if let Some(user) = self.principal_to_user(principal) {
-            self.proposals.iter().any(|proposal| {
+            self.proposals.values().any(|proposal| {
                 proposal.status == Status::Open
                     && proposal
                         .bulletins
@@ -236,7 +293,7 @@ impl State {
     pub fn execute_pending_upgrade(&mut self, force: bool) -> bool {
         let pending_upgrade =
             self.proposals
-                .iter()
+                .values()
                 .rev()
                 .find_map(|proposal| match &proposal.payload {
                     Payload::Release(payload)
@@ -382,12 +439,18 @@ impl State {
 
     pub fn load(&mut self) {
         assets::load();
-        match token::balances_from_ledger(&self.ledger) {
-            Ok(value) => self.balances = value,
-            Err(err) => self.logger.log(
-                format!("the token ledger is inconsistent: {}", err),
-                "CRITICAL".into(),
-            ),
+        // Balances are a part of the snapshot; they are only restored from the ledger
+        // if the snapshot predates them.
+        if self.balances.is_empty() && !self.ledger.is_empty() {
+            match token::balances_from_ledger(
+                (0..self.ledger.len() as u64).filter_map(|id| self.ledger.get(&id)),
+            ) {
+                Ok(value) => self.balances = value,
+                Err(err) => self.logger.log(
+                    format!("the token ledger is inconsistent: {}", err),
+                    "CRITICAL".into(),
+                ),
+            }
         }
         if !self.realms.contains_key(CONFIG.dao_realm) {
             self.realms.insert(
@@ -400,23 +463,24 @@ impl State {
                 },
             );
         }
+        self.hot_ranking.load();
+        assets::load_badges(self.badge_hashes.iter());
         self.last_upgrade = time();
         self.last_hourly_chores = time();
     }
 
     pub fn realms_posts(&self, caller: Principal, page: usize) -> Vec<Post> {
-        let realm_ids = match self
-            .principal_to_user(caller)
-            .map(|user| user.realms.as_slice())
-        {
-            None | Some(&[]) => return Default::default(),
-            Some(ids) => ids.iter().collect::<BTreeSet<_>>(),
+        let realm_ids = match self.principal_to_user(caller) {
+            Some(user) if !user.realms.is_empty() => {
+                user.realms.iter().cloned().collect::<BTreeSet<_>>()
+            }
+            _ => return Default::default(),
         };
         self.last_posts(caller, None, false)
             .filter(|post| {
                 post.realm
                     .as_ref()
-                    .map(|id| realm_ids.contains(&id))
+                    .map(|id| realm_ids.contains(id))
                     .unwrap_or_default()
             })
             .skip(page * CONFIG.feed_page_size)
@@ -425,11 +489,18 @@ impl State {
             .collect()
     }
 
-    pub fn hot_posts(&self, realm: Option<String>, page: usize) -> Vec<Post> {
-        self.hot
-            .iter()
+    pub fn hot_posts(&self, caller: Principal, realm: Option<String>, page: usize) -> Vec<Post> {
+        let now = time();
+        let user = self.principal_to_user(caller);
+        let mutes = user.as_ref().map(|user| &user.mutes);
+        self.hot_ranking
+            .iter(realm)
             .filter_map(|post_id| Post::get(self, post_id))
-            .filter(|post| realm.is_none() || post.realm == realm)
+            .filter(|post| {
+                !mutes
+                    .map(|mutes| mutes.muted(self, post, now))
+                    .unwrap_or_default()
+            })
             .skip(page * CONFIG.feed_page_size)
             .take(CONFIG.feed_page_size)
             .cloned()
@@ -440,6 +511,7 @@ impl State {
         if !self.realms.contains_key(&name) {
             return false;
         }
+        let archived = self.realms.get(&name).map(|realm| realm.archived) == Some(true);
         let user = match self.principal_to_user_mut(principal) {
             Some(user) => user,
             _ => return false,
@@ -452,6 +524,9 @@ impl State {
                 .num_members -= 1;
             return false;
         }
+        if archived {
+            return false;
+        }
         user.realms.push(name.clone());
         self.realms
             .get_mut(&name)
@@ -460,6 +535,77 @@ impl State {
         true
     }
 
+    pub fn transfer_realm(
+        &mut self,
+        principal: Principal,
+        name: String,
+        recipient: UserId,
+    ) -> Result<(), String> {
+        let user = self.principal_to_user(principal).ok_or("no user found")?;
+        let user_id = user.id;
+        let user_name = user.name.clone();
+        let realm = self.realms.get_mut(&name).ok_or("no realm found")?;
+        if !realm.controllers.contains(&user_id) {
+            return Err("not authorized".into());
+        }
+        if realm.archived {
+            return Err("realm is archived".into());
+        }
+        if realm.controllers.len() == 1 && realm.controllers.contains(&recipient) {
+            return Err("user already controls the realm".into());
+        }
+        let recipient = self.users.get_mut(&recipient).ok_or("no recipient found")?;
+        realm.pending_transfer = Some(recipient.id);
+        recipient.notify(format!(
+            "@{} wants to transfer the realm [{1}](/#/realm/{1}) to you. Accept the transfer on the realm page.",
+            user_name, name
+        ));
+        Ok(())
+    }
+
+    pub fn accept_realm_transfer(
+        &mut self,
+        principal: Principal,
+        name: String,
+    ) -> Result<(), String> {
+        let user = self.principal_to_user(principal).ok_or("no user found")?;
+        let user_id = user.id;
+        let user_name = user.name.clone();
+        let realm = self.realms.get(&name).ok_or("no realm found")?;
+        if realm.pending_transfer != Some(user_id) {
+            return Err("no pending transfer".into());
+        }
+        let previous_controllers = realm.controllers.clone();
+        self.assign_realm_controller(&name, user_id)?;
+        for id in previous_controllers {
+            if let Some(user) = self.users.get_mut(&id) {
+                user.notify(format!(
+                    "@{} accepted the transfer of the realm [{1}](/#/realm/{1}).",
+                    user_name, name
+                ));
+            }
+        }
+        self.logger.info(format!(
+            "Realm [{0}](/#/realm/{0}) was transferred to @{1}.",
+            name, user_name
+        ));
+        Ok(())
+    }
+
+    // Makes the given user the only controller of the realm and adds them to its members.
+    fn assign_realm_controller(&mut self, name: &str, user_id: UserId) -> Result<(), String> {
+        let realm = self.realms.get_mut(name).ok_or("no realm found")?;
+        let user = self.users.get_mut(&user_id).ok_or("no user found")?;
+        realm.controllers = vec![user_id].into_iter().collect();
+        realm.pending_transfer = None;
+        realm.archived = false;
+        if !user.realms.iter().any(|realm| realm == name) {
+            user.realms.push(name.to_string());
+            realm.num_members += 1;
+        }
+        Ok(())
+    }
+
     #[allow(clippy::too_many_arguments)]
     pub fn edit_realm(
         &mut self,
@@ -479,6 +625,9 @@ impl State {
         if !realm.controllers.contains(&user_id) {
             return Err("not authorized".into());
         }
+        if realm.archived {
+            return Err("realm is archived".into());
+        }
         if controllers.is_empty() {
             return Err("no controllers specified".into());
         }
@@ -532,7 +681,7 @@ impl State {
         let user = self
             .principal_to_user(principal)
             .ok_or("no user found")?
-            .clone();
+            .into_owned();
 
         self.charge(user.id, CONFIG.realm_cost, "realm creation".to_string())
             .map_err(|err| {
@@ -563,27 +712,66 @@ impl State {
     }
 
     pub async fn tip(principal: Principal, post_id: PostId, amount: String) -> Result<(), String> {
-        let result: Result<_, String> = read(|state| {
-            let tipper = state.principal_to_user(principal).ok_or("no user found")?;
-            let tipper_id = tipper.id;
+        let e8s = (parse_icp(&amount)? + invoices::fee()).e8s();
+        let result: Result<_, String> = mutate(|state| {
+            let post = Post::get(state, &post_id).ok_or("post not found")?;
+            let tipper_id = delegates::resolve(
+                state,
+                principal,
+                delegates::Permission::Tip,
+                post.realm.as_ref(),
+                0,
+                time(),
+            )?;
+            let tipper = state.users.get(&tipper_id).ok_or("no user found")?;
+            let tipper_principal = tipper.principal;
             let tipper_name = tipper.name.clone();
-            let author_id = Post::get(state, &post_id).ok_or("post not found")?.user;
+            let author_id = post.user;
+            if state.blocked(author_id, tipper_id) {
+                return Err("you were blocked by the author".into());
+            }
             let recipient = state
                 .users
                 .get(&author_id)
                 .ok_or("no user found")?
                 .account
                 .clone();
-            Ok((recipient, tipper_name, author_id, tipper_id))
+            delegates::spend_e8s(state, tipper_id, principal, e8s)?;
+            Ok((
+                recipient,
+                tipper_name,
+                author_id,
+                tipper_id,
+                tipper_principal,
+            ))
         });
-        let (recipient, tipper_name, author_id, tipper_id) = result?;
-        let tip = State::icp_transfer(principal, recipient, &amount).await?;
+        let (recipient, tipper_name, author_id, tipper_id, tipper_principal) = result?;
+        let tip = match State::icp_transfer(tipper_principal, recipient, &amount).await {
+            Ok(tip) => tip,
+            Err(err) => {
+                mutate(|state| delegates::refund_e8s(state, tipper_id, principal, e8s));
+                return Err(err);
+            }
+        };
         mutate(|state| {
-            Post::mutate(state, &post_id, |post| {
+            delegates::record(
+                state,
+                tipper_id,
+                principal,
+                0,
+                format!("tip of {} ICP for post {}", amount, post_id),
+                time(),
+            );
+            let users_len = state.users.len();
+            let mut hot_ranking = std::mem::take(&mut state.hot_ranking);
+            let result = Post::mutate(state, &post_id, |post| {
                 post.watchers.insert(tipper_id);
                 post.tips.push((tipper_id, tip.e8s()));
+                hot_ranking.update(post, users_len);
                 Ok(())
-            })?;
+            });
+            state.hot_ranking = hot_ranking;
+            result?;
             state
                 .users
                 .get_mut(&author_id)
@@ -716,7 +904,7 @@ impl State {
         });
     }
 
-    fn notify_with_predicate<T: AsRef<str>>(
+    pub fn notify_with_predicate<T: AsRef<str>>(
         &mut self,
         filter: &dyn Fn(&User) -> bool,
         message: T,
@@ -748,9 +936,24 @@ impl State {
                         Predicate::ReportOpen(post_id) => Post::get(self, post_id)
                             .and_then(|p| p.report.as_ref().map(|r| !r.closed))
                             .unwrap_or_default(),
+                        Predicate::UserAppealOpen(user_id) => self
+                            .users
+                            .get(user_id)
+                            .and_then(|p| {
+                                p.report
+                                    .as_ref()
+                                    .map(|r| r.appeal.as_ref().map(|a| !a.closed))
+                            })
+                            .flatten()
+                            .unwrap_or_default(),
+                        Predicate::AppealOpen(post_id) => Post::get(self, post_id)
+                            .and_then(|p| p.report.as_ref())
+                            .and_then(|r| r.appeal.as_ref().map(|a| !a.closed))
+                            .unwrap_or_default(),
+                        Predicate::HeldPost(user_id) => self.held_posts.contains_key(user_id),
                         Predicate::Proposal(post_id) => self
                             .proposals
-                            .iter()
+                            .values()
                             .last()
                             .map(|p| p.status == Status::Open && p.post_id == *post_id)
                             .unwrap_or_default(),
@@ -762,7 +965,14 @@ impl State {
         }
     }
 
-    pub fn search(&self, mut term: String) -> Vec<SearchResult> {
+    /// Searches users, realms, tags and posts. If `filtered` is set, posts hidden by the
+    /// caller's filters and mutes are skipped.
+    pub fn search(&self, caller: Principal, mut term: String, filtered: bool) -> Vec<SearchResult> {
+        let viewer = if filtered {
+            caller
+        } else {
+            Principal::anonymous()
+        };
         const SNIPPET_LEN: usize = 100;
         term = term.to_lowercase();
         let snippet = |body: &str, i: usize| {
@@ -837,31 +1047,30 @@ impl State {
                         None
                     }),
             )
-            .chain(
-                self.last_posts(Principal::anonymous(), None, true)
-                    .filter_map(|Post { id, body, user, .. }| {
-                        if id.to_string() == term {
-                            return Some(SearchResult {
-                                id: *id,
-                                user_id: *user,
-                                relevant: snippet(body, 0),
-                                result: "post".to_string(),
-                                ..Default::default()
-                            });
-                        }
-                        let search_body = body.to_lowercase();
-                        if let Some(i) = search_body.find(&term) {
-                            return Some(SearchResult {
-                                id: *id,
-                                user_id: *user,
-                                relevant: snippet(body, i),
-                                result: "post".to_string(),
-                                ..Default::default()
-                            });
-                        }
-                        None
-                    }),
-            )
+            .chain(self.last_posts(viewer, None, true).filter_map(
+                |Post { id, body, user, .. }| {
+                    if id.to_string() == term {
+                        return Some(SearchResult {
+                            id: *id,
+                            user_id: *user,
+                            relevant: snippet(body, 0),
+                            result: "post".to_string(),
+                            ..Default::default()
+                        });
+                    }
+                    let search_body = body.to_lowercase();
+                    if let Some(i) = search_body.find(&term) {
+                        return Some(SearchResult {
+                            id: *id,
+                            user_id: *user,
+                            relevant: snippet(body, i),
+                            result: "post".to_string(),
+                            ..Default::default()
+                        });
+                    }
+                    None
+                },
+            ))
             .take(100)
             .collect()
     }
@@ -1023,7 +1232,7 @@ impl State {
         // Mint team tokens
         for user in [0, 305]
             .iter()
-            .filter_map(|id| self.users.get(id).cloned())
+            .filter_map(|id| self.users.get(id).map(Cow::into_owned))
             .collect::<Vec<_>>()
         {
             let acc = account(user.principal);
@@ -1096,28 +1305,7 @@ impl State {
     ) -> Result<Tokens, String> {
         State::claim_user_icp(principal).await?;
 
-        fn parse(amount: &str) -> Result<Tokens, String> {
-            let parse = |s: &str| {
-                s.parse::<u64>()
-                    .map_err(|err| format!("Couldn't parse as u64: {:?}", err))
-            };
-            match &amount.split('.').collect::<Vec<_>>().as_slice() {
-                [icpts] => Ok(Tokens::from_e8s(parse(icpts)? * 10_u64.pow(8))),
-                [icpts, e8s] => {
-                    let mut e8s = e8s.to_string();
-                    while e8s.len() < 8 {
-                        e8s.push('0');
-                    }
-                    let e8s = &e8s[..8];
-                    Ok(Tokens::from_e8s(
-                        parse(icpts)? * 10_u64.pow(8) + parse(e8s)?,
-                    ))
-                }
-                _ => Err(format!("Can't parse amount {}", amount)),
-            }
-        }
-
-        let amount = parse(amount)?;
+        let amount = parse_icp(amount)?;
         invoices::transfer(
             parse_account(&recipient)?,
             amount + invoices::fee(),
@@ -1213,6 +1401,39 @@ impl State {
         }
     }
 
+    /// Checks if `bytes` can be added to the draft of the given user without exceeding the size
+    /// limit of one draft or of all drafts.
+    pub fn check_draft_growth(&self, principal: Principal, bytes: usize) -> Result<(), String> {
+        let size = self
+            .principal_to_user(principal)
+            .and_then(|user| user.draft.as_ref().map(Draft::size))
+            .unwrap_or_default();
+        if size + bytes > CONFIG.max_draft_size_bytes {
+            return Err("post attachments are too large".into());
+        }
+        let total = self
+            .users
+            .values()
+            .filter_map(|user| user.draft.as_ref())
+            .map(Draft::size)
+            .sum::<usize>();
+        if total + bytes > CONFIG.max_drafts_bytes {
+            return Err("too many uploads in progress, please retry later".into());
+        }
+        Ok(())
+    }
+
+    // Drops drafts of posts which were never committed.
+    fn expire_drafts(&mut self, now: u64) {
+        for user in self.users.values_mut() {
+            if matches!(&user.draft, Some(draft)
+                if draft.timestamp + CONFIG.draft_expiration_hours * HOUR < now)
+            {
+                user.draft = None;
+            }
+        }
+    }
+
     fn conclude_polls(&mut self, now: u64) {
         for post_id in self.pending_polls.clone() {
             match Post::conclude_poll(self, &post_id, now) {
@@ -1230,7 +1451,7 @@ impl State {
         mutate(|state| {
             for proposal_id in state
                 .proposals
-                .iter()
+                .values()
                 .filter_map(|p| (p.status == Status::Open).then_some(p.id))
                 .collect::<Vec<_>>()
             {
@@ -1245,23 +1466,37 @@ impl State {
                 state.logger.info("An emergency release is pending! 🚨");
             }
 
-            if let Err(err) = state.archive_cold_data() {
+            if let Err(err) = state.archive_cold_data(now) {
                 state
                     .logger
                     .error(format!("couldn't archive cold data: {:?}", err));
             }
 
             state.recompute_stalwarts(now);
+
+            deletion::delete_accounts(state, now);
+
+            state.storage.expire_uploads(now);
         });
 
+        storage::Storage::collect_garbage(CONFIG.max_erased_blobs).await;
+        storage::Storage::verify_blobs(CONFIG.max_verified_blobs).await;
+
         if let Ok(e8s_for_one_xdr) = invoices::get_xdr_in_e8s().await {
             mutate(|state| state.e8s_for_one_xdr = e8s_for_one_xdr);
         }
     }
 
-    fn archive_cold_data(&mut self) -> Result<(), String> {
+    fn archive_cold_data(&mut self, now: u64) -> Result<(), String> {
         let max_posts_in_heap = 20_000;
-        archive_cold_posts(self, max_posts_in_heap)
+        archive_cold_posts(self, max_posts_in_heap)?;
+        let max_users_in_heap = 50_000;
+        user::archive_inactive_users(self, max_users_in_heap, now);
+        let max_proposals_in_heap = 1_000;
+        proposals::archive_concluded_proposals(self, max_proposals_in_heap);
+        let max_transactions_in_heap = 100_000;
+        token::archive_transactions(self, max_transactions_in_heap);
+        Ok(())
     }
 
     async fn handle_nns_proposals(now: u64) {
@@ -1376,7 +1611,23 @@ impl State {
         mutate(|state| {
             // Automatically dump the heap to the stable memory. This should be the first
             // opearation to avoid blocking of the backup by a panic in other parts of the routine.
-            memory::heap_to_stable(state);
+            // The memory is left untouched while a backup is exported or imported. The compaction
+            // runs before the snapshot, so that the snapshot refers to the relocated objects.
+            if state.backup.is_none() {
+                if let Err(err) = memory::compact(
+                    state,
+                    CONFIG.max_compaction_moves,
+                    CONFIG.max_compaction_scanned,
+                ) {
+                    state
+                        .logger
+                        .error(format!("couldn't compact the memory: {:?}", err));
+                }
+
+                memory::heap_to_stable(state);
+            }
+
+            state.expire_drafts(now);
 
             state.conclude_polls(now)
         });
@@ -1384,6 +1635,8 @@ impl State {
         State::top_up().await;
 
         State::handle_nns_proposals(now).await;
+
+        storage::Storage::upgrade_buckets().await;
     }
 
     pub async fn chores(now: u64) {
@@ -1420,7 +1673,7 @@ impl State {
         mutate(|state| state.clean_up(now));
 
         // We only mint and distribute if no open proposals exists
-        if read(|state| state.proposals.iter().all(|p| p.status != Status::Open)) {
+        if read(|state| state.proposals.values().all(|p| p.status != Status::Open)) {
             mutate(|state| state.mint());
             match invoices::get_xdr_in_e8s().await {
                 Ok(e8s_for_one_xdr) => {
@@ -1452,6 +1705,7 @@ impl State {
     }
 
     fn clean_up(&mut self, now: u64) {
+        let mut expired_mutes = Vec::new();
         for user in self.users.values_mut() {
             user.accounting.clear();
             if user.active_within_weeks(now, 1) {
@@ -1473,8 +1727,26 @@ impl State {
                 );
             }
             user.karma_donations.clear();
+            let muter = user.id;
+            expired_mutes.extend(
+                user.mutes
+                    .clean_up(now)
+                    .into_iter()
+                    .map(|muted| (muter, muted)),
+            );
+            delegates::clean_up(user, now);
+        }
+        for (muter, muted) in expired_mutes {
+            if let Some(user) = self.users.get_mut(&muted) {
+                user.muted_by.remove(&muter);
+            }
         }
         self.accounting.clean_up();
+        self.held_posts
+            .retain(|_, post| post.timestamp + WEEK > now);
+        self.recoveries.retain(|_, recovery| {
+            recovery.timestamp + CONFIG.recovery_expiration_weeks * WEEK > now
+        });
     }
 
     fn charge_for_inactivity(&mut self, now: u64) {
@@ -1509,10 +1781,10 @@ impl State {
     }
 
     fn recompute_stalwarts(&mut self, now: u64) {
+        let mut stalwart_seats = (self.users.len() * CONFIG.stalwart_percentage / 100).max(3);
         let mut users = self.users.values_mut().collect::<Vec<_>>();
         users.sort_unstable_by_key(|a| std::cmp::Reverse(a.karma()));
 
-        let mut stalwart_seats = (users.len() * CONFIG.stalwart_percentage / 100).max(3);
         let mut left = Vec::new();
         let mut joined = Vec::new();
         let mut left_logs = Vec::new();
@@ -1528,6 +1800,17 @@ impl State {
                 u.stalwart = false;
                 continue;
             }
+            u.moderation.prune(now);
+            if u.moderation.poor() {
+                if u.stalwart {
+                    u.stalwart = false;
+                    // let the user rejoin once they become active again
+                    u.moderation.consecutive_abstentions = 0;
+                    left.push(u.id);
+                    left_logs.push(format!("@{} (moderation)", u.name));
+                }
+                continue;
+            }
             match (
                 u.stalwart,
                 u.active_weeks >= CONFIG.min_stalwart_activity_weeks as u32,
@@ -1596,7 +1879,7 @@ impl State {
 
     // Check if user has some unclaimed e8s in the Treasury and transfers them to user's account.
     async fn claim_user_icp(principal: Principal) -> Result<(), String> {
-        let user = match read(|state| state.principal_to_user(principal).cloned()) {
+        let user = match read(|state| state.principal_to_user(principal).map(Cow::into_owned)) {
             Some(user) => user,
             None => return Ok(()),
         };
@@ -1678,7 +1961,8 @@ impl State {
                 .chain(user.previous_names.iter())
                 .map(|name| name.to_lowercase())
                 .any(|existing_name| existing_name == name)
-        }) {
+        }) || self.users.find(&name).is_some()
+        {
             return Err("taken".into());
         }
         if name.len() < 2 || name.len() > 16 {
@@ -1732,20 +2016,109 @@ impl State {
             .collect()
     }
 
+    pub fn collect_bookmark(
+        &mut self,
+        principal: Principal,
+        name: &str,
+        post_id: PostId,
+        note: String,
+    ) -> Result<(), String> {
+        if Post::get(self, &post_id).is_none() {
+            return Err("no post found".into());
+        }
+        self.principal_to_user_mut(principal)
+            .ok_or("no user found")?
+            .collect_bookmark(name, post_id, note)
+    }
+
+    /// Mutes a user, a tag or the thread of a post for the given number of hours or, if no
+    /// duration is specified, until the mute gets removed.
+    pub fn mute(
+        &mut self,
+        principal: Principal,
+        kind: String,
+        value: String,
+        hours: Option<u64>,
+        now: u64,
+    ) -> Result<(), String> {
+        let user_id = self.principal_to_user(principal).ok_or("no user found")?.id;
+        let expiry = match hours {
+            Some(0) => return Err("invalid duration".into()),
+            Some(hours) => now.saturating_add(hours.saturating_mul(HOUR)),
+            None => u64::MAX,
+        };
+        let key = self.mute_key(user_id, &kind, &value)?;
+        if let MuteKey::User(id) = key {
+            self.users
+                .get_mut(&id)
+                .expect("no user found")
+                .muted_by
+                .insert(user_id);
+        }
+        let mutes = &mut self.users.get_mut(&user_id).expect("no user found").mutes;
+        match key {
+            MuteKey::User(id) => mutes.users.insert(id, expiry),
+            MuteKey::Tag(tag) => mutes.tags.insert(tag, expiry),
+            MuteKey::Thread(id) => mutes.threads.insert(id, expiry),
+        };
+        Ok(())
+    }
+
+    pub fn unmute(
+        &mut self,
+        principal: Principal,
+        kind: String,
+        value: String,
+    ) -> Result<(), String> {
+        let user_id = self.principal_to_user(principal).ok_or("no user found")?.id;
+        let key = self.mute_key(user_id, &kind, &value)?;
+        if let MuteKey::User(id) = key {
+            if let Some(user) = self.users.get_mut(&id) {
+                user.muted_by.remove(&user_id);
+            }
+        }
+        let mutes = &mut self.users.get_mut(&user_id).expect("no user found").mutes;
+        match key {
+            MuteKey::User(id) => mutes.users.remove(&id),
+            MuteKey::Tag(tag) => mutes.tags.remove(&tag),
+            MuteKey::Thread(id) => mutes.threads.remove(&id),
+        }
+        .map(|_| ())
+        .ok_or_else(|| "no mute found".into())
+    }
+
+    fn mute_key(&self, user_id: UserId, kind: &str, value: &str) -> Result<MuteKey, String> {
+        match kind {
+            "user" => {
+                let id = value.parse().map_err(|_| "cannot parse user id")?;
+                if id == user_id {
+                    return Err("you can't mute yourself".into());
+                }
+                if !self.users.contains_key(&id) {
+                    return Err("no user found".into());
+                }
+                Ok(MuteKey::User(id))
+            }
+            "tag" => Ok(MuteKey::Tag(value.to_lowercase())),
+            "thread" => {
+                let id = value.parse().map_err(|_| "cannot parse post id")?;
+                self.thread(id)
+                    .next()
+                    .map(MuteKey::Thread)
+                    .ok_or_else(|| "no post found".into())
+            }
+            _ => Err("mute unknown".into()),
+        }
+    }
+
     pub fn last_posts<'a>(
         &'a self,
         caller: Principal,
         realm: Option<String>,
         with_comments: bool,
     ) -> Box<dyn Iterator<Item = &'a Post> + 'a> {
-        let inverse_filters = self.principal_to_user(caller).and_then(|user| {
-            let filters = &user.filters;
-            if filters.is_empty() {
-                None
-            } else {
-                Some(filters)
-            }
-        });
+        let user = self.principal_to_user(caller);
+        let now = time();
         Box::new(
             {
                 let last_id = self.next_post_id.saturating_sub(1);
@@ -1753,6 +2126,14 @@ impl State {
             }
             .filter_map(move |i| Post::get(self, &i))
             .filter(move |post| {
+                let inverse_filters = user
+                    .as_ref()
+                    .map(|user| &user.filters)
+                    .filter(|filters| !filters.is_empty());
+                let mutes = user
+                    .as_ref()
+                    .map(|user| &user.mutes)
+                    .filter(|mutes| !mutes.is_empty());
                 !post.is_deleted()
                     && (with_comments || post.parent.is_none())
                     && (realm.is_none() || post.realm == realm)
@@ -1772,6 +2153,9 @@ impl State {
                             },
                         )
                         .unwrap_or(true)
+                    && !mutes
+                        .map(|mutes| mutes.muted(self, post, now))
+                        .unwrap_or_default()
             }),
         )
     }
@@ -1824,18 +2208,22 @@ impl State {
         Box::new(result.into_iter().rev())
     }
 
-    pub fn user(&self, handle: &str) -> Option<&User> {
+    pub fn user(&self, handle: &str) -> Option<Cow<'_, User>> {
         handle
             .parse::<u64>()
             .ok()
             .and_then(|id| self.users.get(&id))
             .or_else(|| {
-                self.users.values().find(|user| {
-                    std::iter::once(&user.name)
-                        .chain(user.previous_names.iter())
-                        .any(|name| name.to_lowercase() == handle.to_lowercase())
-                })
+                self.users
+                    .values()
+                    .find(|user| {
+                        std::iter::once(&user.name)
+                            .chain(user.previous_names.iter())
+                            .any(|name| name.to_lowercase() == handle.to_lowercase())
+                    })
+                    .map(Cow::Borrowed)
             })
+            .or_else(|| self.users.find(&handle.to_lowercase()))
     }
 
     pub async fn change_principal(
@@ -1888,7 +2276,7 @@ impl State {
         Ok(())
     }
 
-    pub fn principal_to_user(&self, principal: Principal) -> Option<&User> {
+    pub fn principal_to_user(&self, principal: Principal) -> Option<Cow<'_, User>> {
         self.principals
             .get(&principal)
             .and_then(|id| self.users.get(id))
@@ -1975,6 +2363,7 @@ impl State {
                 / self.last_revenues.len().max(1) as u64,
             team_tokens: self.team_tokens.clone(),
             meta: format!("Memory health: {}", self.memory.health("MB")),
+            fragmentation: self.memory.fragmentation(),
             weekly_karma_leaders,
             bootcamp_users,
             module_hash: self.module_hash.clone(),
@@ -1994,6 +2383,16 @@ impl State {
             total_rewards_shared: self.total_rewards_shared,
             account: invoices::main_account().to_string(),
             users_online,
+            moderation: stalwarts
+                .iter()
+                .map(|u| {
+                    (
+                        u.id,
+                        u.moderation.accuracy(),
+                        u.moderation.average_response_time(),
+                    )
+                })
+                .collect(),
             stalwarts: stalwarts.into_iter().map(|u| u.id).collect(),
             bots,
             state_size: stable64_size() << 16,
@@ -2004,7 +2403,7 @@ impl State {
                 .storage
                 .buckets
                 .iter()
-                .map(|(id, size)| (id.to_string(), *size))
+                .map(|(id, size)| (id.to_string(), *size, self.storage.bucket_status(*id)))
                 .collect(),
         }
     }
@@ -2019,12 +2418,12 @@ impl State {
         let user = self
             .principal_to_user(principal)
             .ok_or("no user found")?
-            .clone();
+            .into_owned();
         if !user.stalwart {
             return Err("only stalwarts can vote on reports".into());
         }
         let stalwarts = self.users.values().filter(|u| u.stalwart).count();
-        let (user_id, report, penalty, subject) = match domain.as_str() {
+        let (user_id, mut report, penalty, subject) = match domain.as_str() {
             "post" => Post::mutate(
                 self,
                 &id,
@@ -2032,12 +2431,8 @@ impl State {
                     post.vote_on_report(stalwarts, user.id, vote)?;
                     let post_user = post.user;
                     let post_report = post.report.clone().ok_or("no report")?;
-                    Ok((
-                        post_user,
-                        post_report,
-                        CONFIG.reporting_penalty_post,
-                        format!("post {}", id),
-                    ))
+                    let penalty = post_report.category.penalty("post");
+                    Ok((post_user, post_report, penalty, format!("post {}", id)))
                 },
             )?,
             "misbehaviour" => {
@@ -2053,33 +2448,64 @@ impl State {
                 (
                     id,
                     report.clone(),
-                    CONFIG.reporting_penalty_misbehaviour,
+                    report.category.penalty("misbehaviour"),
                     format!("user {}", id),
                 )
             }
             _ => return Err("unknown report type".into()),
         };
-        reports::finalize_report(self, &report, penalty, user_id, subject)
+        if let Some(stats) = self.users.get_mut(&user.id).map(|u| &mut u.moderation) {
+            stats.votes += 1;
+            stats.response_time += time().saturating_sub(report.timestamp);
+            stats.consecutive_abstentions = 0;
+        }
+        if report.closed && domain == "post" {
+            self.open_post_reports.remove(&id);
+        }
+        reports::finalize_report(self, &mut report, penalty, user_id, subject)?;
+        if report.confirmed() {
+            self.set_report(&domain, id, report)?;
+        }
+        Ok(())
     }
 
-    pub fn vote_on_poll(
-        &mut self,
-        principal: Principal,
-        time: u64,
-        post_id: PostId,
-        vote: u16,
-    ) -> Result<(), String> {
-        let user = self
-            .principal_to_user(principal)
-            .ok_or_else(|| "no user found".to_string())?;
-        let (user_id, user_realms) = (user.id, user.realms.clone());
-        Post::mutate(self, &post_id, |post| {
-            post.watchers.insert(user_id);
-            post.vote_on_poll(user_id, user_realms.clone(), time, vote)
-        })
+    fn get_report(&self, domain: &str, id: u64) -> Result<(UserId, Report, String), String> {
+        match domain {
+            "post" => {
+                let post = Post::get(self, &id).ok_or("no post found")?;
+                Ok((
+                    post.user,
+                    post.report.clone().ok_or("no report")?,
+                    format!("post {}", id),
+                ))
+            }
+            "misbehaviour" => Ok((
+                id,
+                self.users
+                    .get(&id)
+                    .and_then(|u| u.report.clone())
+                    .ok_or("no report")?,
+                format!("user {}", id),
+            )),
+            _ => Err("unknown report type".into()),
+        }
     }
 
-    pub fn report(
+    fn set_report(&mut self, domain: &str, id: u64, report: Report) -> Result<(), String> {
+        match domain {
+            "post" => Post::mutate(self, &id, |post| {
+                post.report = Some(report);
+                Ok(())
+            }),
+            "misbehaviour" => {
+                self.users.get_mut(&id).ok_or("no user found")?.report = Some(report);
+                Ok(())
+            }
+            _ => Err("unknown report type".into()),
+        }
+    }
+
+    pub fn appeal_report(
         &mut self,
         principal: Principal,
         domain: String,
@@ -2089,23 +2515,130 @@ impl State {
         if reason.len() > 1000 {
             return Err("reason too long".into());
         }
-        let cycles_required = if domain == "post" {
-            CONFIG.reporting_penalty_post
-        } else {
-            CONFIG.reporting_penalty_misbehaviour
-        } / 2;
-        let user = match self.principal_to_user(principal) {
-            Some(user) if user.cycles() >= cycles_required => user.clone(),
-            _ => {
-                return Err(format!(
-                    "You need at least {} cycles for this report",
-                    cycles_required
-                ))
+        let user = self
+            .principal_to_user(principal)
+            .ok_or("no user found")?
+            .into_owned();
+        let (user_id, mut report, subject) = self.get_report(&domain, id)?;
+        if user_id != user.id {
+            return Err("only penalized users can appeal".into());
+        }
+        if !report.confirmed() {
+            return Err("only confirmed reports can be appealed".into());
+        }
+        if report.appeal.is_some() {
+            return Err("this report was already appealed".into());
+        }
+        self.charge(
+            user.id,
+            CONFIG.report_appeal_deposit,
+            format!("appeal deposit for {}", subject),
+        )
+        .map_err(|err| {
+            format!(
+                "couldn't charge {} cycles for the appeal: {}",
+                CONFIG.report_appeal_deposit, err
+            )
+        })?;
+        let predicate = if domain == "post" {
+            Predicate::AppealOpen(id)
+        } else {
+            Predicate::UserAppealOpen(id)
+        };
+        self.notify_with_predicate(
+            &|u| u.stalwart && u.id != user.id && u.id != report.reporter && !report.voted(u.id),
+            format!("@{} appealed the report for {}", user.name, subject),
+            predicate,
+        );
+        report.appeal = Some(Box::new(Report {
+            reporter: user.id,
+            reason,
+            ..Default::default()
+        }));
+        self.set_report(&domain, id, report)
+    }
+
+    pub fn vote_on_appeal(
+        &mut self,
+        principal: Principal,
+        domain: String,
+        id: u64,
+        vote: bool,
+    ) -> Result<(), String> {
+        let user = self
+            .principal_to_user(principal)
+            .ok_or("no user found")?
+            .into_owned();
+        if !user.stalwart {
+            return Err("only stalwarts can vote on appeals".into());
+        }
+        let (user_id, mut report, subject) = self.get_report(&domain, id)?;
+        if report.voted(user.id) || report.reporter == user.id {
+            return Err("you can't vote on the appeal of a report you created or voted on".into());
+        }
+        // only stalwarts not involved into the original report can vote
+        let stalwarts = self
+            .users
+            .values()
+            .filter(|u| {
+                u.stalwart && u.id != user_id && u.id != report.reporter && !report.voted(u.id)
+            })
+            .count();
+        let appeal = report.appeal.as_mut().ok_or("no appeal found")?;
+        if appeal.closed {
+            return Err("appeal is closed".into());
+        }
+        appeal.vote(stalwarts, user.id, vote)?;
+        self.set_report(&domain, id, report.clone())?;
+        reports::finalize_appeal(self, &report, user_id, subject)
+    }
+
+    pub fn vote_on_poll(
+        &mut self,
+        principal: Principal,
+        time: u64,
+        post_id: PostId,
+        vote: u16,
+    ) -> Result<(), String> {
+        let user = self
+            .principal_to_user(principal)
+            .ok_or_else(|| "no user found".to_string())?;
+        let (user_id, user_realms) = (user.id, user.realms.clone());
+        Post::mutate(self, &post_id, |post| {
+            post.watchers.insert(user_id);
+            post.vote_on_poll(user_id, user_realms.clone(), time, vote)
+        })
+    }
+
+    pub fn report(
+        &mut self,
+        principal: Principal,
+        domain: String,
+        id: u64,
+        reason: String,
+        category: ReportCategory,
+    ) -> Result<(), String> {
+        if reason.len() > 1000 {
+            return Err("reason too long".into());
+        }
+        if category == ReportCategory::OffTopic && domain != "post" {
+            return Err("only posts can be reported as off-topic".into());
+        }
+        let cycles_required = category.penalty(&domain) / 2;
+        let user = match self.principal_to_user(principal) {
+            Some(user) if user.cycles() >= cycles_required => user.into_owned(),
+            _ => {
+                return Err(format!(
+                    "You need at least {} cycles for this report",
+                    cycles_required
+                ))
             }
         };
         let report = Some(Report {
             reporter: user.id,
             reason,
+            category,
+            timestamp: time(),
             ..Default::default()
         });
 
@@ -2115,9 +2648,13 @@ impl State {
                     if post.report.is_some() {
                         return Err("this post is already reported".into());
                     }
+                    if category == ReportCategory::OffTopic && post.realm.is_none() {
+                        return Err("only posts in realms can be reported as off-topic".into());
+                    }
                     post.report = report.clone();
                     Ok(post.user)
                 })?;
+                self.open_post_reports.insert(id);
                 let author_name = self
                     .users
                     .get(&post_user)
@@ -2212,11 +2749,11 @@ impl State {
             .expect("no user found")
             .change_karma(-karma_penalty, format!("deletion of post {}", post.id));
 
-        self.hot.retain(|id| id != &post_id);
+        self.hot_ranking.remove(&post_id);
 
         match &post.extension {
             Some(Extension::Proposal(proposal_id)) => {
-                if let Some(proposal) = self.proposals.iter_mut().find(|p| &p.id == proposal_id) {
+                if let Some(proposal) = self.proposals.get_mut(proposal_id) {
                     proposal.status = Status::Cancelled
                 }
             }
@@ -2243,17 +2780,30 @@ impl State {
             Some((_, delta)) => *delta,
             _ => return Err("unknown reaction".into()),
         };
-        let user = self
-            .principal_to_user(principal)
-            .ok_or("no user for principal found")?
-            .clone();
         let post = Post::get(self, &post_id).ok_or("post not found")?.clone();
+        let cost = delta.unsigned_abs() + CONFIG.reaction_fee;
+        let user_id = delegates::resolve(
+            self,
+            principal,
+            delegates::Permission::React,
+            post.realm.as_ref(),
+            cost,
+            time,
+        )?;
+        let user = self
+            .users
+            .get(&user_id)
+            .expect("no user found")
+            .into_owned();
         if post.is_deleted() {
             return Err("post deleted".into());
         }
         if post.user == user.id {
             return Err("reactions to own posts are forbidden".into());
         }
+        if self.blocked(post.user, user.id) {
+            return Err("you were blocked by the author".into());
+        }
         if post
             .reactions
             .values()
@@ -2303,20 +2853,72 @@ impl State {
                 log,
                 None,
             )?;
-            post.make_hot(&mut self.hot, self.users.len(), user.id);
         }
 
-        self.principal_to_user_mut(principal)
-            .expect("no user for principal found")
+        self.users
+            .get_mut(&user_id)
+            .expect("no user found")
             .last_activity = time;
-        let user_id = user.id;
-        Post::mutate(self, &post_id, |post| {
+        delegates::record(
+            self,
+            user_id,
+            principal,
+            cost,
+            format!("reaction to post {}", post_id),
+            time,
+        );
+        let users_len = self.users.len();
+        let mut hot_ranking = std::mem::take(&mut self.hot_ranking);
+        let result = Post::mutate(self, &post_id, |post| {
             post.reactions.entry(reaction).or_default().insert(user_id);
+            hot_ranking.update(post, users_len);
             Ok(())
-        })
+        });
+        self.hot_ranking = hot_ranking;
+        result
+    }
+
+    /// Blocks or unblocks the given user. Blocked users can't comment in the blocker's threads,
+    /// react to or tip their posts, mention them or follow them.
+    pub fn toggle_block(&mut self, principal: Principal, user_id: UserId) -> Result<bool, String> {
+        let blocker = self.principal_to_user(principal).ok_or("no user found")?;
+        let blocker_id = blocker.id;
+        if blocker_id == user_id {
+            return Err("you can't block yourself".into());
+        }
+        let unblock = blocker.blocked.contains(&user_id);
+        if !unblock && !self.users.contains_key(&user_id) {
+            return Err("no user found".into());
+        }
+        let blocker = self
+            .principal_to_user_mut(principal)
+            .expect("no user found");
+        if unblock {
+            blocker.blocked.remove(&user_id);
+            return Ok(false);
+        }
+        blocker.blocked.insert(user_id);
+        blocker.followers.remove(&user_id);
+        if let Some(user) = self.users.get_mut(&user_id) {
+            user.followees.remove(&blocker_id);
+        }
+        Ok(true)
+    }
+
+    /// Returns true if the user `user_id` was blocked by the user `blocker_id`.
+    pub fn blocked(&self, blocker_id: UserId, user_id: UserId) -> bool {
+        self.users
+            .get(&blocker_id)
+            .map(|user| user.blocked.contains(&user_id))
+            .unwrap_or_default()
     }
 
     pub fn toggle_following_user(&mut self, principal: Principal, followee_id: UserId) -> bool {
+        if let Some(user) = self.principal_to_user(principal) {
+            if !user.followees.contains(&followee_id) && self.blocked(followee_id, user.id) {
+                return false;
+            }
+        }
         let (added, (id, name)) = {
             let user = match self.principal_to_user_mut(principal) {
                 Some(user) => user,
@@ -2344,6 +2946,28 @@ impl State {
     }
 }
 
+// Parses an ICP amount with up to 8 decimals.
+fn parse_icp(amount: &str) -> Result<Tokens, String> {
+    let parse = |s: &str| {
+        s.parse::<u64>()
+            .map_err(|err| format!("Couldn't parse as u64: {:?}", err))
+    };
+    match &amount.split('.').collect::<Vec<_>>().as_slice() {
+        [icpts] => Ok(Tokens::from_e8s(parse(icpts)? * 10_u64.pow(8))),
+        [icpts, e8s] => {
+            let mut e8s = e8s.to_string();
+            while e8s.len() < 8 {
+                e8s.push('0');
+            }
+            let e8s = &e8s[..8];
+            Ok(Tokens::from_e8s(
+                parse(icpts)? * 10_u64.pow(8) + parse(e8s)?,
+            ))
+        }
+        _ => Err(format!("Can't parse amount {}", amount)),
+    }
+}
+
 // Checks if any feed represents the superset for the given tag set.
 // The `strict` option requires the sets to be equal.
 fn covered_by_feeds(
@@ -2465,7 +3089,7 @@ pub(crate) mod tests {
             create_user_with_params(state, pr(0), "peter", false, 10000)
         });
 
-        let user = read(|state| state.users.get(&id).unwrap().clone());
+        let user = read(|state| state.users.get(&id).unwrap().into_owned());
         assert_eq!(user.name, "peter".to_string());
         assert!(user.previous_names.is_empty());
 
@@ -2489,7 +3113,7 @@ pub(crate) mod tests {
         )
         .is_ok());
 
-        let user = read(|state| state.users.get(&id).unwrap().clone());
+        let user = read(|state| state.users.get(&id).unwrap().into_owned());
         assert_eq!(user.name, "john".to_string());
         assert_eq!(user.previous_names.as_slice(), &["peter"]);
 
@@ -2793,7 +3417,7 @@ pub(crate) mod tests {
             val => panic!("unexpected outcome: {:?}", val),
         };
 
-        mutate(|state| state.proposals.get_mut(0).unwrap().status = Status::Executed);
+        mutate(|state| state.proposals.get_mut(&0).unwrap().status = Status::Executed);
 
         assert!(State::change_principal(pr(1), new_principal_str.clone())
             .await
@@ -3419,6 +4043,70 @@ pub(crate) mod tests {
         });
     }
 
+    #[test]
+    fn test_realm_transfer() {
+        STATE.with(|cell| {
+            cell.replace(Default::default());
+            let state = &mut *cell.borrow_mut();
+            let u0 = create_user_with_params(state, pr(0), "user0", true, 2000);
+            let u1 = create_user(state, pr(1));
+            let name = "REALM".to_string();
+            state
+                .create_realm(
+                    pr(0),
+                    name.clone(),
+                    Default::default(),
+                    Default::default(),
+                    Default::default(),
+                    Default::default(),
+                    vec![u0].into_iter().collect(),
+                )
+                .unwrap();
+
+            assert_eq!(
+                state.transfer_realm(pr(1), name.clone(), u1),
+                Err("not authorized".to_string())
+            );
+            assert_eq!(
+                state.accept_realm_transfer(pr(1), name.clone()),
+                Err("no pending transfer".to_string())
+            );
+            assert_eq!(state.transfer_realm(pr(0), name.clone(), u1), Ok(()));
+            assert_eq!(
+                state.accept_realm_transfer(pr(0), name.clone()),
+                Err("no pending transfer".to_string())
+            );
+            assert_eq!(state.accept_realm_transfer(pr(1), name.clone()), Ok(()));
+
+            let realm = state.realms.get(&name).unwrap();
+            assert_eq!(realm.controllers, vec![u1].into_iter().collect());
+            assert_eq!(realm.pending_transfer, None);
+            assert_eq!(realm.num_members, 1);
+            assert!(state.users.get(&u1).unwrap().realms.contains(&name));
+
+            // archived realms are read-only
+            state.realms.get_mut(&name).unwrap().archived = true;
+            assert_eq!(
+                Post::create(
+                    state,
+                    "Realm post".to_string(),
+                    &[],
+                    pr(1),
+                    0,
+                    None,
+                    Some(name.clone()),
+                    None,
+                ),
+                Err("realm REALM is archived".to_string())
+            );
+            assert!(!state.toggle_realm_membership(pr(0), name.clone()));
+            assert_eq!(
+                state.transfer_realm(pr(1), name.clone(), u0),
+                Err("realm is archived".to_string())
+            );
+        })
+    }
+
     #[test]
     fn test_covered_by_feed() {
         let m = |v: Vec<&str>| v.into_iter().map(|v| v.to_string()).collect();
@@ -3558,6 +4246,350 @@ pub(crate) mod tests {
         });
     }
 
+    #[test]
+    fn test_mutes() {
+        STATE.with(|cell| {
+            cell.replace(Default::default());
+            let state = &mut *cell.borrow_mut();
+            let author = create_user(state, pr(0));
+            let viewer = create_user(state, pr(1));
+            create_user(state, pr(2));
+            assert!(state.toggle_following_user(pr(1), author));
+            let now = time();
+
+            let post_id = Post::create(
+                state,
+                "Hello world #abc".to_string(),
+                &[],
+                pr(0),
+                now,
+                None,
+                None,
+                None,
+            )
+            .unwrap();
+            let visible = |state: &State| {
+                let user = state.users.get(&viewer).unwrap();
+                let in_feed = user
+                    .personal_feed(state, 0, true)
+                    .any(|post| post.id == post_id);
+                let in_last_posts = state
+                    .last_posts(pr(1), None, true)
+                    .any(|post| post.id == post_id);
+                let in_search = state
+                    .search(pr(1), "world".into(), true)
+                    .iter()
+                    .any(|result| result.id == post_id);
+                assert_eq!(in_feed, in_last_posts);
+                assert_eq!(in_feed, in_search);
+                in_feed
+            };
+            assert!(visible(state));
+            // the unfiltered search ignores mutes
+            let found = |state: &State| {
+                state
+                    .search(pr(1), "world".into(), false)
+                    .iter()
+                    .any(|result| result.id == post_id)
+            };
+
+            // temporary and permanent mutes of tags and users
+            assert!(state
+                .mute(pr(1), "tag".into(), "ABC".into(), Some(24), now)
+                .is_ok());
+            assert!(!visible(state));
+            assert!(state.unmute(pr(1), "tag".into(), "abc".into()).is_ok());
+            assert_eq!(
+                state.unmute(pr(1), "tag".into(), "abc".into()),
+                Err("no mute found".into())
+            );
+            assert!(visible(state));
+            assert!(state
+                .mute(pr(1), "user".into(), author.to_string(), None, now)
+                .is_ok());
+            assert!(!visible(state));
+            assert!(found(state));
+            assert!(state.users.get(&author).unwrap().muted_by.contains(&viewer));
+            assert_eq!(
+                state.mute(pr(1), "user".into(), viewer.to_string(), None, now),
+                Err("you can't mute yourself".into())
+            );
+
+            // expired mutes are ignored and cleaned up
+            assert!(state
+                .mute(
+                    pr(1),
+                    "user".into(),
+                    author.to_string(),
+                    Some(1),
+                    now - 2 * HOUR
+                )
+                .is_ok());
+            assert!(visible(state));
+            state.clean_up(now);
+            assert!(state.users.get(&viewer).unwrap().mutes.is_empty());
+            assert!(state.users.get(&author).unwrap().muted_by.is_empty());
+
+            // muting a thread via one of its comments mutes the whole thread and its notifications
+            let comment_id = Post::create(
+                state,
+                "Reply".to_string(),
+                &[],
+                pr(1),
+                now,
+                Some(post_id),
+                None,
+                None,
+            )
+            .unwrap();
+            assert!(state
+                .mute(pr(1), "thread".into(), comment_id.to_string(), None, now)
+                .is_ok());
+            assert!(state
+                .users
+                .get(&viewer)
+                .unwrap()
+                .mutes
+                .threads
+                .contains_key(&post_id));
+            assert!(!visible(state));
+            let watched = format!("watched_{}", post_id);
+            Post::create(
+                state,
+                "Another reply".to_string(),
+                &[],
+                pr(2),
+                now,
+                Some(post_id),
+                None,
+                None,
+            )
+            .unwrap();
+            assert!(!state
+                .users
+                .get(&viewer)
+                .unwrap()
+                .inbox
+                .contains_key(&watched));
+
+            assert!(state
+                .unmute(pr(1), "thread".into(), post_id.to_string())
+                .is_ok());
+            Post::create(
+                state,
+                "One more reply".to_string(),
+                &[],
+                pr(2),
+                now,
+                Some(post_id),
+                None,
+                None,
+            )
+            .unwrap();
+            assert!(state
+                .users
+                .get(&viewer)
+                .unwrap()
+                .inbox
+                .contains_key(&watched));
+        })
+    }
+
+    #[test]
+    fn test_blocking() {
+        STATE.with(|cell| {
+            cell.replace(Default::default());
+            let state = &mut *cell.borrow_mut();
+            let blocker = create_user(state, pr(0));
+            let blocked = create_user(state, pr(1));
+            let blocked_name = state.users.get(&blocked).unwrap().name.clone();
+            let blocker_name = state.users.get(&blocker).unwrap().name.clone();
+            assert!(state.toggle_following_user(pr(1), blocker));
+            let post_id =
+                Post::create(state, "Hello".to_string(), &[], pr(0), 0, None, None, None).unwrap();
+            let comment_id = Post::create(
+                state,
+                "Reply".to_string(),
+                &[],
+                pr(1),
+                0,
+                Some(post_id),
+                None,
+                None,
+            )
+            .unwrap();
+
+            assert_eq!(
+                state.toggle_block(pr(0), blocker),
+                Err("you can't block yourself".into())
+            );
+            assert_eq!(state.toggle_block(pr(0), 666), Err("no user found".into()));
+            assert!(state.users.get(&blocker).unwrap().blocked.is_empty());
+            assert_eq!(state.toggle_block(pr(0), blocked), Ok(true));
+            assert!(!state
+                .users
+                .get(&blocker)
+                .unwrap()
+                .followers
+                .contains(&blocked));
+            assert!(!state
+                .users
+                .get(&blocked)
+                .unwrap()
+                .followees
+                .contains(&blocker));
+            assert!(!state.toggle_following_user(pr(1), blocker));
+
+            // no comments anywhere in the thread
+            for parent in &[post_id, comment_id] {
+                assert_eq!(
+                    Post::create(
+                        state,
+                        "Reply".to_string(),
+                        &[],
+                        pr(1),
+                        0,
+                        Some(*parent),
+                        None,
+                        None
+                    ),
+                    Err("you were blocked by the author".into())
+                );
+            }
+            // no reactions
+            assert_eq!(
+                state.react(pr(1), post_id, 10, 0),
+                Err("you were blocked by the author".into())
+            );
+            // mentions don't notify
+            let inbox_size = state.users.get(&blocker).unwrap().inbox.len();
+            Post::create(
+                state,
+                format!("Hello @{}", blocker_name),
+                &[],
+                pr(1),
+                0,
+                None,
+                None,
+                None,
+            )
+            .unwrap();
+            assert_eq!(state.users.get(&blocker).unwrap().inbox.len(), inbox_size);
+
+            // the blocker can still interact with the blocked user
+            assert!(Post::create(
+                state,
+                format!("Hello @{}", blocked_name),
+                &[],
+                pr(0),
+                0,
+                Some(comment_id),
+                None,
+                None
+            )
+            .is_ok());
+
+            // unblocking restores the interactions
+            assert_eq!(state.toggle_block(pr(0), blocked), Ok(false));
+            assert!(state.react(pr(1), post_id, 10, 0).is_ok());
+            assert!(state.toggle_following_user(pr(1), blocker));
+        })
+    }
+
+    #[test]
+    fn test_collect_bookmark() {
+        STATE.with(|cell| {
+            cell.replace(Default::default());
+            let state = &mut *cell.borrow_mut();
+            let id = create_user(state, pr(0));
+            let post_id =
+                Post::create(state, "Hello".to_string(), &[], pr(0), 0, None, None, None).unwrap();
+            let user = state.users.get_mut(&id).unwrap();
+            assert!(user.create_bookmark_collection("list".into()).is_ok());
+            assert_eq!(
+                state.collect_bookmark(pr(0), "list", post_id + 1, String::new()),
+                Err("no post found".into())
+            );
+            assert!(state
+                .collect_bookmark(pr(0), "list", post_id, String::new())
+                .is_ok());
+            assert_eq!(
+                state.collect_bookmark(pr(1), "list", post_id, String::new()),
+                Err("no user found".into())
+            );
+        })
+    }
+
+    #[test]
+    fn test_for_you_feed() {
+        STATE.with(|cell| {
+            cell.replace(Default::default());
+            let state = &mut *cell.borrow_mut();
+            let viewer = create_user(state, pr(0));
+            let followee = create_user(state, pr(1));
+            let friend_of_friend = create_user(state, pr(2));
+            create_user(state, pr(3));
+            assert!(state.toggle_following_user(pr(0), followee));
+            assert!(state.toggle_following_user(pr(1), friend_of_friend));
+
+            let mut post_ids = Vec::new();
+            for i in 1..=3 {
+                post_ids.push(
+                    Post::create(state, "Hello".to_string(), &[], pr(i), 0, None, None, None)
+                        .unwrap(),
+                );
+            }
+            let feed = |state: &State| {
+                state
+                    .users
+                    .get(&viewer)
+                    .unwrap()
+                    .for_you_feed(state, 0)
+                    .iter()
+                    .map(|post| post.id)
+                    .collect::<Vec<_>>()
+            };
+
+            // posts of strangers are not included, followees rank higher
+            assert_eq!(feed(state), vec![post_ids[0], post_ids[1]]);
+
+            // bookmarks and reactions boost the relevance of an author
+            let user = state.users.get_mut(&viewer).unwrap();
+            assert!(user.toggle_bookmark(post_ids[1]));
+            state.react(pr(0), post_ids[1], 100, 0).unwrap();
+            assert_eq!(feed(state), vec![post_ids[1], post_ids[0]]);
+            assert!(state
+                .users
+                .get(&viewer)
+                .unwrap()
+                .for_you_feed(state, 1)
+                .is_empty());
+
+            // posts of followees are included even if they are older than the global window,
+            // posts of followees of followees only from the global window
+            state
+                .users
+                .get_mut(&3)
+                .unwrap()
+                .change_cycles(1_000_000, CyclesDelta::Plus, "")
+                .unwrap();
+            for i in 0..CONFIG.for_you_feed_candidates as u64 {
+                Post::create(
+                    state,
+                    "spam".to_string(),
+                    &[],
+                    pr(3),
+                    i * HOUR,
+                    None,
+                    None,
+                    None,
+                )
+                .unwrap();
+            }
+            assert_eq!(feed(state), vec![post_ids[0]]);
+        })
+    }
+
     #[test]
     fn test_personal_feed() {
         STATE.with(|cell| cell.replace(Default::default()));
@@ -3941,6 +4973,54 @@ pub(crate) mod tests {
         })
     }
 
+    #[test]
+    fn test_drafts() {
+        STATE.with(|cell| {
+            cell.replace(Default::default());
+            let state = &mut *cell.borrow_mut();
+            let id = create_user(state, pr(0));
+            let draft = |size, timestamp| Draft {
+                body: Default::default(),
+                realm: None,
+                extension: None,
+                blobs: vec![("a".into(), ByteBuf::from(vec![0; size]))],
+                timestamp,
+            };
+            let half = CONFIG.max_draft_size_bytes / 2;
+            state.users.get_mut(&id).unwrap().draft = Some(draft(half, 2 * HOUR));
+
+            assert_eq!(state.check_draft_growth(pr(0), half), Ok(()));
+            assert_eq!(
+                state.check_draft_growth(pr(0), half + 1),
+                Err("post attachments are too large".into())
+            );
+
+            // drafts of other users count towards the global limit
+            for i in 1..=(CONFIG.max_drafts_bytes / CONFIG.max_draft_size_bytes) {
+                let id = create_user(state, pr(i as u8));
+                state.users.get_mut(&id).unwrap().draft =
+                    Some(draft(CONFIG.max_draft_size_bytes, HOUR));
+            }
+            assert_eq!(
+                state.check_draft_growth(pr(0), 1),
+                Err("too many uploads in progress, please retry later".into())
+            );
+
+            // only the stale drafts expire
+            state.expire_drafts((CONFIG.draft_expiration_hours + 1) * HOUR + 1);
+            assert!(state.users.get(&id).unwrap().draft.is_some());
+            assert_eq!(
+                state
+                    .users
+                    .values()
+                    .filter(|user| user.draft.is_some())
+                    .count(),
+                1
+            );
+            assert_eq!(state.check_draft_growth(pr(0), 1), Ok(()));
+        })
+    }
+
     #[test]
     fn test_stalwarts() {
         STATE.with(|cell| {
@@ -3986,6 +5066,48 @@ pub(crate) mod tests {
                 .unwrap()
                 .controllers
                 .is_empty());
+
+            // stalwarts ignoring reports get demoted
+            let id = state.users.values().find(|u| u.stalwart).unwrap().id;
+            state
+                .users
+                .get_mut(&id)
+                .unwrap()
+                .moderation
+                .consecutive_abstentions = CONFIG.stalwart_max_consecutive_abstentions;
+            state.recompute_stalwarts(now + WEEK * 2);
+            assert!(!state.users.get(&id).unwrap().stalwart);
+            assert!(!state
+                .realms
+                .get(CONFIG.dao_realm)
+                .unwrap()
+                .controllers
+                .contains(&id));
+
+            // stalwarts with a poor accuracy get demoted until their votes leave the window
+            let id = state
+                .users
+                .values()
+                .filter(|u| u.stalwart)
+                .max_by_key(|u| u.karma())
+                .unwrap()
+                .id;
+            let moderation = &mut state.users.get_mut(&id).unwrap().moderation;
+            for _ in 0..CONFIG.stalwart_min_resolved_votes {
+                moderation.record_vote(now, false);
+            }
+            state.recompute_stalwarts(now + WEEK * 2);
+            assert!(!state.users.get(&id).unwrap().stalwart);
+            state.recompute_stalwarts(now + WEEK * 3);
+            assert!(!state.users.get(&id).unwrap().stalwart);
+            state.recompute_stalwarts(now + WEEK * (3 + CONFIG.stalwart_moderation_window_weeks));
+            let user = state.users.get(&id).unwrap();
+            assert!(user.stalwart);
+            assert!(user.moderation.recent_votes.is_empty());
+            assert_eq!(
+                user.moderation.resolved_votes,
+                CONFIG.stalwart_min_resolved_votes
+            );
         })
     }
 
@@ -4042,7 +5164,7 @@ pub(crate) mod tests {
             .await
             .is_ok());
 
-        let user = read(|state| state.users.get(&id).unwrap().clone());
+        let user = read(|state| state.users.get(&id).unwrap().into_owned());
         // Make sure didn't pay with own cycles
         assert_eq!(user.cycles(), prev_balance);
         // Make sure Taggr payed for the invite
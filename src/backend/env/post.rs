@@ -1,7 +1,7 @@
 use std::cmp::{Ordering, PartialOrd};
 
 use super::*;
-use super::{storage::Storage, user::UserId};
+use super::{images, storage::Storage, user::UserId};
 use crate::mutate;
 use crate::reports::Report;
 use serde::{Deserialize, Serialize};
@@ -46,6 +46,101 @@ pub enum Extension {
     Repost(PostId),
 }
 
+// Hot posts ranked by a time-decayed score: the binary logarithm of the engagement plus the age of
+// the post in units of `hot_score_half_life_hours`. Doubling the engagement of a post hence has the
+// same effect as being published one half-life later. Because the age component is fixed at the
+// creation, the score only needs to be recomputed when the engagement of a post changes.
+// Besides the global ranking, every realm has its own ranking, so that the hot posts of smaller
+// realms are not pushed out by the global activity. Each ranking holds up to `num_hot_posts`.
+#[derive(Default, Serialize, Deserialize)]
+pub struct HotRanking {
+    scores: BTreeMap<PostId, u64>,
+    #[serde(default)]
+    realms: BTreeMap<PostId, String>,
+    #[serde(skip)]
+    rankings: BTreeMap<Option<String>, BTreeSet<(u64, PostId)>>,
+}
+
+impl HotRanking {
+    pub fn update(&mut self, post: &Post, total_users: usize) {
+        self.remove(&post.id);
+        if let Some(score) = post.hot_score(total_users) {
+            self.scores.insert(post.id, score);
+            if let Some(realm) = &post.realm {
+                self.realms.insert(post.id, realm.clone());
+            }
+            self.insert(score, post.id, post.realm.clone());
+        }
+    }
+
+    pub fn remove(&mut self, post_id: &PostId) {
+        if let Some(score) = self.scores.remove(post_id) {
+            let realm = self.realms.remove(post_id);
+            for key in [None, realm] {
+                if let Some(ranking) = self.rankings.get_mut(&key) {
+                    ranking.remove(&(score, *post_id));
+                }
+            }
+        }
+    }
+
+    // Returns post ids of the realm, or of all realms, from the highest to the lowest score.
+    pub fn iter(&self, realm: Option<String>) -> impl Iterator<Item = &PostId> {
+        self.rankings
+            .get(&realm)
+            .into_iter()
+            .flat_map(|ranking| ranking.iter().rev().map(|(_, id)| id))
+    }
+
+    // Restores the rankings from the scores after the deserialization.
+    pub fn load(&mut self) {
+        self.rankings.clear();
+        let entries = self
+            .scores
+            .iter()
+            .map(|(post_id, score)| (*score, *post_id, self.realms.get(post_id).cloned()))
+            .collect::<Vec<_>>();
+        for (score, post_id, realm) in entries {
+            self.insert(score, post_id, realm);
+        }
+    }
+
+    // Inserts the post into the global ranking and the ranking of its realm and drops posts
+    // which fell out of all rankings.
+    fn insert(&mut self, score: u64, post_id: PostId, realm: Option<String>) {
+        let keys = match realm {
+            Some(realm) => vec![None, Some(realm)],
+            None => vec![None],
+        };
+        for key in &keys {
+            self.rankings
+                .entry(key.clone())
+                .or_default()
+                .insert((score, post_id));
+        }
+        let mut dropped = Vec::new();
+        for key in keys {
+            if let Some(ranking) = self.rankings.get_mut(&key) {
+                while ranking.len() > CONFIG.num_hot_posts {
+                    if let Some(entry) = ranking.pop_first() {
+                        dropped.push(entry);
+                    }
+                }
+            }
+        }
+        for (score, id) in dropped {
+            if !self
+                .rankings
+                .values()
+                .any(|ranking| ranking.contains(&(score, id)))
+            {
+                self.scores.remove(&id);
+                self.realms.remove(&id);
+            }
+        }
+    }
+}
+
 #[derive(Clone, Default, Serialize, Deserialize)]
 pub struct Post {
     pub id: PostId,
@@ -83,6 +178,15 @@ impl PartialOrd for Post {
     }
 }
 
+// Videos are limited to `max_media_size_bytes`, all other blobs to `max_blob_size_bytes`.
+fn max_blob_size(blob: &[u8]) -> usize {
+    if images::is_video(blob) {
+        CONFIG.max_media_size_bytes
+    } else {
+        CONFIG.max_blob_size_bytes
+    }
+}
+
 impl Post {
     pub fn new(
         user: UserId,
@@ -177,47 +281,69 @@ impl Post {
         Ok(())
     }
 
-    pub fn valid(&self, blobs: &[(String, Blob)]) -> Result<(), String> {
+    /// Validates the post and its blobs and returns the files to store: the processed images
+    /// together with their thumbnails and the videos.
+    pub fn valid(&self, blobs: &[(String, Blob)]) -> Result<Vec<(String, Blob)>, String> {
         if self.body.is_empty() || self.body.chars().count() > CONFIG.max_post_length {
             return Err("invalid post content".into());
         }
         if !blobs.iter().all(|(key, blob)| {
-            key.len() <= 8 && blob.len() > 0 && blob.len() <= CONFIG.max_blob_size_bytes
+            key.len() <= 8 && blob.len() > 0 && blob.len() <= max_blob_size(blob)
         }) {
             return Err("invalid blobs".into());
         }
-        Ok(())
+        let mut files = Vec::new();
+        for (id, blob) in blobs {
+            if images::is_video(blob) {
+                files.push((id.clone(), blob.clone()));
+                continue;
+            }
+            let (image, thumbnail) = images::process(blob)?;
+            files.push((id.clone(), image));
+            files.push((id.clone() + images::THUMBNAIL_SUFFIX, thumbnail));
+        }
+        Ok(files)
     }
 
-    pub async fn save_blobs(post_id: PostId, blobs: Vec<(String, Blob)>) -> Result<(), String> {
+    /// Stores the files of the post which are not stored yet.
+    pub async fn save_blobs(post_id: PostId, files: Vec<(String, Blob)>) -> Result<(), String> {
         let existing_blobs = read(|state| {
-            Post::get(state, &post_id)
-                .map(|post| post.files.keys().cloned().collect::<BTreeSet<_>>())
+            Post::get(state, &post_id).map(|post| {
+                post.files
+                    .keys()
+                    .filter_map(|key| key.split('@').next())
+                    .map(String::from)
+                    .collect::<BTreeSet<_>>()
+            })
         })
         .unwrap_or_default();
 
-        for (id, blob) in blobs
+        for (id, blob) in files
             .into_iter()
             .filter(|(id, _)| !existing_blobs.contains(id))
         {
-            match Storage::write_to_bucket(blob.as_slice()).await {
-                Ok((bucket_id, offset)) => mutate(|state| {
-                    Post::mutate(state, &post_id, |post| {
-                        post.files
-                            .insert(format!("{}@{}", id, bucket_id), (offset, blob.len()));
-                        Ok(())
-                    })
-                }),
-                Err(err) => {
-                    let msg = format!("Couldn't write a blob to bucket: {:?}", err);
-                    mutate(|state| state.logger.error(&msg));
-                    Err(err)
-                }
-            }?
+            Post::save_blob(post_id, id, &blob).await?;
         }
         Ok(())
     }
 
+    async fn save_blob(post_id: PostId, id: String, blob: &[u8]) -> Result<(), String> {
+        match Storage::store_blob(blob).await {
+            Ok((bucket_id, offset)) => mutate(|state| {
+                Post::mutate(state, &post_id, |post| {
+                    post.files
+                        .insert(format!("{}@{}", id, bucket_id), (offset, blob.len()));
+                    Ok(())
+                })
+            }),
+            Err(err) => {
+                let msg = format!("Couldn't write a blob to bucket: {:?}", err);
+                mutate(|state| state.logger.error(&msg));
+                Err(err)
+            }
+        }
+    }
+
     pub fn vote_on_report(
         &mut self,
         stalwarts: usize,
@@ -267,11 +393,11 @@ impl Post {
             }
     }
 
-    pub fn make_hot(&self, hot_list: &mut VecDeque<PostId>, total_users: usize, user_id: UserId) {
-        // if it's a comment or reaction is from the users itself, exit
-        if self.parent.is_some() || self.user == user_id {
-            return;
-        };
+    // Returns the hot score of a root post or `None` if the post isn't hot.
+    pub fn hot_score(&self, total_users: usize) -> Option<u64> {
+        if self.parent.is_some() {
+            return None;
+        }
         let engagements = self
             .reactions
             .iter()
@@ -282,27 +408,29 @@ impl Post {
             + self.tree_size;
 
         if engagements as f32 / (total_users as f32) < CONFIG.hot_post_engagement_percentage {
-            return;
+            return None;
         }
         // negative reactions balance
         let karma = reaction_karma();
-        if self
+        let reactions = self
             .reactions
             .iter()
             .map(|(r_id, users)| {
                 karma.get(r_id).copied().unwrap_or_default() * users.len() as Karma
             })
-            .sum::<i64>()
-            < 0
-        {
-            return;
+            .sum::<i64>();
+        if reactions < 0 {
+            return None;
         }
 
-        hot_list.retain(|post_id| *post_id != self.id);
-        hot_list.push_front(self.id);
-        if hot_list.len() > CONFIG.num_hot_posts {
-            hot_list.pop_back();
+        let engagement = reactions
+            + self.tree_size as Karma * CONFIG.hot_score_comment_weight
+            + self.tips.len() as Karma * CONFIG.hot_score_tip_weight;
+        if engagement <= 0 {
+            return None;
         }
+        let age = self.timestamp() as f64 / (CONFIG.hot_score_half_life_hours * HOUR) as f64;
+        Some((((engagement as f64).log2() + age) * 1000.0) as u64)
     }
 
     /// Checks if the poll has ended. If not, returns `Ok(false)`. If the poll ended,
@@ -314,7 +442,7 @@ impl Post {
                 if let Some(Extension::Poll(poll)) = post.extension.as_ref() {
                     let user_ids = poll.votes.values().flatten().cloned();
                     let users = user_ids
-                        .filter_map(|id| state.users.get(&id).map(|user| (id, user.clone())))
+                        .filter_map(|id| state.users.get(&id).map(|user| (id, user.into_owned())))
                         .collect::<BTreeMap<_, _>>();
                     Some((
                         users
@@ -390,7 +518,7 @@ impl Post {
         principal: Principal,
         timestamp: u64,
     ) -> Result<(), String> {
-        mutate(|state| {
+        let files = mutate(|state| {
             let user = state
                 .principal_to_user(principal)
                 .ok_or("no user found")?
@@ -402,20 +530,27 @@ impl Post {
             if let Some(false) = picked_realm.as_ref().map(|name| user.realms.contains(name)) {
                 return Err("you're not in the realm".into());
             }
+            if [&post.realm, &picked_realm]
+                .iter()
+                .filter_map(|name| name.as_ref().and_then(|name| state.realms.get(name)))
+                .any(|realm| realm.archived)
+            {
+                return Err("realm is archived".into());
+            }
             let user_id = user.id;
             post.tags = tags(CONFIG.max_tag_length, &body);
             post.body = body;
-            post.valid(&blobs)?;
             let old_blob_ids = post
                 .files
                 .keys()
                 .filter_map(|key| key.split('@').next())
                 .collect::<BTreeSet<_>>();
             let new_blobs = blobs
-                .iter()
+                .into_iter()
                 .filter(|(id, _)| !old_blob_ids.contains(id.as_str()))
-                .count();
-            let costs = post.costs(new_blobs);
+                .collect::<Vec<_>>();
+            let files = post.valid(&new_blobs)?;
+            let costs = post.costs(new_blobs.len());
             state.charge(user_id, costs, format!("editing of post {}", id))?;
             post.patches.push((post.timestamp, patch));
             post.timestamp = timestamp;
@@ -430,10 +565,10 @@ impl Post {
             if current_realm != picked_realm {
                 change_realm(state, id, picked_realm)
             }
-            Ok(())
+            Ok(files)
         })?;
 
-        Post::save_blobs(id, blobs).await?;
+        Post::save_blobs(id, files).await?;
 
         Ok(())
     }
@@ -449,17 +584,44 @@ impl Post {
         picked_realm: Option<String>,
         extension: Option<Extension>,
     ) -> Result<PostId, String> {
+        Post::create_post(
+            state,
+            body,
+            blobs,
+            principal,
+            timestamp,
+            parent,
+            picked_realm,
+            extension,
+            true,
+        )
+        .map(|(id, _)| id)
+    }
+
+    /// Creates a post, optionally skipping the spam check for posts approved by stalwarts.
+    /// Returns the id of the post and the files of its blobs, which still need to be saved.
+    #[allow(clippy::too_many_arguments)]
+    pub fn create_post(
+        state: &mut State,
+        body: String,
+        blobs: &[(String, Blob)],
+        principal: Principal,
+        timestamp: u64,
+        parent: Option<PostId>,
+        picked_realm: Option<String>,
+        extension: Option<Extension>,
+        spam_check: bool,
+    ) -> Result<(PostId, Vec<(String, Blob)>), String> {
         let user = match state.principal_to_user(principal) {
             Some(user) => user,
             // look for an authorized controller
             None => {
                 let controller_id = principal.to_string();
-                match state
-                    .users
-                    .values()
-                    .find(|u| u.controllers.contains(&controller_id))
-                {
-                    Some(user) => user,
+                match state.users.values().find(|u| {
+                    u.controllers.contains(&controller_id)
+                        || u.delegates.contains_key(&controller_id)
+                }) {
+                    Some(user) => Cow::Borrowed(user),
                     None => return Err(format!("no user with controller {} found", controller_id)),
                 }
             }
@@ -505,6 +667,21 @@ impl Post {
             if !user.realms.contains(name) {
                 return Err(format!("not a member of the realm {}", name));
             }
+            if state.realms.get(name).map(|realm| realm.archived) == Some(true) {
+                return Err(format!("realm {} is archived", name));
+            }
+        }
+        if let Some(parent_id) = parent {
+            // authors of the thread and of the parent post can block comments
+            if state
+                .thread(parent_id)
+                .take(1)
+                .chain(Some(parent_id))
+                .filter_map(|id| Post::get(state, &id))
+                .any(|post| state.blocked(post.user, user.id))
+            {
+                return Err("you were blocked by the author".into());
+            }
         }
         let user_id = user.id;
         let mut post = Post::new(
@@ -517,7 +694,55 @@ impl Post {
             realm.clone(),
         );
         let costs = post.costs(blobs.len());
-        post.valid(blobs)?;
+        let files = post.valid(blobs)?;
+        if let Some(delegate) = user.delegates.get(&principal.to_string()) {
+            let permission = if parent.is_none() {
+                delegates::Permission::Post
+            } else {
+                delegates::Permission::Comment
+            };
+            delegate.authorize(permission, realm.as_ref(), costs, timestamp)?;
+        }
+        if spam_check && !matches!(post.extension, Some(Extension::Proposal(_))) {
+            let score = spam::score(state, &user, &post.body, timestamp);
+            if score >= CONFIG.spam_rejection_score {
+                return Err("post rejected by the spam filter".into());
+            }
+            if score >= CONFIG.spam_review_score {
+                if state.held_posts.contains_key(&user_id) {
+                    return Err("you already have a post waiting for review".into());
+                }
+                spam::check_held_blobs(state, blobs)?;
+                let user_name = user.name.clone();
+                state.held_posts.insert(
+                    user_id,
+                    spam::HeldPost {
+                        user: user_id,
+                        principal,
+                        body: post.body,
+                        blobs: blobs.to_vec(),
+                        parent,
+                        realm: post.realm,
+                        extension: post.extension,
+                        score,
+                        timestamp,
+                    },
+                );
+                state.logger.info(format!(
+                    "@{}'s post was held for a review by stalwarts (spam score `{}`).",
+                    user_name, score
+                ));
+                state.notify_with_predicate(
+                    &|u| u.stalwart && u.id != user_id,
+                    format!(
+                        "A post of @{} was held by the spam filter and waits for a [review](#/moderation).",
+                        user_name
+                    ),
+                    Predicate::HeldPost(user_id),
+                );
+                return Err("post was held for a review by stalwarts".into());
+            }
+        }
         let trusted_user = user.trusted();
         let future_id = state.next_post_id;
         state.charge(user_id, costs, format!("new post {}", future_id))?;
@@ -531,6 +756,14 @@ impl Post {
         user.last_activity = timestamp;
         let id = state.new_post_id();
         post.id = id;
+        delegates::record(
+            state,
+            user_id,
+            principal,
+            costs,
+            format!("post {}", id),
+            timestamp,
+        );
         if let Some(realm) = realm.and_then(|name| state.realms.get_mut(&name)) {
             realm.num_posts += 1;
             realm.last_update = timestamp;
@@ -566,7 +799,7 @@ impl Post {
         Post::save(state, post);
 
         let users_len = state.users.len();
-        let mut hot_posts = std::mem::take(&mut state.hot);
+        let mut hot_ranking = std::mem::take(&mut state.hot_ranking);
         state
             .thread(id)
             .filter(|post_id| post_id != &id)
@@ -574,14 +807,14 @@ impl Post {
                 Post::mutate(state, &id, |post| {
                     post.tree_size += 1;
                     post.tree_update = timestamp;
-                    post.make_hot(&mut hot_posts, users_len, user_id);
+                    hot_ranking.update(post, users_len);
                     Ok(())
                 })
             })
             .expect("couldn't adjust post on the thread");
 
-        state.hot = hot_posts;
-        Ok(id)
+        state.hot_ranking = hot_ranking;
+        Ok((id, files))
     }
 
     pub fn count(state: &State) -> usize {
@@ -607,6 +840,7 @@ impl Post {
     // Takes the post from cold or hot memory
     fn take(state: &mut State, post_id: &PostId) -> Post {
         cache().remove(post_id);
+        state.memory.mark_dirty_post(*post_id);
         state
             .posts
             .remove(post_id)
@@ -621,12 +855,19 @@ impl Post {
         F: FnOnce(&mut Post) -> Result<T, String>,
     {
         let mut post = Post::take(state, post_id);
+        let files = post.files.clone();
         let result = f(&mut post);
+        for (key, location) in files {
+            if !post.files.contains_key(&key) {
+                state.storage.release(&key, location);
+            }
+        }
         Post::save(state, post);
         result
     }
 
     fn save(state: &mut State, post: Post) {
+        state.memory.mark_dirty_post(post.id);
         if state.posts.insert(post.id, post).is_some() {
             panic!("no post should exist")
         }
@@ -657,6 +898,7 @@ pub fn archive_cold_posts(state: &mut State, max_posts_in_heap: usize) -> Result
                 .posts
                 .remove(&post_id)
                 .ok_or(format!("no post found for id={post_id}"))?;
+            state.memory.mark_dirty_post(post_id);
             state.memory.posts.insert(post_id, post)
         })
         .expect("couldn't archive post");
@@ -702,15 +944,41 @@ fn notify_about(state: &mut State, post: &Post) {
         .expect("no user found")
         .name
         .clone();
-    let mut notified: HashSet<_> = HashSet::new();
+    let now = post.timestamp();
+    // Users who muted the author aren't notified.
+    let mut notified: HashSet<_> = state
+        .users
+        .get(&post.user)
+        .expect("no user found")
+        .muted_by
+        .iter()
+        .filter(|id| {
+            state
+                .users
+                .get(id)
+                .map(|user| user.mutes.user_muted(post.user, now))
+                .unwrap_or_default()
+        })
+        .copied()
+        .collect();
     // Don't notify the author
     notified.insert(post.user);
+    // Users who blocked the author or muted one of the tags or the thread aren't notified either.
+    let ignores = |state: &State, user_id: &UserId| {
+        state
+            .users
+            .get(user_id)
+            .map(|user| {
+                user.blocked.contains(&post.user) || user.mutes.content_muted(state, post, now)
+            })
+            .unwrap_or_default()
+    };
     if let Some(parent) = post
         .parent
         .and_then(|parent_id| Post::get(state, &parent_id))
     {
         let parent_author = parent.user;
-        if parent_author != post.user {
+        if !notified.contains(&parent_author) && !ignores(state, &parent_author) {
             if let Some(user) = state.users.get_mut(&parent_author) {
                 user.notify_about_post(
                     format!("@{} replied to your post", post_user_name,),
@@ -724,7 +992,7 @@ fn notify_about(state: &mut State, post: &Post) {
     user_handles(CONFIG.max_tag_length, &post.body)
         .into_iter()
         .filter_map(|handle| state.user(&handle).map(|user| user.id))
-        .filter(|id| !notified.contains(id))
+        .filter(|id| !notified.contains(id) && !ignores(state, id))
         .collect::<Vec<_>>()
         .into_iter()
         .for_each(|mentioned_user_id| {
@@ -752,7 +1020,7 @@ fn notify_about(state: &mut State, post: &Post) {
             .collect::<Vec<_>>()
             .into_iter()
             .for_each(|(post_id, user_id)| {
-                if notified.contains(&user_id) {
+                if notified.contains(&user_id) || ignores(state, &user_id) {
                     return;
                 }
                 if let Some(user) = state.users.get_mut(&user_id) {
@@ -1031,9 +1299,9 @@ mod tests {
             )
             .is_err());
 
-        // valid blob
-        assert!(p
-            .valid(
+        // unsupported blob type
+        assert_eq!(
+            p.valid(
                 vec![(
                     "abcdefgh".to_string(),
                     ByteBuf::from(
@@ -1046,12 +1314,166 @@ mod tests {
                     )
                 )]
                 .as_slice()
+            ),
+            Err("unsupported file type".into())
+        );
+
+        // valid image, stored with a thumbnail
+        let mut png = Vec::new();
+        image::DynamicImage::ImageRgb8(image::ImageBuffer::from_pixel(8, 8, image::Rgb([1, 2, 3])))
+            .write_to(
+                &mut std::io::Cursor::new(&mut png),
+                image::ImageOutputFormat::Png,
             )
-            .is_ok());
+            .unwrap();
+        let files = p
+            .valid(vec![("abcdefgh".to_string(), ByteBuf::from(png))].as_slice())
+            .unwrap();
+        assert_eq!(
+            files.iter().map(|(id, _)| id.as_str()).collect::<Vec<_>>(),
+            vec!["abcdefgh", "abcdefgh.thumb"]
+        );
+
+        // videos larger than images are stored unchanged
+        let video = ByteBuf::from(
+            b"\0\0\0\x18ftypmp42"
+                .iter()
+                .chain([0, 1].iter().cycle())
+                .take(CONFIG.max_blob_size_bytes + 1)
+                .cloned()
+                .collect::<Vec<_>>(),
+        );
+        assert_eq!(
+            p.valid(vec![("abcdefgh".to_string(), video.clone())].as_slice()),
+            Ok(vec![("abcdefgh".to_string(), video)])
+        );
 
         // empty blob
         assert!(p
             .valid(vec![("abcdefgh".to_string(), Default::default())].as_slice())
             .is_err());
     }
+
+    #[test]
+    fn test_hot_ranking() {
+        STATE.with(|cell| {
+            cell.replace(Default::default());
+            let state = &mut *cell.borrow_mut();
+            for i in 0..5 {
+                create_user(state, pr(i));
+            }
+            let half_life = CONFIG.hot_score_half_life_hours * HOUR;
+
+            let old_post =
+                Post::create(state, "old".to_string(), &[], pr(0), 0, None, None, None).unwrap();
+            let new_post = Post::create(
+                state,
+                "new".to_string(),
+                &[],
+                pr(0),
+                2 * half_life,
+                None,
+                None,
+                None,
+            )
+            .unwrap();
+            assert!(state.hot_posts(Principal::anonymous(), None, 0).is_empty());
+
+            for i in 1..=3 {
+                state.react(pr(i), old_post, 100, 0).unwrap();
+            }
+            state.react(pr(1), new_post, 100, 0).unwrap();
+
+            // the new post wins despite the lower engagement
+            let ids = |state: &State| {
+                state
+                    .hot_posts(Principal::anonymous(), None, 0)
+                    .iter()
+                    .map(|post| post.id)
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(ids(state), vec![new_post, old_post]);
+
+            // comments and reactions make the old post hot again
+            Post::create(
+                state,
+                "comment".to_string(),
+                &[],
+                pr(4),
+                0,
+                Some(old_post),
+                None,
+                None,
+            )
+            .unwrap();
+            assert_eq!(ids(state), vec![new_post, old_post]);
+            state.react(pr(4), old_post, 100, 0).unwrap();
+            assert_eq!(ids(state), vec![old_post, new_post]);
+
+            // the ranking is restored from the scores after upgrades
+            state.hot_ranking.load();
+            assert_eq!(ids(state), vec![old_post, new_post]);
+            assert_eq!(state.hot_ranking.iter(None).count(), 2);
+
+            // realms have their own ranking
+            Post::mutate(state, &new_post, |post| {
+                post.realm = Some("REALM".into());
+                Ok(())
+            })
+            .unwrap();
+            let post = Post::get(state, &new_post).unwrap().clone();
+            state.hot_ranking.update(&post, 5);
+            let realm_ids = |state: &State| {
+                state
+                    .hot_posts(Principal::anonymous(), Some("REALM".into()), 0)
+                    .iter()
+                    .map(|post| post.id)
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(realm_ids(state), vec![new_post]);
+            assert_eq!(ids(state), vec![old_post, new_post]);
+            state.hot_ranking.load();
+            assert_eq!(realm_ids(state), vec![new_post]);
+            assert_eq!(ids(state), vec![old_post, new_post]);
+        })
+    }
+
+    #[test]
+    fn test_hot_ranking_overflow() {
+        let mut ranking = HotRanking::default();
+        let n = CONFIG.num_hot_posts as u64;
+        for id in 0..n {
+            ranking.scores.insert(id, 100 + id);
+            ranking.insert(100 + id, id, None);
+        }
+
+        // a realm post with the lowest score falls out of the global ranking only
+        let realm_post = n;
+        ranking.scores.insert(realm_post, 1);
+        ranking.realms.insert(realm_post, "REALM".into());
+        ranking.insert(1, realm_post, Some("REALM".into()));
+        assert_eq!(ranking.iter(None).count(), CONFIG.num_hot_posts);
+        assert!(!ranking.iter(None).any(|id| *id == realm_post));
+        assert_eq!(
+            ranking.iter(Some("REALM".into())).collect::<Vec<_>>(),
+            vec![&realm_post]
+        );
+        assert_eq!(ranking.scores.get(&realm_post), Some(&1));
+
+        // it survives the reload and can be removed
+        ranking.load();
+        assert_eq!(
+            ranking.iter(Some("REALM".into())).collect::<Vec<_>>(),
+            vec![&realm_post]
+        );
+        ranking.remove(&realm_post);
+        assert_eq!(ranking.iter(Some("REALM".into())).count(), 0);
+        assert!(!ranking.realms.contains_key(&realm_post));
+
+        // a post without a realm falling out of the global ranking is dropped entirely
+        ranking.scores.insert(realm_post + 1, 1);
+        ranking.insert(1, realm_post + 1, None);
+        assert!(!ranking.scores.contains_key(&(realm_post + 1)));
+        assert_eq!(ranking.iter(None).count(), CONFIG.num_hot_posts);
+    }
 }
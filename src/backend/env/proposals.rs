@@ -1,3 +1,4 @@
+use super::badges::{self, Attestation, Issuer};
 use super::config::CONFIG;
 use super::post::{Extension, Post, PostId};
 use super::token::account;
@@ -42,6 +43,10 @@ pub enum Payload {
     Release(Release),
     Fund(String, Token),
     Reward(Reward),
+    // Reassigns an abandoned realm to a new controller or archives it if none is given.
+    AbandonedRealm(String, Option<UserId>),
+    Badge(UserId, Attestation),
+    RevokeBadge(UserId, Attestation),
 }
 
 #[derive(Clone, Default, Serialize, Deserialize)]
@@ -167,6 +172,17 @@ impl Proposal {
         }
 
         if approvals * 100 >= voting_power * CONFIG.proposal_approval_threshold as u64 {
+            // the realm controllers could have become active since the proposal was created
+            if let Payload::AbandonedRealm(name, _) = &self.payload {
+                if realm_controllers_active(state, name, time) {
+                    self.status = Status::Rejected;
+                    state.logger.info(format!(
+                        "Proposal `{}` was rejected: the controllers of realm [{1}](/#/realm/{1}) became active.",
+                        self.id, name
+                    ));
+                    return Ok(());
+                }
+            }
             match &mut self.payload {
                 Payload::Fund(receiver, tokens) => mint_tokens(state, receiver, *tokens)?,
                 Payload::Reward(reward) => {
@@ -179,6 +195,19 @@ impl Proposal {
                     reward.votes.clear();
                     reward.minted = tokens_to_mint;
                 }
+                Payload::AbandonedRealm(name, controller) => {
+                    settle_abandoned_realm(state, name, *controller)?
+                }
+                Payload::Badge(user_id, attestation) => badges::grant(
+                    state,
+                    *user_id,
+                    attestation.clone(),
+                    Issuer::Dao(self.id),
+                    time,
+                )?,
+                Payload::RevokeBadge(user_id, attestation) => {
+                    badges::revoke(state, *user_id, attestation)?
+                }
                 _ => {}
             }
             self.status = Status::Executed;
@@ -205,8 +234,52 @@ fn mint_tokens(state: &mut State, receiver: &str, mut tokens: Token) -> Result<(
     Ok(())
 }
 
+fn realm_controllers_active(state: &State, name: &str, time: u64) -> bool {
+    let weeks = CONFIG.realm_abandonment_weeks;
+    state
+        .realms
+        .get(name)
+        .into_iter()
+        .flat_map(|realm| realm.controllers.iter())
+        .any(
+            |id| matches!(state.users.get(id), Some(user) if user.active_within_weeks(time, weeks)),
+        )
+}
+
+fn settle_abandoned_realm(
+    state: &mut State,
+    name: &str,
+    controller: Option<UserId>,
+) -> Result<(), String> {
+    match controller {
+        Some(user_id) => {
+            state.assign_realm_controller(name, user_id)?;
+            let user = state.users.get_mut(&user_id).ok_or("no user found")?;
+            user.notify(format!(
+                "You became the controller of the realm [{0}](/#/realm/{0}) via proposal execution.",
+                name
+            ));
+            let user_name = user.name.clone();
+            state.logger.info(format!(
+                "Realm [{0}](/#/realm/{0}) was reassigned to @{1} via proposal execution.",
+                name, user_name
+            ));
+        }
+        None => {
+            let realm = state.realms.get_mut(name).ok_or("no realm found")?;
+            realm.archived = true;
+            realm.pending_transfer = None;
+            state.logger.info(format!(
+                "Realm [{0}](/#/realm/{0}) was archived via proposal execution.",
+                name
+            ));
+        }
+    }
+    Ok(())
+}
+
 impl Payload {
-    fn validate(&mut self, minting_ratio: u64) -> Result<(), String> {
+    fn validate(&mut self, state: &State, time: u64) -> Result<(), String> {
         match self {
             Payload::Release(release) => {
                 if release.commit.is_empty() {
@@ -222,7 +295,7 @@ impl Payload {
             Payload::Fund(controller, tokens) => {
                 Principal::from_text(controller).map_err(|err| err.to_string())?;
                 let base = 10_u64.pow(CONFIG.token_decimals as u32);
-                let max_funding_amount = CONFIG.max_funding_amount / minting_ratio / base;
+                let max_funding_amount = CONFIG.max_funding_amount / state.minting_ratio() / base;
                 if *tokens / base > max_funding_amount {
                     return Err(format!(
                         "funding amount is higher than the configured maximum of {} tokens",
@@ -230,6 +303,35 @@ impl Payload {
                     ));
                 }
             }
+            Payload::AbandonedRealm(name, controller) => {
+                if name == CONFIG.dao_realm {
+                    return Err("the DAO realm can't be reassigned".into());
+                }
+                let realm = state.realms.get(name).ok_or("no realm found")?;
+                if realm.archived && controller.is_none() {
+                    return Err("realm is already archived".into());
+                }
+                if realm_controllers_active(state, name, time) {
+                    return Err(format!(
+                        "realm controllers were active within the last {} weeks",
+                        CONFIG.realm_abandonment_weeks
+                    ));
+                }
+                if let Some(id) = controller {
+                    state.users.get(id).ok_or("no user found")?;
+                }
+            }
+            Payload::Badge(user_id, attestation) => badges::validate(state, *user_id, attestation)?,
+            Payload::RevokeBadge(user_id, attestation) => {
+                state
+                    .users
+                    .get(user_id)
+                    .ok_or("no user found")?
+                    .badges
+                    .iter()
+                    .find(|badge| &badge.attestation == attestation)
+                    .ok_or("no badge found")?;
+            }
             _ => {}
         }
         Ok(())
@@ -243,7 +345,7 @@ pub fn propose(
     mut payload: Payload,
     time: u64,
 ) -> Result<u32, String> {
-    payload.validate(state.minting_ratio())?;
+    payload.validate(state, time)?;
     let user = state
         .principal_to_user_mut(caller)
         .ok_or("proposer user not found")?;
@@ -261,7 +363,7 @@ pub fn propose(
     // invalidate some previous proposals depending on their type
     state
         .proposals
-        .iter_mut()
+        .values_mut()
         .filter(|p| {
             p.status == Status::Open
                 && matches!(p.payload, Payload::Release(_))
@@ -284,16 +386,19 @@ pub fn propose(
         Some(Extension::Proposal(id)),
     )?;
 
-    state.proposals.push(Proposal {
-        post_id,
-        proposer,
-        timestamp: time,
-        status: Status::Open,
-        payload,
-        bulletins: Vec::default(),
-        voting_power: 0,
+    state.proposals.insert(
         id,
-    });
+        Proposal {
+            post_id,
+            proposer,
+            timestamp: time,
+            status: Status::Open,
+            payload,
+            bulletins: Vec::default(),
+            voting_power: 0,
+            id,
+        },
+    );
     state.notify_with_predicate(
         &|user| user.active_within_weeks(time, 1) && user.balance > 0,
         format!("@{} submitted a new proposal", &proposer_name,),
@@ -316,7 +421,7 @@ pub fn vote_on_proposal(
 ) -> Result<(), String> {
     let mut proposals = std::mem::take(&mut state.proposals);
     let proposal = proposals
-        .get_mut(proposal_id as usize)
+        .get_mut(&proposal_id)
         .ok_or_else(|| "no proposals founds".to_string())?;
     if proposal.status != Status::Open {
         state.proposals = proposals;
@@ -340,7 +445,7 @@ pub fn vote_on_proposal(
 pub fn cancel_proposal(state: &mut State, caller: Principal, proposal_id: u32) {
     let mut proposals = std::mem::take(&mut state.proposals);
     let proposal = proposals
-        .get_mut(proposal_id as usize)
+        .get_mut(&proposal_id)
         .expect("no proposals founds");
     let user = state.principal_to_user(caller).expect("no user found");
     if proposal.status == Status::Open && proposal.proposer == user.id {
@@ -349,6 +454,34 @@ pub fn cancel_proposal(state: &mut State, caller: Principal, proposal_id: u32) {
     state.proposals = proposals;
 }
 
+/// Moves the oldest concluded proposals from the heap to the stable memory until at most
+/// `max_proposals_in_heap` proposals remain in the heap.
+pub fn archive_concluded_proposals(state: &mut State, max_proposals_in_heap: usize) {
+    let excess = state
+        .proposals
+        .len()
+        .saturating_sub(state.proposals.archived() + max_proposals_in_heap);
+    let ids = state
+        .proposals
+        .values()
+        .filter(|proposal| proposal.status != Status::Open)
+        .map(|proposal| proposal.id)
+        .take(excess.min(max_proposals_in_heap))
+        .collect::<Vec<_>>();
+    if ids.is_empty() {
+        return;
+    }
+    for id in &ids {
+        state
+            .proposals
+            .archive(id, None)
+            .expect("couldn't archive proposal");
+    }
+    state
+        .logger
+        .info(format!("`{}` concluded proposals archived.", ids.len()));
+}
+
 pub(super) fn execute_proposal(
     state: &mut State,
     proposal_id: u32,
@@ -356,7 +489,7 @@ pub(super) fn execute_proposal(
 ) -> Result<(), String> {
     let mut proposals = std::mem::take(&mut state.proposals);
     let proposal = proposals
-        .get_mut(proposal_id as usize)
+        .get_mut(&proposal_id)
         .ok_or_else(|| "no proposals founds".to_string())?;
     if proposal.status != Status::Open {
         state.proposals = proposals;
@@ -387,7 +520,7 @@ mod tests {
     use crate::{
         env::{
             tests::{create_user, pr},
-            time, Karma,
+            time, Karma, WEEK,
         },
         STATE,
     };
@@ -427,10 +560,7 @@ mod tests {
             )
             .expect("couldn't create proposal");
 
-            assert_eq!(
-                state.proposals.get(id2 as usize).unwrap().status,
-                Status::Open
-            );
+            assert_eq!(state.proposals.get(&id2).unwrap().status, Status::Open);
 
             let upgrade_id = propose(
                 state,
@@ -454,29 +584,17 @@ mod tests {
             )
             .expect("couldn't create proposal");
 
-            assert_eq!(
-                state.proposals.get(id3 as usize).unwrap().status,
-                Status::Open
-            );
-            assert_eq!(
-                state.proposals.get(id2 as usize).unwrap().status,
-                Status::Open
-            );
+            assert_eq!(state.proposals.get(&id3).unwrap().status, Status::Open);
+            assert_eq!(state.proposals.get(&id2).unwrap().status, Status::Open);
 
             cancel_proposal(state, pr(2), id);
-            assert_eq!(
-                state.proposals.get(id as usize).unwrap().status,
-                Status::Open
-            );
+            assert_eq!(state.proposals.get(&id).unwrap().status, Status::Open);
 
             cancel_proposal(state, pr(1), id);
-            assert_eq!(
-                state.proposals.get(id as usize).unwrap().status,
-                Status::Cancelled
-            );
+            assert_eq!(state.proposals.get(&id).unwrap().status, Status::Cancelled);
 
             assert_eq!(
-                state.proposals.get(upgrade_id as usize).unwrap().status,
+                state.proposals.get(&upgrade_id).unwrap().status,
                 Status::Open
             );
 
@@ -494,11 +612,11 @@ mod tests {
             .expect("couldn't create proposal");
 
             assert_eq!(
-                state.proposals.get(upgrade_id as usize).unwrap().status,
+                state.proposals.get(&upgrade_id).unwrap().status,
                 Status::Cancelled
             );
             assert_eq!(
-                state.proposals.get(upgrade_id2 as usize).unwrap().status,
+                state.proposals.get(&upgrade_id2).unwrap().status,
                 Status::Open
             );
         });
@@ -561,7 +679,7 @@ mod tests {
 
             assert_eq!(state.proposals.len(), 1);
 
-            let p = state.proposals.iter_mut().next().unwrap();
+            let p = state.proposals.values_mut().next().unwrap();
             p.status = Status::Executed;
 
             assert_eq!(state.proposals.len(), 1);
@@ -592,7 +710,10 @@ mod tests {
             // vote no 3 times
             for i in 1..4 {
                 assert!(vote_on_proposal(state, 0, pr(i), prop_id, false, data).is_ok());
-                assert_eq!(state.proposals.iter().last().unwrap().status, Status::Open);
+                assert_eq!(
+                    state.proposals.values().last().unwrap().status,
+                    Status::Open
+                );
             }
 
             // error cases again
@@ -627,7 +748,7 @@ mod tests {
                 Ok(())
             );
             assert_eq!(
-                state.proposals.iter().last().unwrap().status,
+                state.proposals.values().last().unwrap().status,
                 Status::Rejected,
             );
 
@@ -662,14 +783,20 @@ mod tests {
             // make sure it is executed when 2/3 have voted
             for i in 2..7 {
                 assert!(vote_on_proposal(state, 0, pr(i), prop_id, true, data).is_ok());
-                assert_eq!(state.proposals.iter().last().unwrap().status, Status::Open);
+                assert_eq!(
+                    state.proposals.values().last().unwrap().status,
+                    Status::Open
+                );
             }
             assert!(vote_on_proposal(state, 0, pr(7), prop_id, true, data).is_ok());
-            assert_eq!(state.proposals.iter().last().unwrap().status, Status::Open);
+            assert_eq!(
+                state.proposals.values().last().unwrap().status,
+                Status::Open
+            );
 
             assert!(vote_on_proposal(state, 0, pr(8), prop_id, true, data).is_ok());
             assert_eq!(
-                state.proposals.iter().last().unwrap().status,
+                state.proposals.values().last().unwrap().status,
                 Status::Executed
             );
             assert_eq!(
@@ -707,23 +834,26 @@ mod tests {
                 Ok(())
             );
             assert_eq!(
-                state.proposals.iter().last().unwrap().voting_power,
+                state.proposals.values().last().unwrap().voting_power,
                 10000 * 3
             );
 
             // after a day we only count 99% of voting power
             assert_eq!(execute_proposal(state, prop_id, time() + HOUR * 24), Ok(()));
-            assert_eq!(state.proposals.iter().last().unwrap().voting_power, 29700);
-            assert_eq!(state.proposals.iter().last().unwrap().status, Status::Open);
+            assert_eq!(state.proposals.values().last().unwrap().voting_power, 29700);
+            assert_eq!(
+                state.proposals.values().last().unwrap().status,
+                Status::Open
+            );
 
             // after a day we only count 98% of voting power and it's enough to reject
             assert_eq!(
                 execute_proposal(state, prop_id, time() + 2 * HOUR * 24),
                 Ok(())
             );
-            assert_eq!(state.proposals.iter().last().unwrap().voting_power, 29400);
+            assert_eq!(state.proposals.values().last().unwrap().voting_power, 29400);
             assert_eq!(
-                state.proposals.iter().last().unwrap().status,
+                state.proposals.values().last().unwrap().status,
                 Status::Rejected
             );
         })
@@ -763,7 +893,7 @@ mod tests {
             }
 
             assert_eq!(
-                state.proposals.iter().last().unwrap().status,
+                state.proposals.values().last().unwrap().status,
                 Status::Rejected
             );
             assert_eq!(state.principal_to_user(pr(1)).unwrap().cycles(), 498);
@@ -870,7 +1000,7 @@ mod tests {
                 Ok(())
             );
 
-            let proposal = state.proposals.iter().find(|p| p.id == prop_id).unwrap();
+            let proposal = state.proposals.values().find(|p| p.id == prop_id).unwrap();
             if let Payload::Reward(reward) = &proposal.payload {
                 assert_eq!(reward.minted, 48571);
                 assert_eq!(proposal.status, Status::Executed);
@@ -915,7 +1045,7 @@ mod tests {
                 Ok(())
             );
 
-            let proposal = state.proposals.iter().find(|p| p.id == prop_id).unwrap();
+            let proposal = state.proposals.values().find(|p| p.id == prop_id).unwrap();
             if let Payload::Reward(reward) = &proposal.payload {
                 assert_eq!(reward.minted, 0);
                 assert_eq!(proposal.status, Status::Rejected);
@@ -958,7 +1088,7 @@ mod tests {
                 Ok(())
             );
 
-            let proposal = state.proposals.iter().find(|p| p.id == prop_id).unwrap();
+            let proposal = state.proposals.values().find(|p| p.id == prop_id).unwrap();
             if let Payload::Reward(reward) = &proposal.payload {
                 assert_eq!(reward.minted, 42857);
                 assert_eq!(proposal.status, Status::Executed);
@@ -986,4 +1116,98 @@ mod tests {
             );
         })
     }
+
+    #[test]
+    fn test_abandoned_realm_proposal() {
+        STATE.with(|cell| {
+            cell.replace(Default::default());
+            let state = &mut *cell.borrow_mut();
+
+            let id = create_user(state, pr(1));
+            let user = state.users.get_mut(&id).unwrap();
+            user.change_karma(1000, "test");
+            user.stalwart = true;
+            let controller = create_user(state, pr(2));
+            state.mint();
+
+            let name = "REALM".to_string();
+            state.realms.insert(
+                name.clone(),
+                crate::env::Realm {
+                    controllers: vec![controller].into_iter().collect(),
+                    ..Default::default()
+                },
+            );
+            state.users.get_mut(&controller).unwrap().last_activity = time();
+
+            assert_eq!(
+                propose(
+                    state,
+                    pr(1),
+                    "test".into(),
+                    Payload::AbandonedRealm(name.clone(), None),
+                    time()
+                ),
+                Err("realm controllers were active within the last 12 weeks".into())
+            );
+
+            let now = time() + (CONFIG.realm_abandonment_weeks + 1) * WEEK;
+
+            // the proposal is rejected if a controller becomes active before the execution
+            let prop_id = propose(
+                state,
+                pr(1),
+                "test".into(),
+                Payload::AbandonedRealm(name.clone(), None),
+                now,
+            )
+            .expect("couldn't propose");
+            state.users.get_mut(&controller).unwrap().last_activity = now;
+            assert_eq!(
+                vote_on_proposal(state, now, pr(1), prop_id, true, ""),
+                Ok(())
+            );
+            assert_eq!(
+                state.proposals.get(&prop_id).unwrap().status,
+                Status::Rejected
+            );
+            assert!(!state.realms.get(&name).unwrap().archived);
+            state.users.get_mut(&controller).unwrap().last_activity = time();
+
+            let prop_id = propose(
+                state,
+                pr(1),
+                "test".into(),
+                Payload::AbandonedRealm(name.clone(), None),
+                now,
+            )
+            .expect("couldn't propose");
+            assert_eq!(
+                vote_on_proposal(state, now, pr(1), prop_id, true, ""),
+                Ok(())
+            );
+            assert_eq!(
+                state.proposals.get(&prop_id).unwrap().status,
+                Status::Executed
+            );
+            assert!(state.realms.get(&name).unwrap().archived);
+
+            // an archived realm can be revived with a new controller
+            let prop_id = propose(
+                state,
+                pr(1),
+                "test".into(),
+                Payload::AbandonedRealm(name.clone(), Some(id)),
+                now,
+            )
+            .expect("couldn't propose");
+            assert_eq!(
+                vote_on_proposal(state, now, pr(1), prop_id, true, ""),
+                Ok(())
+            );
+            let realm = state.realms.get(&name).unwrap();
+            assert!(!realm.archived);
+            assert_eq!(realm.controllers, vec![id].into_iter().collect());
+        })
+    }
 }
@@ -0,0 +1,253 @@
+use super::user::UserId;
+use super::*;
+use serde::{Deserialize, Serialize};
+
+// A pending recovery of an account. Every guardian approves the principal the account should be
+// re-bound to. As soon as enough guardians agree on the same principal, the recovery gets
+// authorized and can be completed after the waiting period, unless the owner vetoes it.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct Recovery {
+    pub approvals: BTreeMap<UserId, Principal>,
+    pub authorized: Option<(Principal, u64)>,
+    pub timestamp: u64,
+}
+
+/// Sets the guardians of the caller's account and the number of guardians needed for a
+/// recovery. Any pending recovery of the account gets cancelled.
+pub fn set_guardians(
+    state: &mut State,
+    principal: Principal,
+    guardians: BTreeSet<UserId>,
+    threshold: usize,
+) -> Result<(), String> {
+    let user_id = state
+        .principal_to_user(principal)
+        .ok_or("no user found")?
+        .id;
+    if guardians.len() > CONFIG.max_guardians {
+        return Err(format!(
+            "not more than {} guardians are allowed",
+            CONFIG.max_guardians
+        ));
+    }
+    if guardians.contains(&user_id) {
+        return Err("you can't be your own guardian".into());
+    }
+    if guardians.iter().any(|id| !state.users.contains_key(id)) {
+        return Err("no user found".into());
+    }
+    if guardians.is_empty() && threshold != 0
+        || !guardians.is_empty() && (threshold == 0 || threshold > guardians.len())
+    {
+        return Err("invalid threshold".into());
+    }
+    state.recoveries.remove(&user_id);
+    let user = state.users.get_mut(&user_id).expect("no user found");
+    user.guardians = guardians;
+    user.guardian_threshold = threshold;
+    Ok(())
+}
+
+/// Records the approval of a guardian to re-bind the account `user_id` to `new_principal`.
+pub fn approve(
+    state: &mut State,
+    principal: Principal,
+    user_id: UserId,
+    new_principal: String,
+    now: u64,
+) -> Result<(), String> {
+    let guardian = state.principal_to_user(principal).ok_or("no user found")?;
+    let guardian_id = guardian.id;
+    let guardian_name = guardian.name.clone();
+    let user = state.users.get(&user_id).ok_or("no user found")?;
+    if !user.guardians.contains(&guardian_id) {
+        return Err("not a guardian".into());
+    }
+    let threshold = user.guardian_threshold;
+    let new_principal = Principal::from_text(new_principal).map_err(|e| e.to_string())?;
+    if state.principals.contains_key(&new_principal) {
+        return Err("principal already controls a user".into());
+    }
+    let recovery = state.recoveries.entry(user_id).or_insert_with(|| Recovery {
+        timestamp: now,
+        ..Default::default()
+    });
+    if recovery.authorized.is_some() {
+        return Err("recovery already authorized".into());
+    }
+    recovery.approvals.insert(guardian_id, new_principal);
+    let approvals = recovery
+        .approvals
+        .values()
+        .filter(|p| **p == new_principal)
+        .count();
+    if approvals < threshold {
+        return Ok(());
+    }
+    recovery.authorized = Some((new_principal, now));
+    let user = state.users.get_mut(&user_id).expect("no user found");
+    user.notify(format!(
+        "Your guardians authorized re-binding your account to the principal `{}`. \
+        The recovery can be completed in {} days, unless you veto it.",
+        new_principal, CONFIG.recovery_waiting_period_days
+    ));
+    let user_name = user.name.clone();
+    state.logger.info(format!(
+        "Recovery of @{}'s account was authorized by guardians (last approval by @{}).",
+        user_name, guardian_name
+    ));
+    Ok(())
+}
+
+/// Cancels a pending recovery of the caller's account.
+pub fn veto(state: &mut State, principal: Principal) -> Result<(), String> {
+    let user = state.principal_to_user(principal).ok_or("no user found")?;
+    let (user_id, user_name, guardians) = (user.id, user.name.clone(), user.guardians.clone());
+    state
+        .recoveries
+        .remove(&user_id)
+        .ok_or("no pending recovery")?;
+    for guardian_id in guardians {
+        if let Some(guardian) = state.users.get_mut(&guardian_id) {
+            guardian.notify(format!(
+                "@{} vetoed the recovery of their account.",
+                user_name
+            ));
+        }
+    }
+    Ok(())
+}
+
+// Returns the current principal of the account, if the caller is authorized to complete its
+// recovery.
+fn recoverable(
+    state: &State,
+    caller: Principal,
+    user_id: UserId,
+    now: u64,
+) -> Result<Principal, String> {
+    let (new_principal, authorized_at) = state
+        .recoveries
+        .get(&user_id)
+        .and_then(|recovery| recovery.authorized)
+        .ok_or("no authorized recovery")?;
+    if new_principal != caller {
+        return Err("recovery authorized for a different principal".into());
+    }
+    if authorized_at + CONFIG.recovery_waiting_period_days * DAY > now {
+        return Err("waiting period is not over yet".into());
+    }
+    Ok(state.users.get(&user_id).ok_or("no user found")?.principal)
+}
+
+/// Re-binds the account to the caller's principal after the waiting period of an authorized
+/// recovery.
+pub async fn complete(caller: Principal, user_id: UserId, now: u64) -> Result<(), String> {
+    // The recovery is removed before the principal change, so that it can't be completed twice
+    // while the change awaits the ICP transfer.
+    let (principal, recovery) = mutate(|state| {
+        let principal = recoverable(state, caller, user_id, now)?;
+        let recovery = state
+            .recoveries
+            .remove(&user_id)
+            .expect("no recovery found");
+        Ok::<_, String>((principal, recovery))
+    })?;
+    if let Err(err) = State::change_principal(principal, caller.to_string()).await {
+        // restore the recovery only if the account is still bound to its old principal
+        mutate(|state| {
+            if state.users.get(&user_id).map(|user| user.principal) == Some(principal) {
+                state.recoveries.insert(user_id, recovery);
+            }
+        });
+        return Err(err);
+    }
+    mutate(|state| {
+        let user = state.users.get_mut(&user_id).expect("no user found");
+        user.notify("Your account was recovered by your guardians.");
+        let user_name = user.name.clone();
+        state
+            .logger
+            .info(format!("@{}'s account was recovered.", user_name));
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        env::tests::{create_user, pr},
+        STATE,
+    };
+
+    #[test]
+    fn test_recovery() {
+        STATE.with(|cell| {
+            cell.replace(Default::default());
+            let state = &mut *cell.borrow_mut();
+            let id = create_user(state, pr(0));
+            let guardians = (1..=3)
+                .map(|i| create_user(state, pr(i)))
+                .collect::<BTreeSet<_>>();
+            let new_principal = pr(10);
+            let now = WEEK;
+
+            assert_eq!(
+                set_guardians(state, pr(0), guardians.clone(), 4),
+                Err("invalid threshold".into())
+            );
+            assert_eq!(
+                set_guardians(state, pr(0), vec![id].into_iter().collect(), 1),
+                Err("you can't be your own guardian".into())
+            );
+            assert!(set_guardians(state, pr(0), guardians, 2).is_ok());
+
+            assert_eq!(
+                approve(state, pr(4), id, new_principal.to_string(), now),
+                Err("no user found".into())
+            );
+            assert_eq!(
+                approve(state, pr(1), id, pr(2).to_string(), now),
+                Err("principal already controls a user".into())
+            );
+
+            // guardians need to agree on the same principal
+            assert!(approve(state, pr(1), id, new_principal.to_string(), now).is_ok());
+            assert!(approve(state, pr(2), id, pr(11).to_string(), now).is_ok());
+            assert!(state.recoveries.get(&id).unwrap().authorized.is_none());
+            assert!(approve(state, pr(2), id, new_principal.to_string(), now).is_ok());
+            assert_eq!(
+                state.recoveries.get(&id).unwrap().authorized,
+                Some((new_principal, now))
+            );
+            // the owner gets notified
+            assert!(!state.users.get(&id).unwrap().inbox.is_empty());
+
+            // the recovery can be completed only after the waiting period by the new principal
+            assert_eq!(
+                recoverable(state, new_principal, id, now + DAY),
+                Err("waiting period is not over yet".into())
+            );
+            let later = now + CONFIG.recovery_waiting_period_days * DAY;
+            assert_eq!(
+                recoverable(state, pr(11), id, later),
+                Err("recovery authorized for a different principal".into())
+            );
+            assert_eq!(recoverable(state, new_principal, id, later), Ok(pr(0)));
+
+            // the owner can veto
+            assert!(veto(state, pr(0)).is_ok());
+            assert_eq!(
+                recoverable(state, new_principal, id, later),
+                Err("no authorized recovery".into())
+            );
+            assert_eq!(veto(state, pr(0)), Err("no pending recovery".into()));
+
+            // stale recoveries get cleaned up
+            assert!(approve(state, pr(3), id, new_principal.to_string(), now).is_ok());
+            state.clean_up(now + CONFIG.recovery_expiration_weeks * WEEK);
+            assert!(state.recoveries.is_empty());
+        })
+    }
+}
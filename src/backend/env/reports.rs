@@ -2,6 +2,32 @@ use super::user::UserId;
 use super::*;
 use serde::{Deserialize, Serialize};
 
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum ReportCategory {
+    // Reports filed before categories were introduced.
+    #[default]
+    Other,
+    Spam,
+    Abuse,
+    IllegalContent,
+    Impersonation,
+    OffTopic,
+}
+
+impl ReportCategory {
+    pub fn penalty(&self, domain: &str) -> Cycles {
+        match self {
+            ReportCategory::Spam => CONFIG.reporting_penalty_spam,
+            ReportCategory::Abuse => CONFIG.reporting_penalty_abuse,
+            ReportCategory::IllegalContent => CONFIG.reporting_penalty_illegal_content,
+            ReportCategory::Impersonation => CONFIG.reporting_penalty_impersonation,
+            ReportCategory::OffTopic => CONFIG.reporting_penalty_off_topic,
+            ReportCategory::Other if domain == "post" => CONFIG.reporting_penalty_post,
+            ReportCategory::Other => CONFIG.reporting_penalty_misbehaviour,
+        }
+    }
+}
+
 #[derive(Clone, Default, Serialize, Deserialize)]
 pub struct Report {
     pub reporter: UserId,
@@ -9,6 +35,30 @@ pub struct Report {
     pub rejected_by: Vec<UserId>,
     pub closed: bool,
     pub reason: String,
+    #[serde(default)]
+    pub category: ReportCategory,
+    #[serde(default)]
+    pub timestamp: u64,
+    // Karma and cycles the penalized user lost once the report was confirmed.
+    #[serde(default)]
+    pub penalty_karma: Karma,
+    #[serde(default)]
+    pub penalty_cycles: Cycles,
+    // Parts of the penalty paid to the reporter and stalwarts: (receiver, cycles, karma received).
+    #[serde(default)]
+    pub penalty_transfers: Vec<(UserId, Cycles, Karma)>,
+    // The part of the penalty which was burned.
+    #[serde(default)]
+    pub penalty_burned: Cycles,
+    // The stalwart status and the active weeks the penalized user lost.
+    #[serde(default)]
+    pub penalty_stalwart: bool,
+    #[serde(default)]
+    pub penalty_active_weeks: u32,
+    // An appeal of the penalized user, voted on by stalwarts who didn't vote on the report.
+    // Confirmation of the appeal reverses the penalty.
+    #[serde(default)]
+    pub appeal: Option<Box<Report>>,
 }
 
 impl Report {
@@ -37,11 +87,72 @@ impl Report {
         }
         Ok(())
     }
+
+    pub fn confirmed(&self) -> bool {
+        self.closed && self.confirmed_by.len() > self.rejected_by.len()
+    }
+
+    pub fn voted(&self, user_id: UserId) -> bool {
+        self.confirmed_by.contains(&user_id) || self.rejected_by.contains(&user_id)
+    }
+}
+
+#[derive(Serialize)]
+pub struct OpenReport {
+    pub domain: String,
+    pub id: u64,
+    pub reporter: UserId,
+    pub category: ReportCategory,
+    pub reason: String,
+    pub confirmations: usize,
+    pub rejections: usize,
+    pub age: u64,
+}
+
+/// Returns all open post and user reports, sorted by urgency: the reports with the highest
+/// penalty come first, the oldest reports first within the same penalty.
+pub fn moderation_queue(state: &State, now: u64) -> Vec<OpenReport> {
+    let post_reports = state.open_post_reports.iter().filter_map(|id| {
+        Post::get(state, id)
+            .and_then(|post| post.report.as_ref())
+            .map(|report| ("post", *id, report))
+    });
+    let user_reports = state.users.values().filter_map(|user| {
+        user.report
+            .as_ref()
+            .map(|report| ("misbehaviour", user.id, report))
+    });
+    let mut queue = post_reports
+        .chain(user_reports)
+        .filter(|(_, _, report)| !report.closed)
+        .map(|(domain, id, report)| OpenReport {
+            domain: domain.to_string(),
+            id,
+            reporter: report.reporter,
+            category: report.category,
+            reason: report.reason.clone(),
+            confirmations: report.confirmed_by.len(),
+            rejections: report.rejected_by.len(),
+            age: now.saturating_sub(report.timestamp),
+        })
+        .collect::<Vec<_>>();
+    queue.sort_by_key(|report| {
+        std::cmp::Reverse((report.category.penalty(&report.domain), report.age))
+    });
+    queue
+}
+
+fn total_karma(state: &State, user_id: UserId) -> Karma {
+    state
+        .users
+        .get(&user_id)
+        .map(|user| user.karma() + user.karma_to_reward() as Karma)
+        .unwrap_or_default()
 }
 
 pub fn finalize_report(
     state: &mut State,
-    report: &Report,
+    report: &mut Report,
     penalty: Cycles,
     user_id: UserId,
     subject: String,
@@ -49,7 +160,8 @@ pub fn finalize_report(
     if !report.closed {
         return Ok(());
     }
-    let (sponsor_id, unit) = if report.confirmed_by.len() > report.rejected_by.len() {
+    let cycles_before = state.users.get(&user_id).ok_or("no user found")?.cycles();
+    let (sponsor_id, unit) = if report.confirmed() {
         // penalty for the user
         let user = state.users.get_mut(&user_id).ok_or("no user found")?;
         user.notify(format!(
@@ -60,6 +172,8 @@ pub fn finalize_report(
             -(penalty as Karma),
             format!("moderation penalty for {}", subject),
         );
+        report.penalty_stalwart = user.stalwart;
+        report.penalty_active_weeks = user.active_weeks;
         user.stalwart = false;
         user.active_weeks = 0;
         let unit = penalty.min(user.cycles()) / 2;
@@ -71,6 +185,7 @@ pub fn finalize_report(
             "Your report for {} was deleted by stalwarts. Thanks for keeping {} safe and clean!",
             subject, CONFIG.name
         ));
+        let karma_before = total_karma(state, report.reporter);
         state
             .cycle_transfer(
                 user_id,
@@ -82,6 +197,11 @@ pub fn finalize_report(
                 None,
             )
             .map_err(|err| format!("couldn't reward reporter: {}", err))?;
+        report.penalty_transfers.push((
+            report.reporter,
+            unit,
+            total_karma(state, report.reporter) - karma_before,
+        ));
         (user_id, unit)
     } else {
         // penalty for reporter
@@ -106,10 +226,30 @@ pub fn finalize_report(
         .cloned()
         .collect::<Vec<_>>();
     let stalwart_reward = (unit / stalwarts.len() as u64).min(CONFIG.stalwart_moderation_reward);
+    // the rewards are scaled by the accuracy of moderators, but never exceed the penalty
+    let rewards = stalwarts
+        .iter()
+        .map(|id| {
+            let weight = state
+                .users
+                .get(id)
+                .map(|user| user.moderation.reward_weight())
+                .unwrap_or_default();
+            stalwart_reward * weight / 100
+        })
+        .collect::<Vec<_>>();
+    let rewards_pool = stalwart_reward * stalwarts.len() as u64;
+    let total_rewards = rewards.iter().sum::<u64>().max(1);
     let mut total_stalwart_rewards = 0;
     let log = &format!("stalwarts moderation rewards for {}", subject);
-    for stalwart_id in stalwarts.iter() {
+    for (stalwart_id, reward) in stalwarts.iter().zip(rewards) {
         let moderator = state.users.get(stalwart_id).expect("no user found").id;
+        let stalwart_reward = if total_rewards > rewards_pool {
+            reward * rewards_pool / total_rewards
+        } else {
+            reward
+        };
+        let karma_before = total_karma(state, moderator);
         state
             .cycle_transfer(
                 sponsor_id,
@@ -121,6 +261,13 @@ pub fn finalize_report(
                 None,
             )
             .map_err(|err| format!("couldn't reward stalwarts: {}", err))?;
+        if report.confirmed() {
+            report.penalty_transfers.push((
+                moderator,
+                stalwart_reward,
+                total_karma(state, moderator) - karma_before,
+            ));
+        }
         total_stalwart_rewards += stalwart_reward;
     }
     if unit > total_stalwart_rewards {
@@ -131,6 +278,111 @@ pub fn finalize_report(
                 format!("moderation penalty for {}", subject),
             )
             .expect("couldn't charge user");
+        if report.confirmed() {
+            report.penalty_burned = unit - total_stalwart_rewards;
+        }
+    }
+    update_moderation_stats(state, report, user_id);
+    if report.confirmed() {
+        report.penalty_karma = penalty as Karma;
+        report.penalty_cycles = cycles_before
+            .saturating_sub(state.users.get(&user_id).ok_or("no user found")?.cycles());
+    }
+    state.denotify_users(&|u| u.stalwart);
+    Ok(())
+}
+
+// Updates the accuracy of stalwarts who voted on a closed report and the abstentions of the ones
+// who didn't.
+fn update_moderation_stats(state: &mut State, report: &Report, user_id: UserId) {
+    let confirmed = report.confirmed();
+    let now = time();
+    for user in state.users.values_mut() {
+        let stats = &mut user.moderation;
+        if report.voted(user.id) {
+            stats.record_vote(now, report.confirmed_by.contains(&user.id) == confirmed);
+        } else if user.stalwart && user.id != report.reporter && user.id != user_id {
+            stats.abstentions += 1;
+            stats.consecutive_abstentions += 1;
+        }
+    }
+}
+
+pub fn finalize_appeal(
+    state: &mut State,
+    report: &Report,
+    user_id: UserId,
+    subject: String,
+) -> Result<(), String> {
+    let appeal = match report.appeal.as_ref() {
+        Some(appeal) if appeal.closed => appeal,
+        _ => return Ok(()),
+    };
+    let user = state.users.get_mut(&user_id).ok_or("no user found")?;
+    if !appeal.confirmed() {
+        user.notify(format!(
+            "Your appeal of the report for your {} was rejected by stalwarts.",
+            subject
+        ));
+        state.denotify_users(&|u| u.stalwart);
+        return Ok(());
+    }
+
+    // the penalty is reversed: the rewards are taken back from the reporter and stalwarts, the
+    // cycles they were paid with and the burned cycles are returned to the user together with
+    // the deposit
+    let log = format!("appeal of report for {}", subject);
+    let overturned_log = format!("overturned report for {}", subject);
+    let mut refund = 0;
+    for (receiver_id, cycles, karma) in &report.penalty_transfers {
+        refund += cycles;
+        let receiver = match state.users.get_mut(receiver_id) {
+            Some(receiver) => receiver,
+            None => continue,
+        };
+        receiver.change_karma(-karma, &overturned_log);
+        let debit = receiver.cycles().min(*cycles);
+        let name = receiver.name.clone();
+        if debit > 0 {
+            state.charge(*receiver_id, debit, &overturned_log)?;
+        }
+        if debit < *cycles {
+            state.logger.info(format!(
+                "`{}` cycles of moderation rewards for {} couldn't be taken back from @{}.",
+                cycles - debit,
+                subject,
+                name
+            ));
+        }
+    }
+    let returned = refund + report.penalty_burned + CONFIG.report_appeal_deposit;
+    state.burned_cycles -= returned as i64;
+    let user = state.users.get_mut(&user_id).ok_or("no user found")?;
+    user.change_cycles(returned, CyclesDelta::Plus, &log)?;
+    user.change_karma(report.penalty_karma, &log);
+    if report.penalty_stalwart {
+        user.stalwart = true;
+    }
+    user.active_weeks = user.active_weeks.max(report.penalty_active_weeks);
+    user.notify(format!(
+        "Your appeal of the report for your {} was accepted by stalwarts and the penalty was reversed.",
+        subject
+    ));
+
+    // the reporter pays the penalty instead
+    let reporter = state
+        .users
+        .get_mut(&report.reporter)
+        .ok_or("no user found")?;
+    reporter.notify(format!(
+        "Your report of {} was overturned on appeal by stalwarts.",
+        subject
+    ));
+    let log = format!("overturned report penalty for {}", subject);
+    reporter.change_karma(-report.penalty_karma, &log);
+    let penalty = reporter.cycles().min(report.penalty_cycles);
+    if penalty > 0 {
+        state.charge(report.reporter, penalty, log)?;
     }
     state.denotify_users(&|u| u.stalwart);
     Ok(())
@@ -193,7 +445,13 @@ mod tests {
                 .unwrap();
             assert_eq!(reporter_user.cycles(), 0);
             assert_eq!(
-                state.report(reporter, "post".into(), post_id, String::new()),
+                state.report(
+                    reporter,
+                    "post".into(),
+                    post_id,
+                    String::new(),
+                    Default::default()
+                ),
                 Err("You need at least 100 cycles for this report".into())
             );
             let p = Post::get(state, &post_id).unwrap();
@@ -205,7 +463,13 @@ mod tests {
                 .unwrap();
             assert_eq!(reporter_user.cycles(), 500);
             state
-                .report(reporter, "post".into(), post_id, String::new())
+                .report(
+                    reporter,
+                    "post".into(),
+                    post_id,
+                    String::new(),
+                    Default::default(),
+                )
                 .unwrap();
 
             // make sure the reporter is correct
@@ -215,7 +479,13 @@ mod tests {
 
             // Another user cannot overwrite the report
             assert_eq!(
-                state.report(pr(8), "post".into(), post_id, String::new()),
+                state.report(
+                    pr(8),
+                    "post".into(),
+                    post_id,
+                    String::new(),
+                    Default::default()
+                ),
                 Err("this post is already reported".into())
             );
             // the reporter is stil lthe same
@@ -319,7 +589,13 @@ mod tests {
 
             let reporter = pr(7);
             state
-                .report(reporter, "post".into(), post_id, String::new())
+                .report(
+                    reporter,
+                    "post".into(),
+                    post_id,
+                    String::new(),
+                    Default::default(),
+                )
                 .unwrap();
             // set cycles to 777
             let reporter_user = state.principal_to_user_mut(reporter).unwrap();
@@ -372,10 +648,220 @@ mod tests {
                 state.principal_to_user(pr(9)).unwrap().karma_to_reward(),
                 CONFIG.stalwart_moderation_reward
             );
-            // he voted twice
+            // he voted twice, but his first vote didn't match the outcome
             assert_eq!(
                 state.principal_to_user(pr(6)).unwrap().karma_to_reward(),
-                CONFIG.stalwart_moderation_reward * 2
+                CONFIG.stalwart_moderation_reward + CONFIG.stalwart_moderation_reward / 2
+            );
+            let stats = &state.principal_to_user(pr(6)).unwrap().moderation;
+            assert_eq!(stats.votes, 2);
+            assert_eq!(stats.resolved_votes, 2);
+            assert_eq!(stats.accuracy(), Some(50));
+            assert_eq!(
+                state
+                    .principal_to_user(pr(1))
+                    .unwrap()
+                    .moderation
+                    .abstentions,
+                2
+            );
+        })
+    }
+
+    #[test]
+    fn test_report_appeal() {
+        STATE.with(|cell| {
+            cell.replace(Default::default());
+            let state = &mut *cell.borrow_mut();
+
+            let u = create_user(state, pr(0));
+            for i in 1..=10 {
+                let id = create_user(state, pr(i));
+                state.users.get_mut(&id).unwrap().stalwart = true;
+            }
+            let reporter = create_user(state, pr(11));
+            state.users.get_mut(&u).unwrap().active_weeks = 5;
+            let reporter_karma = total_karma(state, reporter);
+            let burned_cycles = state.burned_cycles;
+
+            let post_id =
+                Post::create(state, "post".to_string(), &[], pr(0), 0, None, None, None).unwrap();
+            state
+                .report(
+                    pr(11),
+                    "post".into(),
+                    post_id,
+                    String::new(),
+                    Default::default(),
+                )
+                .unwrap();
+
+            assert_eq!(
+                state.appeal_report(pr(0), "post".into(), post_id, String::new()),
+                Err("only confirmed reports can be appealed".into())
+            );
+
+            for i in 1..=2 {
+                state
+                    .vote_on_report(pr(i), "post".into(), post_id, true)
+                    .unwrap();
+            }
+            let report = Post::get(state, &post_id).unwrap().report.clone().unwrap();
+            assert!(report.confirmed());
+            assert_eq!(report.penalty_cycles, CONFIG.reporting_penalty_post);
+            assert_eq!(
+                state.users.get(&u).unwrap().cycles(),
+                1000 - CONFIG.post_cost - CONFIG.reporting_penalty_post
+            );
+
+            assert_eq!(
+                state.appeal_report(pr(11), "post".into(), post_id, String::new()),
+                Err("only penalized users can appeal".into())
+            );
+            assert_eq!(
+                state.appeal_report(pr(0), "post".into(), post_id, "mistake".into()),
+                Ok(())
+            );
+            assert_eq!(
+                state.appeal_report(pr(0), "post".into(), post_id, "mistake".into()),
+                Err("this report was already appealed".into())
+            );
+
+            // stalwarts who voted on the report can't vote on the appeal
+            assert_eq!(
+                state.vote_on_appeal(pr(1), "post".into(), post_id, true),
+                Err("you can't vote on the appeal of a report you created or voted on".into())
+            );
+            state
+                .vote_on_appeal(pr(3), "post".into(), post_id, true)
+                .unwrap();
+            assert_eq!(
+                state.users.get(&u).unwrap().cycles(),
+                1000 - CONFIG.post_cost
+                    - CONFIG.reporting_penalty_post
+                    - CONFIG.report_appeal_deposit
+            );
+            state
+                .vote_on_appeal(pr(4), "post".into(), post_id, true)
+                .unwrap();
+
+            let report = Post::get(state, &post_id).unwrap().report.clone().unwrap();
+            assert!(report.appeal.as_ref().unwrap().confirmed());
+            assert_eq!(
+                state.users.get(&u).unwrap().cycles(),
+                1000 - CONFIG.post_cost
+            );
+            // the rewards are taken back instead of minting new cycles and the reporter pays the
+            // penalty
+            let reward = |id| {
+                report
+                    .penalty_transfers
+                    .iter()
+                    .find(|(receiver, _, _)| *receiver == id)
+                    .map(|(_, cycles, _)| *cycles)
+                    .unwrap()
+            };
+            assert_eq!(
+                state.users.get(&reporter).unwrap().cycles(),
+                1000 - reward(reporter) - report.penalty_cycles
+            );
+            assert_eq!(
+                total_karma(state, reporter),
+                reporter_karma - report.penalty_karma
+            );
+            for i in 1..=2 {
+                let stalwart = state.principal_to_user(pr(i)).unwrap();
+                assert_eq!(stalwart.karma_to_reward(), 0);
+                assert_eq!(stalwart.cycles(), 1000 - reward(stalwart.id));
+            }
+            assert_eq!(
+                state.burned_cycles,
+                burned_cycles + (CONFIG.post_cost + report.penalty_cycles) as i64
+            );
+            assert_eq!(state.users.get(&u).unwrap().active_weeks, 5);
+            assert_eq!(
+                state.vote_on_appeal(pr(5), "post".into(), post_id, true),
+                Err("appeal is closed".into())
+            );
+        })
+    }
+
+    #[test]
+    fn test_moderation_queue() {
+        STATE.with(|cell| {
+            cell.replace(Default::default());
+            let state = &mut *cell.borrow_mut();
+
+            let u = create_user(state, pr(0));
+            for i in 1..=10 {
+                let id = create_user(state, pr(i));
+                state.users.get_mut(&id).unwrap().stalwart = true;
+            }
+            create_user(state, pr(11));
+
+            let post_id =
+                Post::create(state, "post".to_string(), &[], pr(0), 0, None, None, None).unwrap();
+            assert_eq!(
+                state.report(
+                    pr(11),
+                    "post".into(),
+                    post_id,
+                    String::new(),
+                    ReportCategory::OffTopic
+                ),
+                Err("only posts in realms can be reported as off-topic".into())
+            );
+            assert_eq!(
+                state.report(
+                    pr(11),
+                    "misbehaviour".into(),
+                    u,
+                    String::new(),
+                    ReportCategory::OffTopic
+                ),
+                Err("only posts can be reported as off-topic".into())
+            );
+            state
+                .report(
+                    pr(11),
+                    "post".into(),
+                    post_id,
+                    "spam".into(),
+                    ReportCategory::Spam,
+                )
+                .unwrap();
+            state
+                .report(
+                    pr(11),
+                    "misbehaviour".into(),
+                    u,
+                    "fake".into(),
+                    ReportCategory::Impersonation,
+                )
+                .unwrap();
+            state
+                .vote_on_report(pr(1), "post".into(), post_id, true)
+                .unwrap();
+
+            let queue = moderation_queue(state, time() + 1000);
+            assert_eq!(queue.len(), 2);
+            assert_eq!(queue[0].domain, "misbehaviour");
+            assert_eq!(queue[0].category, ReportCategory::Impersonation);
+            assert_eq!(queue[1].domain, "post");
+            assert_eq!(queue[1].category, ReportCategory::Spam);
+            assert_eq!(queue[1].confirmations, 1);
+            assert_eq!(queue[1].age, 1000);
+
+            // closed reports leave the queue
+            state
+                .vote_on_report(pr(2), "post".into(), post_id, true)
+                .unwrap();
+            let queue = moderation_queue(state, time());
+            assert_eq!(queue.len(), 1);
+            assert!(state.open_post_reports.is_empty());
+            assert_eq!(
+                state.users.get(&u).unwrap().cycles(),
+                1000 - CONFIG.post_cost - CONFIG.reporting_penalty_spam
             );
         })
     }
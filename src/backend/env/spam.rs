@@ -0,0 +1,308 @@
+use super::post::{Extension, Post, PostId};
+use super::user::{User, UserId};
+use super::*;
+use serde::{Deserialize, Serialize};
+
+// A post whose spam score was too high to be published right away. It waits for a review by
+// stalwarts and gets created (and charged) only after an approval.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct HeldPost {
+    pub user: UserId,
+    pub principal: Principal,
+    pub body: String,
+    pub blobs: Vec<(String, Blob)>,
+    pub parent: Option<PostId>,
+    pub realm: Option<String>,
+    pub extension: Option<Extension>,
+    pub score: u64,
+    pub timestamp: u64,
+}
+
+fn words(body: &str) -> BTreeSet<String> {
+    body.split_whitespace()
+        .map(|word| {
+            word.chars()
+                .filter(|c| c.is_alphanumeric())
+                .collect::<String>()
+                .to_lowercase()
+        })
+        .filter(|word| !word.is_empty())
+        .collect()
+}
+
+// Returns the similarity of two sets of words in percent.
+fn similarity(a: &BTreeSet<String>, b: &BTreeSet<String>) -> u64 {
+    let union = a.union(b).count() as u64;
+    if union == 0 {
+        return 0;
+    }
+    a.intersection(b).count() as u64 * 100 / union
+}
+
+/// Computes the spam score of a new post. The score is a sum of penalties for near-duplicates of
+/// recent posts, links, mentions and bursts of posts from new accounts.
+pub fn score(state: &State, user: &User, body: &str, timestamp: u64) -> u64 {
+    let tokens = body.split_whitespace().collect::<Vec<_>>();
+    let mut score = 0;
+
+    // near-duplicates of recent posts
+    let words = words(body);
+    if words.len() >= CONFIG.spam_min_words {
+        let duplicates = state
+            .last_posts(Principal::anonymous(), None, true)
+            .take_while(|post| post.timestamp() + DAY > timestamp)
+            .take(CONFIG.spam_lookback_posts)
+            .filter(|post| {
+                similarity(&words, &self::words(&post.body)) >= CONFIG.spam_similarity_percentage
+            })
+            .count() as u64;
+        score += duplicates * 35;
+    }
+
+    // link density
+    let links = tokens
+        .iter()
+        .filter(|token| token.contains("http://") || token.contains("https://"))
+        .count() as u64;
+    score += links.saturating_sub(2) * 10;
+    if links > 1 && links * 3 > tokens.len() as u64 {
+        score += 30;
+    }
+
+    // mention flooding
+    let mentions = tokens
+        .iter()
+        .filter(|token| token.starts_with('@'))
+        .collect::<BTreeSet<_>>()
+        .len() as u64;
+    score += mentions.saturating_sub(5) * 10;
+
+    // bursts of new accounts
+    if timestamp.saturating_sub(user.timestamp) < WEEK {
+        let recent_posts = user
+            .posts(state)
+            .take_while(|post| post.timestamp() + HOUR > timestamp)
+            .count() as u64;
+        score += recent_posts * 10;
+    }
+
+    score
+}
+
+// Rejects held posts with attachments above the limit, or if the attachments of all held posts
+// would exceed the global limit.
+pub fn check_held_blobs(state: &State, blobs: &[(String, Blob)]) -> Result<(), String> {
+    let size = blobs.iter().map(|(_, blob)| blob.len()).sum::<usize>();
+    let held = state
+        .held_posts
+        .values()
+        .flat_map(|post| post.blobs.iter())
+        .map(|(_, blob)| blob.len())
+        .sum::<usize>();
+    if size > CONFIG.max_held_post_blobs_bytes || held + size > CONFIG.max_held_blobs_bytes {
+        return Err(
+            "post rejected by the spam filter: attachments are too large for a review".into(),
+        );
+    }
+    Ok(())
+}
+
+pub type ApprovedPost = (PostId, Vec<(String, Blob)>);
+
+/// Approves or rejects a held post of the given user. An approved post gets created and its id
+/// is returned together with the files of its blobs which still need to be saved.
+pub fn review(
+    state: &mut State,
+    principal: Principal,
+    user_id: UserId,
+    approve: bool,
+    timestamp: u64,
+) -> Result<Option<ApprovedPost>, String> {
+    let reviewer = state.principal_to_user(principal).ok_or("no user found")?;
+    if !reviewer.stalwart {
+        return Err("only stalwarts can review held posts".into());
+    }
+    if reviewer.id == user_id {
+        return Err("no reviews of own posts".into());
+    }
+    let reviewer_name = reviewer.name.clone();
+    let post = state
+        .held_posts
+        .get(&user_id)
+        .cloned()
+        .ok_or("no held post found")?;
+    if !approve {
+        state.held_posts.remove(&user_id);
+        state.denotify_users(&|u| u.stalwart);
+        if let Some(user) = state.users.get_mut(&user_id) {
+            user.notify("Your post was rejected by stalwarts as spam.");
+        }
+        state.logger.info(format!(
+            "@{} rejected a held post of user {}.",
+            reviewer_name, user_id
+        ));
+        return Ok(None);
+    }
+    let (post_id, files) = Post::create_post(
+        state,
+        post.body,
+        &post.blobs,
+        post.principal,
+        timestamp,
+        post.parent,
+        post.realm,
+        post.extension,
+        false,
+    )?;
+    // the held post is dropped only once it was created, so that it can be reviewed again
+    // if the creation fails
+    state.held_posts.remove(&user_id);
+    state.denotify_users(&|u| u.stalwart);
+    if let Some(user) = state.users.get_mut(&user_id) {
+        user.notify_about_post("Your post was approved by stalwarts.", post_id);
+    }
+    Ok(Some((post_id, files)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        env::tests::{create_user, pr},
+        STATE,
+    };
+
+    #[test]
+    fn test_spam_score() {
+        STATE.with(|cell| {
+            cell.replace(Default::default());
+            let state = &mut *cell.borrow_mut();
+            let id = create_user(state, pr(0));
+            let user = state.users.get(&id).unwrap().into_owned();
+            let now = WEEK;
+
+            assert_eq!(score(state, &user, "Hello world!", now), 0);
+
+            // links and mentions
+            assert_eq!(
+                score(
+                    state,
+                    &user,
+                    "https://a.com https://b.com https://c.com check this out",
+                    now
+                ),
+                40
+            );
+            assert_eq!(
+                score(state, &user, "@a @b @c @d @e @f @g hi there", now),
+                20
+            );
+
+            // near-duplicates
+            let body = "Buy the best tokens on this exchange, they go up only";
+            Post::create(state, body.into(), &[], pr(0), now, None, None, None).unwrap();
+            assert_eq!(
+                score(
+                    state,
+                    &user,
+                    "Buy the best tokens on this exchange, they go up only!!!",
+                    now
+                ),
+                35
+            );
+            // the duplicate is not recent anymore
+            assert_eq!(score(state, &user, body, now + DAY), 0);
+
+            // new accounts posting in bursts
+            let mut user = user;
+            user.timestamp = now;
+            assert_eq!(score(state, &user, "Hello world!", now), 10);
+        })
+    }
+
+    #[test]
+    fn test_held_posts() {
+        STATE.with(|cell| {
+            cell.replace(Default::default());
+            let state = &mut *cell.borrow_mut();
+            let id = create_user(state, pr(0));
+            let stalwart = create_user(state, pr(1));
+            state.users.get_mut(&stalwart).unwrap().stalwart = true;
+            let now = WEEK;
+
+            let body = "Buy tokens https://a.com https://b.com https://c.com https://d.com";
+            assert_eq!(
+                Post::create(state, body.into(), &[], pr(0), now, None, None, None),
+                Err("post was held for a review by stalwarts".into())
+            );
+            // nothing was charged
+            assert_eq!(state.users.get(&id).unwrap().cycles(), 1000);
+            assert_eq!(
+                Post::create(state, body.into(), &[], pr(0), now, None, None, None),
+                Err("you already have a post waiting for review".into())
+            );
+            assert_eq!(
+                Post::create(
+                    state,
+                    format!(
+                        "{} https://e.com https://f.com https://g.com https://h.com https://i.com",
+                        body
+                    ),
+                    &[],
+                    pr(0),
+                    now,
+                    None,
+                    None,
+                    None
+                ),
+                Err("post rejected by the spam filter".into())
+            );
+
+            assert_eq!(
+                review(state, pr(0), id, true, now),
+                Err("only stalwarts can review held posts".into())
+            );
+            // stalwarts are notified
+            assert!(state
+                .users
+                .get(&stalwart)
+                .unwrap()
+                .inbox
+                .values()
+                .any(|n| matches!(n, Notification::Conditional(_, Predicate::HeldPost(user)) if *user == id)));
+
+            // a failed creation keeps the post for another review
+            let balance = state.users.get(&id).unwrap().cycles();
+            state
+                .users
+                .get_mut(&id)
+                .unwrap()
+                .change_cycles(balance, CyclesDelta::Minus, "")
+                .unwrap();
+            assert!(review(state, pr(1), id, true, now).is_err());
+            assert!(state.held_posts.contains_key(&id));
+            state
+                .users
+                .get_mut(&id)
+                .unwrap()
+                .change_cycles(balance, CyclesDelta::Plus, "")
+                .unwrap();
+
+            let (post_id, _) = review(state, pr(1), id, true, now).unwrap().unwrap();
+            assert_eq!(Post::get(state, &post_id).unwrap().body, body);
+            assert!(state.held_posts.is_empty());
+            assert!(state.users.get(&id).unwrap().cycles() < 1000);
+            assert!(!state
+                .users
+                .get(&stalwart)
+                .unwrap()
+                .inbox
+                .values()
+                .any(|n| matches!(n, Notification::Conditional(_, Predicate::HeldPost(_)))));
+
+            // held posts with large attachments are rejected
+            let blob = ByteBuf::from(vec![0; CONFIG.max_held_post_blobs_bytes + 1]);
+            assert!(check_held_blobs(state, &[("abc".into(), blob)]).is_err());
+        })
+    }
+}
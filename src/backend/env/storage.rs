@@ -4,26 +4,109 @@ use crate::{
 };
 use candid::Principal;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use std::collections::BTreeMap;
+use std::ops::Bound;
 
-use super::config::CONFIG;
+use super::{config::CONFIG, time, HOUR};
+
+// A blob stored in a bucket. Blobs are addressed by the SHA-256 hash of their contents, so that
+// identical blobs are stored only once.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StoredBlob {
+    pub bucket: Principal,
+    pub offset: u64,
+    pub len: u64,
+    // The number of post files referencing the blob.
+    pub references: u32,
+    // The location of a copy of the blob in another bucket.
+    #[serde(default)]
+    pub replica: Option<(Principal, u64)>,
+}
 
 #[derive(Default, Serialize, Deserialize)]
 pub struct Storage {
     pub buckets: BTreeMap<Principal, u64>,
+    #[serde(default)]
+    pub blobs: BTreeMap<String, StoredBlob>,
+    // Hashes of the stored blobs by their locations.
+    #[serde(default)]
+    locations: BTreeMap<(Principal, u64), String>,
+    // Ranges of unreferenced blobs, which are yet to be erased by the garbage collection.
+    #[serde(default)]
+    pub garbage: Vec<(Principal, u64, u64)>,
+    // Erased ranges reused for new blobs.
+    #[serde(default)]
+    pub free_ranges: BTreeMap<Principal, BTreeMap<u64, u64>>,
+    // The hash of the last verified blob.
+    #[serde(default)]
+    verification_cursor: String,
+    // Hex-encoded SHA-256 hashes of the WASM modules installed on the buckets.
+    #[serde(default)]
+    pub wasm_hashes: BTreeMap<Principal, String>,
+    #[serde(default)]
+    pub upgrade: Option<BucketUpgrade>,
+    // Chunked uploads in progress by their locations, with their lengths and start times.
+    #[serde(default)]
+    uploads: BTreeMap<(Principal, u64), (u64, u64)>,
+}
+
+// The rollout of a new bucket WASM module.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct BucketUpgrade {
+    pub wasm_hash: String,
+    // Buckets yet to be upgraded in the order of the rollout.
+    pub pending: Vec<Principal>,
+    pub upgraded: usize,
+    // The bucket which failed the upgrade or the health check and the error. A failed rollout
+    // stays stopped until a new WASM module is released.
+    pub failure: Option<(Principal, String)>,
+}
+
+// The result of a blob verification.
+#[derive(Debug, PartialEq)]
+enum Verification {
+    Intact,
+    Replicated,
+    Repaired,
+}
+
+// Where a new blob should be stored.
+#[derive(Debug, PartialEq)]
+enum Placement {
+    // An identical blob is already stored.
+    Existing(Principal, u64),
+    // A free range was reserved for the blob.
+    Free(Principal, u64),
+    // The blob has to be appended to a bucket.
+    New,
+}
+
+fn hash(blob: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(blob);
+    format!("{:x}", hasher.finalize())
 }
 
+// The maximal number of bytes sent to a bucket in one call. Larger blobs are uploaded in chunks.
+const UPLOAD_CHUNK: usize = 1024 * 1024;
+
+// Uploads not completed within this time are abandoned and their ranges become garbage. Buckets
+// drop their upload sessions after one hour, so an abandoned upload cannot write to the range
+// anymore once it is erased and reused.
+const UPLOAD_TTL: u64 = 2 * HOUR;
+
 const BUCKET_WASM_GZ: &[u8] =
     include_bytes!("../../../target/wasm32-unknown-unknown/release/bucket.wasm.gz");
 
 impl Storage {
-    pub async fn allocate_space() -> Result<Principal, String> {
+    /// Returns a bucket with free space other than the excluded one, creating a new bucket if
+    /// needed.
+    pub async fn allocate_space(excluded: Option<Principal>) -> Result<Principal, String> {
         if let Some(id) = read(|state| {
-            state
-                .storage
-                .buckets
-                .iter()
-                .find_map(|(id, size)| (*size < CONFIG.max_bucket_size).then_some(*id))
+            state.storage.buckets.iter().find_map(|(id, size)| {
+                (*size < CONFIG.max_bucket_size && Some(*id) != excluded).then_some(*id)
+            })
         }) {
             return Ok(id);
         }
@@ -34,6 +117,7 @@ impl Storage {
         });
         canisters::install(id, BUCKET_WASM_GZ, CanisterInstallMode::Install).await?;
         mutate(|state| {
+            state.storage.wasm_hashes.insert(id, hash(BUCKET_WASM_GZ));
             state
                 .logger
                 .info(format!("WASM installed to bucket {}.", id));
@@ -41,34 +125,743 @@ impl Storage {
         Ok(id)
     }
 
-    pub async fn write_to_bucket(blob: &[u8]) -> Result<(Principal, u64), String> {
-        let id = Storage::allocate_space().await?;
-        let response = canisters::call_canister_raw(id, "write", blob)
-            .await
-            .map_err(|err| format!("couldn't call write on a bucket: {:?}", err))?;
-        let mut offset_bytes: [u8; 8] = Default::default();
-        offset_bytes.copy_from_slice(&response);
-        let offset = u64::from_be_bytes(offset_bytes);
-        mutate(|state| state.storage.buckets.insert(id, offset + blob.len() as u64));
-        Ok((id, offset))
+    /// Stores the blob unless an identical blob is stored already and returns its location. New
+    /// blobs are replicated to another bucket if the replication is enabled.
+    pub async fn store_blob(blob: &[u8]) -> Result<(Principal, u64), String> {
+        let hash = hash(blob);
+        let len = blob.len() as u64;
+        let (bucket_id, offset) = match mutate(|state| state.storage.place(&hash, len)) {
+            Placement::Existing(bucket_id, offset) => return Ok((bucket_id, offset)),
+            Placement::Free(bucket_id, offset) => {
+                if let Err(err) = Storage::write_at_offset(bucket_id, offset, blob).await {
+                    mutate(|state| state.storage.free(bucket_id, offset, len));
+                    return Err(err);
+                }
+                (bucket_id, offset)
+            }
+            Placement::New => {
+                let bucket_id = Storage::allocate_space(None).await?;
+                let offset = Storage::write_new(bucket_id, blob, &hash).await?;
+                (bucket_id, offset)
+            }
+        };
+        let location = mutate(|state| state.storage.add(hash.clone(), bucket_id, offset, len));
+        // a failed replication is retried by the verification
+        if CONFIG.replicate_blobs && location == (bucket_id, offset) {
+            if let Err(err) = Storage::replicate(&hash, bucket_id, blob).await {
+                mutate(|state| {
+                    state
+                        .logger
+                        .error(format!("couldn't replicate blob {}: {}", hash, err))
+                });
+            }
+        }
+        Ok(location)
+    }
+
+    // Writes a copy of the blob to a bucket other than the one storing the blob.
+    async fn replicate(hash: &str, bucket_id: Principal, blob: &[u8]) -> Result<(), String> {
+        let replica_bucket_id = Storage::allocate_space(Some(bucket_id)).await?;
+        let offset = Storage::write_new(replica_bucket_id, blob, hash).await?;
+        mutate(|state| {
+            state
+                .storage
+                .set_replica(hash, replica_bucket_id, offset, blob.len() as u64)
+        });
+        Ok(())
+    }
+
+    // Returns the location of an identical blob or reserves a free range for the new blob.
+    fn place(&mut self, hash: &str, len: u64) -> Placement {
+        if let Some(blob) = self.blobs.get_mut(hash) {
+            blob.references += 1;
+            return Placement::Existing(blob.bucket, blob.offset);
+        }
+        let range = self
+            .free_ranges
+            .iter()
+            .flat_map(|(bucket_id, ranges)| {
+                ranges
+                    .iter()
+                    .map(move |(offset, size)| (*size, *bucket_id, *offset))
+            })
+            .filter(|(size, _, _)| *size >= len)
+            .min();
+        match range {
+            Some((size, bucket_id, offset)) => {
+                let ranges = self
+                    .free_ranges
+                    .get_mut(&bucket_id)
+                    .expect("no ranges found");
+                ranges.remove(&offset);
+                if size > len {
+                    ranges.insert(offset + len, size - len);
+                }
+                if ranges.is_empty() {
+                    self.free_ranges.remove(&bucket_id);
+                }
+                Placement::Free(bucket_id, offset)
+            }
+            None => Placement::New,
+        }
+    }
+
+    // Registers a newly written blob. If an identical blob was stored in the meantime, the new
+    // copy becomes garbage.
+    fn add(
+        &mut self,
+        hash: String,
+        bucket_id: Principal,
+        offset: u64,
+        len: u64,
+    ) -> (Principal, u64) {
+        if let Some(blob) = self.blobs.get_mut(&hash) {
+            blob.references += 1;
+            self.garbage.push((bucket_id, offset, len));
+            return (blob.bucket, blob.offset);
+        }
+        self.locations.insert((bucket_id, offset), hash.clone());
+        self.blobs.insert(
+            hash,
+            StoredBlob {
+                bucket: bucket_id,
+                offset,
+                len,
+                references: 1,
+                replica: None,
+            },
+        );
+        (bucket_id, offset)
+    }
+
+    /// Drops a reference of a post file to its blob. Unreferenced blobs become garbage. Blobs
+    /// stored before the introduction of content addressing are referenced by one file only.
+    pub fn release(&mut self, key: &str, (offset, len): (u64, usize)) {
+        let bucket_id = match key
+            .split('@')
+            .nth(1)
+            .and_then(|id| Principal::from_text(id).ok())
+        {
+            Some(id) => id,
+            None => return,
+        };
+        if let Some(hash) = self.locations.get(&(bucket_id, offset)) {
+            let blob = self.blobs.get_mut(hash).expect("no blob found");
+            blob.references = blob.references.saturating_sub(1);
+            if blob.references > 0 {
+                return;
+            }
+            if let Some((replica_bucket_id, replica_offset)) = blob.replica {
+                self.garbage
+                    .push((replica_bucket_id, replica_offset, len as u64));
+            }
+            self.blobs.remove(hash);
+            self.locations.remove(&(bucket_id, offset));
+        }
+        self.garbage.push((bucket_id, offset, len as u64));
+    }
+
+    /// Returns the location of the replica of the blob stored at the given location.
+    pub fn replica(&self, bucket_id: Principal, offset: u64) -> Option<(Principal, u64)> {
+        self.locations
+            .get(&(bucket_id, offset))
+            .and_then(|hash| self.blobs.get(hash))
+            .and_then(|blob| blob.replica)
+    }
+
+    // Registers a newly written replica. If the blob was released in the meantime, the replica
+    // becomes garbage.
+    fn set_replica(&mut self, hash: &str, bucket_id: Principal, offset: u64, len: u64) {
+        match self.blobs.get_mut(hash) {
+            Some(blob) if blob.replica.is_none() => blob.replica = Some((bucket_id, offset)),
+            _ => self.garbage.push((bucket_id, offset, len)),
+        }
+    }
+
+    // Returns true if the blob with the given hash is still stored at the locations of the blob.
+    fn stored_at(&self, hash: &str, blob: &StoredBlob) -> bool {
+        matches!(self.blobs.get(hash), Some(stored) if stored.bucket == blob.bucket
+            && stored.offset == blob.offset
+            && stored.replica == blob.replica)
+    }
+
+    // Returns up to `max_blobs` blobs following the last verified one. Once all blobs are
+    // verified, the verification starts over.
+    fn blobs_to_verify(&mut self, max_blobs: usize) -> Vec<(String, StoredBlob)> {
+        let blobs = self
+            .blobs
+            .range::<str, _>((
+                Bound::Excluded(self.verification_cursor.as_str()),
+                Bound::Unbounded,
+            ))
+            .take(max_blobs)
+            .map(|(hash, blob)| (hash.clone(), blob.clone()))
+            .collect::<Vec<_>>();
+        self.verification_cursor = match blobs.last() {
+            Some((hash, _)) if blobs.len() == max_blobs => hash.clone(),
+            _ => Default::default(),
+        };
+        blobs
+    }
+
+    /// Verifies the next `max_blobs` blobs against their hashes. Blobs without a replica get
+    /// replicated to another bucket if the replication is enabled; a corrupted or lost copy is
+    /// restored from the intact one.
+    pub async fn verify_blobs(max_blobs: usize) {
+        let blobs = mutate(|state| state.storage.blobs_to_verify(max_blobs));
+        if blobs.is_empty() {
+            return;
+        }
+        let (mut intact, mut replicated, mut repaired) = (0, 0, 0);
+        for (hash, blob) in blobs {
+            match Storage::verify_blob(&hash, &blob).await {
+                Ok(Verification::Intact) => intact += 1,
+                Ok(Verification::Replicated) => replicated += 1,
+                Ok(Verification::Repaired) => repaired += 1,
+                Err(err) => mutate(|state| {
+                    state
+                        .logger
+                        .error(format!("couldn't verify blob {}: {}", hash, err))
+                }),
+            }
+        }
+        mutate(|state| {
+            state.logger.info(format!(
+                "Blob verification: `{}` intact, `{}` replicated, `{}` repaired.",
+                intact, replicated, repaired
+            ))
+        });
+    }
+
+    async fn verify_blob(hash: &str, blob: &StoredBlob) -> Result<Verification, String> {
+        let intact_copy =
+            |bytes: Result<Vec<u8>, String>| bytes.ok().filter(|bytes| self::hash(bytes) == hash);
+        let primary =
+            intact_copy(Storage::read_from_bucket(blob.bucket, blob.offset, blob.len).await);
+        let (replica_bucket_id, replica_offset) = match (blob.replica, &primary) {
+            (Some(replica), _) => replica,
+            (None, Some(bytes)) if CONFIG.replicate_blobs => {
+                Storage::replicate(hash, blob.bucket, bytes).await?;
+                return Ok(Verification::Replicated);
+            }
+            (None, Some(_)) => return Ok(Verification::Intact),
+            (None, None) => return Err("the blob is corrupted or unavailable".into()),
+        };
+        let replica = intact_copy(
+            Storage::read_from_bucket(replica_bucket_id, replica_offset, blob.len).await,
+        );
+        // The blob could have been released and its ranges reused by other blobs while the copies
+        // were read, so the locations are checked again right before a repair.
+        let moved = || mutate(|state| !state.storage.stored_at(hash, blob));
+        match (primary, replica) {
+            (Some(_), Some(_)) => Ok(Verification::Intact),
+            (Some(_), None) | (None, Some(_)) if moved() => {
+                Err("the blob was released during the verification".into())
+            }
+            (Some(bytes), None) => {
+                Storage::write_at_offset(replica_bucket_id, replica_offset, &bytes).await?;
+                Ok(Verification::Repaired)
+            }
+            (None, Some(bytes)) => {
+                Storage::write_at_offset(blob.bucket, blob.offset, &bytes).await?;
+                Ok(Verification::Repaired)
+            }
+            (None, None) => Err("all copies of the blob are corrupted or unavailable".into()),
+        }
+    }
+
+    /// Returns the state of the bucket: the hash of its WASM module or the state of its upgrade.
+    pub fn bucket_status(&self, bucket_id: Principal) -> String {
+        match &self.upgrade {
+            Some(BucketUpgrade {
+                failure: Some((id, err)),
+                ..
+            }) if *id == bucket_id => return format!("upgrade failed: {}", err),
+            Some(upgrade) if upgrade.pending.contains(&bucket_id) => {
+                return "upgrade pending".into()
+            }
+            _ => {}
+        }
+        self.wasm_hashes
+            .get(&bucket_id)
+            .map(|hash| hash.chars().take(8).collect())
+            .unwrap_or_else(|| "unknown".into())
+    }
+
+    // Starts a rollout of the WASM module with the given hash to all buckets running a different
+    // module, unless this module is being rolled out already.
+    fn plan_upgrade(&mut self, wasm_hash: &str) {
+        if matches!(&self.upgrade, Some(upgrade) if upgrade.wasm_hash == wasm_hash) {
+            return;
+        }
+        let pending = self
+            .buckets
+            .keys()
+            .filter(|id| self.wasm_hashes.get(id).map(String::as_str) != Some(wasm_hash))
+            .cloned()
+            .collect::<Vec<_>>();
+        self.upgrade = (!pending.is_empty()).then(|| BucketUpgrade {
+            wasm_hash: wasm_hash.into(),
+            pending,
+            ..Default::default()
+        });
+    }
+
+    // Returns the buckets to upgrade in the next stage: the first stage upgrades a single
+    // bucket, every following one up to `stage_size` buckets.
+    fn upgrade_stage(&self, stage_size: usize) -> Vec<Principal> {
+        match &self.upgrade {
+            Some(upgrade) if upgrade.failure.is_none() => upgrade
+                .pending
+                .iter()
+                .take(if upgrade.upgraded == 0 { 1 } else { stage_size })
+                .cloned()
+                .collect(),
+            _ => Default::default(),
+        }
+    }
+
+    // Records the result of a bucket upgrade and returns true if the rollout can continue.
+    fn record_upgrade(
+        &mut self,
+        bucket_id: Principal,
+        installed: bool,
+        result: Result<(), String>,
+    ) -> bool {
+        let upgrade = match self.upgrade.as_mut() {
+            Some(upgrade) => upgrade,
+            None => return false,
+        };
+        if installed {
+            self.wasm_hashes
+                .insert(bucket_id, upgrade.wasm_hash.clone());
+        }
+        if let Err(err) = result {
+            upgrade.failure = Some((bucket_id, err));
+            return false;
+        }
+        upgrade.pending.retain(|id| *id != bucket_id);
+        upgrade.upgraded += 1;
+        if upgrade.pending.is_empty() {
+            self.upgrade = None;
+        }
+        true
+    }
+
+    // Checks that the bucket responds and serves intact blobs.
+    async fn health_check(bucket_id: Principal) -> Result<(), String> {
+        bytes_to_u64(&call_bucket(bucket_id, "balance", &[]).await?)?;
+        let test_blob = read(|state| {
+            state.storage.blobs.iter().find_map(|(hash, blob)| {
+                let location = if blob.bucket == bucket_id {
+                    Some(blob.offset)
+                } else {
+                    blob.replica
+                        .and_then(|(id, offset)| (id == bucket_id).then_some(offset))
+                };
+                location
+                    .filter(|_| blob.len <= UPLOAD_CHUNK as u64)
+                    .map(|offset| (hash.clone(), offset, blob.len))
+            })
+        });
+        match test_blob {
+            Some((hash, offset, len)) => {
+                if self::hash(&Storage::read_from_bucket(bucket_id, offset, len).await?) != hash {
+                    return Err("test read returned a corrupted blob".into());
+                }
+            }
+            // without blobs, the pointer of the bucket is read
+            None => {
+                if bytes_to_u64(&Storage::read_from_bucket(bucket_id, 0, 8).await?)? < 8 {
+                    return Err("test read returned an invalid pointer".into());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs the next stage of the rollout of the current bucket WASM module. Every upgraded
+    /// bucket is health-checked; the rollout stops at the first failure.
+    pub async fn upgrade_buckets() {
+        let wasm_hash = hash(BUCKET_WASM_GZ);
+        let stage = mutate(|state| {
+            state.storage.plan_upgrade(&wasm_hash);
+            state
+                .storage
+                .upgrade_stage(CONFIG.bucket_upgrade_stage_size)
+        });
+        for id in stage {
+            let (installed, result) =
+                match canisters::install(id, BUCKET_WASM_GZ, CanisterInstallMode::Upgrade).await {
+                    Ok(()) => (true, Storage::health_check(id).await),
+                    Err(err) => (false, Err(err)),
+                };
+            let proceed = mutate(|state| {
+                let proceed = state.storage.record_upgrade(id, installed, result.clone());
+                match result {
+                    Ok(()) => state.logger.info(format!("Bucket {} upgraded.", id)),
+                    Err(err) => state.logger.error(format!(
+                        "Upgrade of bucket {} failed, the rollout is stopped: {}",
+                        id, err
+                    )),
+                }
+                if state.storage.upgrade.is_none() {
+                    state.logger.info("All buckets upgraded. 🪣");
+                }
+                proceed
+            });
+            if !proceed {
+                break;
+            }
+        }
     }
-}
 
-#[allow(dead_code)]
-pub async fn upgrade_buckets() {
-    for id in read(|state| state.storage.buckets.keys().cloned().collect::<Vec<_>>()) {
-        if let Err(err) = canisters::install(id, BUCKET_WASM_GZ, CanisterInstallMode::Upgrade).await
+    /// Turns the ranges of uploads abandoned, e.g. by an upgrade, into garbage.
+    pub fn expire_uploads(&mut self, now: u64) {
+        let expired = self
+            .uploads
+            .iter()
+            .filter(|(_, (_, started))| started + UPLOAD_TTL <= now)
+            .map(|((bucket_id, offset), (len, _))| (*bucket_id, *offset, *len))
+            .collect::<Vec<_>>();
+        for (bucket_id, offset, len) in expired {
+            self.uploads.remove(&(bucket_id, offset));
+            self.garbage.push((bucket_id, offset, len));
+        }
+    }
+
+    // Adds the range to the free ranges, merging it with adjacent ones.
+    fn free(&mut self, bucket_id: Principal, mut offset: u64, mut len: u64) {
+        let ranges = self.free_ranges.entry(bucket_id).or_default();
+        if let Some((left_offset, left_len)) = ranges
+            .range(..offset)
+            .last()
+            .map(|(offset, len)| (*offset, *len))
         {
-            mutate(|state| {
-                state
-                    .logger
-                    .error(format!("couldn't upgrade bucket {}: {}", id, err))
+            if left_offset + left_len == offset {
+                ranges.remove(&left_offset);
+                offset = left_offset;
+                len += left_len;
+            }
+        }
+        if let Some(right_len) = ranges.remove(&(offset + len)) {
+            len += right_len;
+        }
+        ranges.insert(offset, len);
+    }
+
+    /// Erases up to `max_ranges` unreferenced blobs in the buckets and makes their ranges
+    /// available for new blobs.
+    pub async fn collect_garbage(max_ranges: usize) {
+        let ranges = mutate(|state| {
+            let garbage = &mut state.storage.garbage;
+            garbage
+                .drain(..max_ranges.min(garbage.len()))
+                .collect::<Vec<_>>()
+        });
+        if ranges.is_empty() {
+            return;
+        }
+        let mut erased = 0;
+        for (bucket_id, offset, len) in ranges {
+            let result = Storage::write_at_offset(bucket_id, offset, &vec![0; len as usize]).await;
+            mutate(|state| match result {
+                Ok(_) => {
+                    state.storage.free(bucket_id, offset, len);
+                    erased += 1;
+                }
+                Err(err) => {
+                    state.storage.garbage.push((bucket_id, offset, len));
+                    state.logger.error(format!(
+                        "couldn't erase a blob in bucket {}: {}",
+                        bucket_id, err
+                    ));
+                }
             });
+        }
+        mutate(|state| {
+            state
+                .logger
+                .info(format!("`{}` unreferenced blobs erased.", erased))
+        });
+    }
+
+    // Overwrites the bucket memory at the given offset in chunks.
+    async fn write_at_offset(bucket_id: Principal, offset: u64, blob: &[u8]) -> Result<(), String> {
+        for (i, chunk) in blob.chunks(UPLOAD_CHUNK).enumerate() {
+            let mut args = (offset + (i * UPLOAD_CHUNK) as u64).to_be_bytes().to_vec();
+            args.extend_from_slice(chunk);
+            call_bucket(bucket_id, "write_at_offset", &args).await?;
+        }
+        Ok(())
+    }
+
+    // Reads a blob from the bucket in chunks.
+    async fn read_from_bucket(
+        bucket_id: Principal,
+        offset: u64,
+        len: u64,
+    ) -> Result<Vec<u8>, String> {
+        let mut blob = Vec::with_capacity(len as usize);
+        while (blob.len() as u64) < len {
+            let position = offset + blob.len() as u64;
+            let mut args = position.to_be_bytes().to_vec();
+            args.extend_from_slice(
+                &(UPLOAD_CHUNK as u64)
+                    .min(len - blob.len() as u64)
+                    .to_be_bytes(),
+            );
+            blob.extend(call_bucket(bucket_id, "read", &args).await?);
+        }
+        Ok(blob)
+    }
+
+    // Appends a new blob to the bucket and returns its offset.
+    async fn write_new(bucket_id: Principal, blob: &[u8], hash: &str) -> Result<u64, String> {
+        if blob.len() > UPLOAD_CHUNK {
+            Storage::upload_to_bucket(bucket_id, blob, hash).await
+        } else {
+            Storage::write_to_bucket(bucket_id, blob).await
+        }
+    }
+
+    async fn write_to_bucket(id: Principal, blob: &[u8]) -> Result<u64, String> {
+        let offset = bytes_to_u64(&call_bucket(id, "write", blob).await?)?;
+        mutate(|state| state.storage.buckets.insert(id, offset + blob.len() as u64));
+        Ok(offset)
+    }
+
+    // Uploads a large blob to a bucket in chunks. The bucket verifies the hash of the blob before
+    // committing the upload. The range of a failed or expired upload becomes garbage.
+    async fn upload_to_bucket(id: Principal, blob: &[u8], hash: &str) -> Result<u64, String> {
+        let len = blob.len() as u64;
+        let mut args = len.to_be_bytes().to_vec();
+        args.extend(hex::decode(hash).map_err(|err| err.to_string())?);
+        let offset = bytes_to_u64(&call_bucket(id, "begin_upload", &args).await?)?;
+        mutate(|state| {
+            state.storage.buckets.insert(id, offset + len);
+            state.storage.uploads.insert((id, offset), (len, time()));
+        });
+        let mut result = Ok(());
+        for (i, chunk) in blob.chunks(UPLOAD_CHUNK).enumerate() {
+            let mut args = offset.to_be_bytes().to_vec();
+            args.extend_from_slice(&((i * UPLOAD_CHUNK) as u64).to_be_bytes());
+            args.extend_from_slice(chunk);
+            result = call_bucket(id, "append_upload", &args).await.map(|_| ());
+            if result.is_err() {
+                break;
+            }
+        }
+        if result.is_ok() {
+            result = call_bucket(id, "commit_upload", &offset.to_be_bytes())
+                .await
+                .map(|_| ());
+        }
+        // an upload which expired in the meantime is garbage already
+        let expired = mutate(|state| {
+            let expired = state.storage.uploads.remove(&(id, offset)).is_none();
+            if result.is_err() && !expired {
+                state.storage.garbage.push((id, offset, len));
+            }
+            expired
+        });
+        if expired {
+            return Err("upload expired".into());
+        }
+        result.map(|_| offset)
+    }
+}
+
+async fn call_bucket(bucket_id: Principal, method: &str, args: &[u8]) -> Result<Vec<u8>, String> {
+    canisters::call_canister_raw(bucket_id, method, args)
+        .await
+        .map_err(|err| format!("couldn't call {} on a bucket: {:?}", method, err))
+}
+
+fn bytes_to_u64(bytes: &[u8]) -> Result<u64, String> {
+    let mut arr: [u8; 8] = Default::default();
+    if bytes.len() != arr.len() {
+        return Err("unexpected response from a bucket".into());
+    }
+    arr.copy_from_slice(bytes);
+    Ok(u64::from_be_bytes(arr))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        env::tests::{create_user, pr},
+        env::Post,
+        STATE,
+    };
+
+    #[test]
+    fn test_blobs() {
+        let mut storage = Storage::default();
+        let bucket = pr(7);
+        let key = |id: &str| format!("{}@{}", id, bucket);
+
+        // identical blobs are stored once
+        assert_eq!(storage.place("a", 10), Placement::New);
+        assert_eq!(storage.add("a".into(), bucket, 8, 10), (bucket, 8));
+        assert_eq!(storage.place("a", 10), Placement::Existing(bucket, 8));
+        assert_eq!(storage.blobs.get("a").unwrap().references, 2);
+        // a concurrently written copy becomes garbage
+        assert_eq!(storage.add("a".into(), bucket, 18, 10), (bucket, 8));
+        assert_eq!(storage.garbage, vec![(bucket, 18, 10)]);
+        storage.garbage.clear();
+
+        // blobs become garbage once all references are released
+        storage.release(&key("x"), (8, 10));
+        storage.release(&key("y"), (8, 10));
+        assert_eq!(storage.blobs.get("a").unwrap().references, 1);
+        assert!(storage.garbage.is_empty());
+        storage.release(&key("z"), (8, 10));
+        assert!(storage.blobs.is_empty());
+        assert_eq!(storage.garbage, vec![(bucket, 8, 10)]);
+        // legacy blobs are released right away
+        storage.release(&key("w"), (28, 4));
+        assert_eq!(storage.garbage.len(), 2);
+
+        // erased ranges get merged and reused
+        storage.free(bucket, 8, 10);
+        storage.free(bucket, 28, 4);
+        storage.free(bucket, 18, 10);
+        assert_eq!(
+            storage.free_ranges.get(&bucket).unwrap(),
+            &vec![(8, 24)].into_iter().collect::<BTreeMap<_, _>>()
+        );
+        assert_eq!(storage.place("b", 30), Placement::New);
+        assert_eq!(storage.place("b", 20), Placement::Free(bucket, 8));
+        assert_eq!(storage.place("c", 4), Placement::Free(bucket, 28));
+        assert!(storage.free_ranges.is_empty());
+
+        // abandoned uploads become garbage
+        storage.garbage.clear();
+        storage.uploads.insert((bucket, 100), (50, 0));
+        storage.expire_uploads(UPLOAD_TTL - 1);
+        assert!(storage.garbage.is_empty());
+        storage.expire_uploads(UPLOAD_TTL);
+        assert!(storage.uploads.is_empty());
+        assert_eq!(storage.garbage, vec![(bucket, 100, 50)]);
+    }
+
+    #[test]
+    fn test_replicas() {
+        let mut storage = Storage::default();
+        let (bucket, other) = (pr(7), pr(8));
+        for (i, hash) in ["a", "b", "c"].iter().enumerate() {
+            storage.add(hash.to_string(), bucket, 8 + i as u64 * 10, 10);
+        }
+
+        // replicas are registered only for stored blobs
+        storage.set_replica("a", other, 8, 10);
+        assert_eq!(storage.replica(bucket, 8), Some((other, 8)));
+        storage.set_replica("d", other, 18, 10);
+        assert_eq!(storage.replica(bucket, 18), None);
+        assert_eq!(storage.garbage, vec![(other, 18, 10)]);
+        storage.garbage.clear();
+
+        // blobs are stored at their locations until they are released
+        let blob = storage.blobs.get("a").unwrap().clone();
+        storage.place("a", 10);
+        storage.release(&format!("y@{}", bucket), (8, 10));
+        assert!(storage.stored_at("a", &blob));
+
+        // replicas are released together with their blobs
+        storage.release(&format!("x@{}", bucket), (8, 10));
+        assert_eq!(storage.replica(bucket, 8), None);
+        assert!(!storage.stored_at("a", &blob));
+        assert_eq!(storage.garbage, vec![(other, 8, 10), (bucket, 8, 10)]);
+
+        // the verification cycles through all blobs
+        let hashes = |blobs: Vec<(String, StoredBlob)>| {
+            blobs.into_iter().map(|(hash, _)| hash).collect::<Vec<_>>()
         };
+        assert_eq!(hashes(storage.blobs_to_verify(1)), vec!["b"]);
+        assert_eq!(hashes(storage.blobs_to_verify(1)), vec!["c"]);
+        assert!(storage.blobs_to_verify(1).is_empty());
+        assert_eq!(hashes(storage.blobs_to_verify(5)), vec!["b", "c"]);
+        assert_eq!(hashes(storage.blobs_to_verify(5)), vec!["b", "c"]);
+    }
+
+    #[test]
+    fn test_bucket_upgrades() {
+        let mut storage = Storage::default();
+        let buckets = (0..4).map(|i| pr(10 + i)).collect::<Vec<_>>();
+        for id in &buckets {
+            storage.buckets.insert(*id, 0);
+        }
+        storage.wasm_hashes.insert(buckets[3], "new".into());
+
+        // buckets running the new module are not upgraded
+        storage.plan_upgrade("new");
+        let upgrade = storage.upgrade.clone().unwrap();
+        assert_eq!(upgrade.pending, buckets[..3].to_vec());
+        assert_eq!(storage.bucket_status(buckets[0]), "upgrade pending");
+        assert_eq!(storage.bucket_status(buckets[3]), "new");
+
+        // the first stage upgrades one bucket
+        assert_eq!(storage.upgrade_stage(2), vec![buckets[0]]);
+        assert!(storage.record_upgrade(buckets[0], true, Ok(())));
+        assert_eq!(storage.upgrade_stage(2), vec![buckets[1], buckets[2]]);
+        // planning the same module again doesn't restart the rollout
+        storage.plan_upgrade("new");
+        assert_eq!(storage.upgrade.as_ref().unwrap().upgraded, 1);
+
+        // the rollout stops at a failure
+        assert!(!storage.record_upgrade(buckets[1], true, Err("no response".into())));
+        assert!(storage.upgrade_stage(2).is_empty());
+        assert_eq!(
+            storage.bucket_status(buckets[1]),
+            "upgrade failed: no response"
+        );
+        storage.plan_upgrade("new");
+        assert!(storage.upgrade_stage(2).is_empty());
+
+        // a new module starts a new rollout
+        storage.plan_upgrade("newer");
+        assert_eq!(storage.upgrade_stage(2), vec![buckets[0]]);
+        for id in &buckets {
+            assert!(storage.record_upgrade(*id, true, Ok(())));
+        }
+        assert!(storage.upgrade.is_none());
+        assert_eq!(storage.bucket_status(buckets[2]), "newer");
+    }
+
+    #[test]
+    fn test_deleted_posts_release_blobs() {
+        STATE.with(|cell| {
+            cell.replace(Default::default());
+            let state = &mut *cell.borrow_mut();
+            create_user(state, pr(0));
+            let bucket = pr(7);
+            state.storage.add("a".into(), bucket, 8, 10);
+            state.storage.place("a", 10);
+            for _ in 0..2 {
+                let post_id =
+                    Post::create(state, "Hello".into(), &[], pr(0), 0, None, None, None).unwrap();
+                Post::mutate(state, &post_id, |post| {
+                    post.files.insert(format!("img@{}", bucket), (8, 10));
+                    Ok(())
+                })
+                .unwrap();
+            }
+
+            Post::mutate(state, &0, |post| {
+                post.delete(vec![String::new()]);
+                Ok(())
+            })
+            .unwrap();
+            assert_eq!(state.storage.blobs.get("a").unwrap().references, 1);
+            Post::mutate(state, &1, |post| {
+                post.delete(vec![String::new()]);
+                Ok(())
+            })
+            .unwrap();
+            assert!(state.storage.blobs.is_empty());
+            assert_eq!(state.storage.garbage, vec![(bucket, 8, 10)]);
+        })
     }
-    mutate(|state| {
-        state
-            .logger
-            .info("Successfully upgraded all storage buckets.")
-    });
 }
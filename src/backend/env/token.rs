@@ -28,7 +28,7 @@ pub struct TransferArgs {
     created_at_time: Option<Timestamp>,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Transaction {
     pub timestamp: u64,
     pub from: Account,
@@ -248,15 +248,20 @@ fn transfer(
             .balances
             .insert(to.clone(), recipient_balance + amount as Token);
     }
-    state.ledger.push(Transaction {
-        timestamp: now,
-        from,
-        to,
-        amount: amount as Token,
-        fee: effective_fee,
-        memo,
-    });
-    Ok(state.ledger.len().saturating_sub(1) as u128)
+    let id = state.ledger.len() as u64;
+    index_transaction(state, id, &from, &to);
+    state.ledger.insert(
+        id,
+        Transaction {
+            timestamp: now,
+            from,
+            to,
+            amount: amount as Token,
+            fee: effective_fee,
+            memo,
+        },
+    );
+    Ok(id as u128)
 }
 
 pub fn account(owner: Principal) -> Account {
@@ -306,6 +311,60 @@ pub fn move_funds(state: &mut State, from: &Account, to: Account) -> Result<u128
     Ok(n)
 }
 
+/// Adds the transaction to the transaction lists of its sender and recipient.
+pub fn index_transaction(state: &mut State, id: u64, from: &Account, to: &Account) {
+    for owner in [from.owner, to.owner].iter().collect::<BTreeSet<_>>() {
+        state
+            .principal_transactions
+            .entry(*owner)
+            .or_default()
+            .insert(id);
+    }
+}
+
+/// Returns the transactions of the principal, newest first.
+pub fn principal_transactions(
+    state: &State,
+    principal: Principal,
+) -> impl Iterator<Item = (u64, Cow<'_, Transaction>)> {
+    state
+        .principal_transactions
+        .get(&principal)
+        .into_iter()
+        .flat_map(|ids| ids.iter().rev())
+        .filter_map(move |id| Some((*id, state.ledger.get(id)?)))
+}
+
+/// Moves the oldest transactions from the heap to the stable memory until at most
+/// `max_transactions_in_heap` transactions remain in the heap. Since transactions are archived in
+/// the order of their ids, the archived transactions always form a prefix of the ledger.
+pub fn archive_transactions(state: &mut State, max_transactions_in_heap: usize) {
+    let ids = state
+        .ledger
+        .iter()
+        .map(|(id, _)| *id)
+        .take(
+            state
+                .ledger
+                .len()
+                .saturating_sub(state.ledger.archived() + max_transactions_in_heap)
+                .min(max_transactions_in_heap),
+        )
+        .collect::<Vec<_>>();
+    if ids.is_empty() {
+        return;
+    }
+    for id in &ids {
+        state
+            .ledger
+            .archive(id, None)
+            .expect("couldn't archive transaction");
+    }
+    state
+        .logger
+        .info(format!("`{}` transactions archived.", ids.len()));
+}
+
 pub fn user_transfer(recipient: String, amount: String) -> Result<u64, String> {
     let minted_supply: Token = read(|state| state.balances.values().sum());
 
@@ -424,12 +483,15 @@ mod tests {
         state.balances.insert(account(pr(0)), 1000);
 
         // Create an open proposal with a pending vote
-        state.proposals.push(Proposal {
-            proposer: 0,
-            bulletins: vec![(0, true, 1)],
-            status: Status::Open,
-            ..Default::default()
-        });
+        state.proposals.insert(
+            0,
+            Proposal {
+                proposer: 0,
+                bulletins: vec![(0, true, 1)],
+                status: Status::Open,
+                ..Default::default()
+            },
+        );
 
         assert_eq!(
             transfer(
@@ -451,7 +513,7 @@ mod tests {
             })),
         );
 
-        state.proposals.clear();
+        state.proposals = Default::default();
 
         assert_eq!(
             transfer(
@@ -512,26 +574,46 @@ mod tests {
                 balance: 8
             }))
         );
+
+        let ids = |principal| {
+            principal_transactions(&state, principal)
+                .map(|(id, _)| id)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(ids(pr(0)), vec![1, 0]);
+        assert_eq!(ids(pr(1)), vec![0]);
+        assert!(ids(pr(2)).is_empty());
     }
 }
 
-pub fn balances_from_ledger(ledger: &[Transaction]) -> Result<HashMap<Account, Token>, String> {
+pub fn balances_from_ledger<T: std::ops::Deref<Target = Transaction>>(
+    ledger: impl Iterator<Item = T>,
+) -> Result<HashMap<Account, Token>, String> {
     let mut balances = HashMap::new();
-    let minting_account = icrc1_minting_account().ok_or("no minting account found")?;
     for transaction in ledger {
-        balances
-            .entry(transaction.to.clone())
-            .and_modify(|balance| *balance += transaction.amount)
-            .or_insert(transaction.amount);
-        if transaction.from != minting_account {
-            let from = balances
-                .get_mut(&transaction.from)
-                .ok_or("paying account not found")?;
-            if transaction.amount + transaction.fee > *from {
-                return Err("account has not enough funds".into());
-            }
-            *from -= transaction.amount + transaction.fee;
-        }
+        apply_transaction(&mut balances, &transaction)?;
     }
     Ok(balances)
 }
+
+/// Applies the transaction to the balances recomputed from the ledger.
+pub fn apply_transaction(
+    balances: &mut HashMap<Account, Token>,
+    transaction: &Transaction,
+) -> Result<(), String> {
+    let minting_account = icrc1_minting_account().ok_or("no minting account found")?;
+    balances
+        .entry(transaction.to.clone())
+        .and_modify(|balance| *balance += transaction.amount)
+        .or_insert(transaction.amount);
+    if transaction.from != minting_account {
+        let from = balances
+            .get_mut(&transaction.from)
+            .ok_or("paying account not found")?;
+        if transaction.amount + transaction.fee > *from {
+            return Err("account has not enough funds".into());
+        }
+        *from -= transaction.amount + transaction.fee;
+    }
+    Ok(())
+}
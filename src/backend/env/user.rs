@@ -17,6 +17,136 @@ impl Filters {
     }
 }
 
+// A named, ordered collection of bookmarked posts with optional private notes.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct BookmarkCollection {
+    pub posts: Vec<PostId>,
+    pub notes: BTreeMap<PostId, String>,
+}
+
+// Mutes of users, tags and threads, each mapped to its expiration timestamp (`u64::MAX` for
+// mutes without expiration). Threads are identified by their root post.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct Mutes {
+    pub users: BTreeMap<UserId, u64>,
+    pub tags: BTreeMap<String, u64>,
+    pub threads: BTreeMap<PostId, u64>,
+}
+
+impl Mutes {
+    pub fn is_empty(&self) -> bool {
+        self.users.is_empty() && self.tags.is_empty() && self.threads.is_empty()
+    }
+
+    /// Returns true if the post's author, one of its tags or its thread is muted at `now`.
+    pub fn muted(&self, state: &State, post: &Post, now: u64) -> bool {
+        self.user_muted(post.user, now) || self.content_muted(state, post, now)
+    }
+
+    pub fn user_muted(&self, user_id: UserId, now: u64) -> bool {
+        self.users
+            .get(&user_id)
+            .map(|expiry| *expiry > now)
+            .unwrap_or_default()
+    }
+
+    /// Returns true if one of the post's tags or its thread is muted at `now`.
+    pub fn content_muted(&self, state: &State, post: &Post, now: u64) -> bool {
+        let active = |expiry: Option<&u64>| expiry.map(|expiry| *expiry > now).unwrap_or_default();
+        post.tags
+            .iter()
+            .any(|tag| active(self.tags.get(&tag.to_lowercase())))
+            || !self.threads.is_empty() && {
+                // resolve the root via the parent, since new posts get checked before being saved
+                let root = match post.parent {
+                    Some(parent_id) => state.thread(parent_id).next(),
+                    None => Some(post.id),
+                };
+                root.map(|id| active(self.threads.get(&id)))
+                    .unwrap_or_default()
+            }
+    }
+
+    // Drops expired mutes and returns the users whose mutes expired.
+    pub fn clean_up(&mut self, now: u64) -> Vec<UserId> {
+        let expired = self
+            .users
+            .iter()
+            .filter(|(_, expiry)| **expiry <= now)
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+        self.users.retain(|_, expiry| *expiry > now);
+        self.tags.retain(|_, expiry| *expiry > now);
+        self.threads.retain(|_, expiry| *expiry > now);
+        expired
+    }
+}
+
+// Statistics of a stalwart's votes on reports.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct ModerationStats {
+    pub votes: u32,
+    // votes on closed reports and how many of them matched the outcome
+    pub resolved_votes: u32,
+    pub accurate_votes: u32,
+    // total time between report creations and votes
+    pub response_time: u64,
+    pub abstentions: u32,
+    pub consecutive_abstentions: u32,
+    // resolved votes of the last weeks and whether they matched the outcome
+    #[serde(default)]
+    pub recent_votes: VecDeque<(u64, bool)>,
+}
+
+impl ModerationStats {
+    pub fn accuracy(&self) -> Option<u64> {
+        (self.resolved_votes > 0)
+            .then(|| self.accurate_votes as u64 * 100 / self.resolved_votes as u64)
+    }
+
+    pub fn average_response_time(&self) -> u64 {
+        self.response_time / (self.votes as u64).max(1)
+    }
+
+    // Weight of the moderator's share in the moderation rewards: from 50 to 150 depending on
+    // the accuracy, and 100 for moderators without resolved votes.
+    pub fn reward_weight(&self) -> u64 {
+        self.accuracy().map(|accuracy| 50 + accuracy).unwrap_or(100)
+    }
+
+    pub fn record_vote(&mut self, now: u64, accurate: bool) {
+        self.resolved_votes += 1;
+        if accurate {
+            self.accurate_votes += 1;
+        }
+        self.recent_votes.push_back((now, accurate));
+        self.prune(now);
+    }
+
+    // Drops votes which fell out of the window, so that the accuracy of demoted stalwarts
+    // recovers over time.
+    pub fn prune(&mut self, now: u64) {
+        let window = CONFIG.stalwart_moderation_window_weeks * WEEK;
+        while matches!(self.recent_votes.front(), Some((timestamp, _)) if timestamp + window < now)
+        {
+            self.recent_votes.pop_front();
+        }
+    }
+
+    // Accuracy of the votes within the window.
+    pub fn recent_accuracy(&self) -> Option<u64> {
+        let accurate = self.recent_votes.iter().filter(|(_, accurate)| *accurate);
+        (!self.recent_votes.is_empty())
+            .then(|| accurate.count() as u64 * 100 / self.recent_votes.len() as u64)
+    }
+
+    pub fn poor(&self) -> bool {
+        (self.recent_votes.len() >= CONFIG.stalwart_min_resolved_votes as usize
+            && self.recent_accuracy().unwrap_or(100) < CONFIG.stalwart_min_moderation_accuracy)
+            || self.consecutive_abstentions >= CONFIG.stalwart_max_consecutive_abstentions
+    }
+}
+
 #[derive(PartialEq)]
 pub enum CyclesDelta {
     Plus,
@@ -28,6 +158,9 @@ pub enum Predicate {
     ReportOpen(PostId),
     UserReportOpen(UserId),
     Proposal(PostId),
+    AppealOpen(PostId),
+    UserAppealOpen(UserId),
+    HeldPost(UserId),
 }
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -45,6 +178,13 @@ pub struct Draft {
     pub realm: Option<String>,
     pub extension: Option<Blob>,
     pub blobs: Vec<(String, Blob)>,
+    pub timestamp: u64,
+}
+
+impl Draft {
+    pub fn size(&self) -> usize {
+        self.blobs.iter().map(|(_, blob)| blob.len()).sum()
+    }
 }
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -85,6 +225,33 @@ pub struct User {
     pub karma_donations: BTreeMap<UserId, u32>,
     #[serde(default)]
     pub previous_names: Vec<String>,
+    #[serde(default)]
+    pub moderation: ModerationStats,
+    #[serde(default)]
+    pub mutes: Mutes,
+    // Reverse index of the user mutes: users who muted this user.
+    #[serde(default)]
+    pub muted_by: BTreeSet<UserId>,
+    #[serde(default)]
+    pub bookmark_collections: BTreeMap<String, BookmarkCollection>,
+    // The name of the collection shared publicly.
+    #[serde(default)]
+    pub public_bookmark_collection: Option<String>,
+    #[serde(default)]
+    pub blocked: BTreeSet<UserId>,
+    #[serde(default)]
+    pub guardians: BTreeSet<UserId>,
+    // The number of guardians needed to recover the account.
+    #[serde(default)]
+    pub guardian_threshold: usize,
+    // Delegated principals with scoped permissions.
+    #[serde(default)]
+    pub delegates: BTreeMap<String, delegates::Delegate>,
+    // The time of the account deletion request.
+    #[serde(default)]
+    pub deletion_requested: Option<u64>,
+    #[serde(default)]
+    pub badges: Vec<badges::Badge>,
 }
 
 impl User {
@@ -127,10 +294,36 @@ impl User {
             filters: Default::default(),
             karma_donations: Default::default(),
             previous_names: Default::default(),
+            moderation: Default::default(),
+            mutes: Default::default(),
+            muted_by: Default::default(),
+            bookmark_collections: Default::default(),
+            public_bookmark_collection: None,
+            blocked: Default::default(),
+            guardians: Default::default(),
+            guardian_threshold: 0,
+            delegates: Default::default(),
+            deletion_requested: None,
+            badges: Default::default(),
         }
     }
 
-    pub fn posts<'a>(&'a self, state: &'a State) -> Box<dyn Iterator<Item = &'a Post> + 'a> {
+    /// Wipes all data of the user except for the id, the join date and the number of posts. The
+    /// handle gets replaced by one which can't be registered.
+    pub fn anonymize(&mut self) {
+        let mut user = User::new(
+            Principal::anonymous(),
+            self.id,
+            self.timestamp,
+            format!("deleted_{}", self.id),
+        );
+        user.account.clear();
+        user.num_posts = self.num_posts;
+        user.last_activity = self.last_activity;
+        *self = user;
+    }
+
+    pub fn posts<'a>(&self, state: &'a State) -> Box<dyn Iterator<Item = &'a Post> + 'a> {
         let id = self.id;
         Box::new(
             state
@@ -142,12 +335,132 @@ impl User {
     pub fn toggle_bookmark(&mut self, post_id: PostId) -> bool {
         if self.bookmarks.contains(&post_id) {
             self.bookmarks.retain(|id| id != &post_id);
+            for collection in self.bookmark_collections.values_mut() {
+                collection.posts.retain(|id| id != &post_id);
+                collection.notes.remove(&post_id);
+            }
             return false;
         }
         self.bookmarks.push_front(post_id);
         true
     }
 
+    pub fn create_bookmark_collection(&mut self, name: String) -> Result<(), String> {
+        if name.trim().is_empty() || name.chars().count() > CONFIG.max_bookmark_collection_name {
+            return Err("invalid collection name".into());
+        }
+        if self.bookmark_collections.len() >= CONFIG.max_bookmark_collections {
+            return Err("too many collections".into());
+        }
+        if self.bookmark_collections.contains_key(&name) {
+            return Err("collection exists".into());
+        }
+        self.bookmark_collections.insert(name, Default::default());
+        Ok(())
+    }
+
+    pub fn delete_bookmark_collection(&mut self, name: &str) -> Result<(), String> {
+        self.bookmark_collections
+            .remove(name)
+            .ok_or("no collection found")?;
+        if self.public_bookmark_collection.as_deref() == Some(name) {
+            self.public_bookmark_collection = None;
+        }
+        Ok(())
+    }
+
+    /// Adds the post to the collection (and to bookmarks) or updates its note if the post is
+    /// already in the collection. An empty note removes the existing one.
+    pub fn collect_bookmark(
+        &mut self,
+        name: &str,
+        post_id: PostId,
+        note: String,
+    ) -> Result<(), String> {
+        if note.len() > CONFIG.max_bookmark_note_length {
+            return Err("note too long".into());
+        }
+        let collection = self
+            .bookmark_collections
+            .get_mut(name)
+            .ok_or("no collection found")?;
+        if !collection.posts.contains(&post_id) {
+            if collection.posts.len() >= CONFIG.max_bookmarks_per_collection {
+                return Err("collection is full".into());
+            }
+            collection.posts.push(post_id);
+        }
+        if note.is_empty() {
+            collection.notes.remove(&post_id);
+        } else {
+            collection.notes.insert(post_id, note);
+        }
+        if !self.bookmarks.contains(&post_id) {
+            self.bookmarks.push_front(post_id);
+        }
+        Ok(())
+    }
+
+    pub fn uncollect_bookmark(&mut self, name: &str, post_id: PostId) -> Result<(), String> {
+        let collection = self
+            .bookmark_collections
+            .get_mut(name)
+            .ok_or("no collection found")?;
+        if !collection.posts.contains(&post_id) {
+            return Err("post not in collection".into());
+        }
+        collection.posts.retain(|id| id != &post_id);
+        collection.notes.remove(&post_id);
+        Ok(())
+    }
+
+    /// Moves the post to the given position inside the collection.
+    pub fn move_bookmark(
+        &mut self,
+        name: &str,
+        post_id: PostId,
+        position: usize,
+    ) -> Result<(), String> {
+        let posts = &mut self
+            .bookmark_collections
+            .get_mut(name)
+            .ok_or("no collection found")?
+            .posts;
+        let i = posts
+            .iter()
+            .position(|id| id == &post_id)
+            .ok_or("post not in collection")?;
+        let post_id = posts.remove(i);
+        posts.insert(position.min(posts.len()), post_id);
+        Ok(())
+    }
+
+    /// Makes the given collection public (replacing the previously public one) or makes all
+    /// collections private.
+    pub fn set_public_bookmark_collection(&mut self, name: Option<String>) -> Result<(), String> {
+        if let Some(name) = &name {
+            if !self.bookmark_collections.contains_key(name) {
+                return Err("no collection found".into());
+            }
+        }
+        self.public_bookmark_collection = name;
+        Ok(())
+    }
+
+    /// Returns the name and posts of the public collection, without notes.
+    pub fn public_bookmarks<'a>(&self, state: &'a State) -> Option<(String, Vec<&'a Post>)> {
+        let name = self.public_bookmark_collection.clone()?;
+        let posts = self
+            .bookmark_collections
+            .get(&name)?
+            .posts
+            .iter()
+            .filter_map(|id| Post::get(state, id))
+            .filter(|post| !post.is_deleted())
+            .collect();
+        Some((name, posts))
+    }
+
     pub fn toggle_filter(&mut self, filter: String, value: String) -> Result<(), String> {
         match filter.as_str() {
             "user" => match value.parse() {
@@ -237,6 +550,7 @@ impl User {
                 iterators: iterators.into_iter().map(|i| i.peekable()).collect(),
             }
             .filter(move |post| with_comments || post.parent.is_none())
+            .filter(move |post| !self.mutes.muted(state, post, time()))
             // if the post if from a realm, it's only included if user if part of it
             .filter(move |post| {
                 post.realm
@@ -249,6 +563,167 @@ impl User {
         )
     }
 
+    // Ranks recent posts from followees, their followees, tag feeds and joined realms by their
+    // relevance for the user and by their engagement. The ranking doesn't depend on the time of the
+    // call, so the pages stay consistent until new posts arrive.
+    pub fn for_you_feed<'a>(&'a self, state: &'a State, page: usize) -> Vec<&'a Post> {
+        let candidates = self.for_you_feed_candidates(state);
+        let newest = candidates
+            .iter()
+            .map(|post| post.timestamp())
+            .max()
+            .unwrap_or_default();
+
+        let friends_of_friends = self
+            .followees
+            .iter()
+            .filter_map(|id| state.users.get(id))
+            .flat_map(|user| user.followees.clone())
+            .filter(|id| *id != self.id && !self.followees.contains(id))
+            .collect::<BTreeSet<_>>();
+
+        // authors of posts the user reacted to or bookmarked
+        let mut affinity: BTreeMap<UserId, u64> = Default::default();
+        for post in candidates.iter() {
+            if post
+                .reactions
+                .values()
+                .any(|users| users.contains(&self.id))
+            {
+                *affinity.entry(post.user).or_default() += 1;
+            }
+        }
+        let mut bookmarked_tags = BTreeSet::new();
+        for post in self.bookmarks.iter().filter_map(|id| Post::get(state, id)) {
+            *affinity.entry(post.user).or_default() += 2;
+            bookmarked_tags.extend(post.tags.iter().map(|tag| tag.to_lowercase()));
+        }
+
+        let mut ranked = candidates
+            .into_iter()
+            .filter(|post| post.user != self.id)
+            .filter_map(|post| {
+                let lc_tags: BTreeSet<_> = post.tags.iter().map(|t| t.to_lowercase()).collect();
+                let mut relevance = 0;
+                if self.followees.contains(&post.user) {
+                    relevance += 30;
+                }
+                if friends_of_friends.contains(&post.user) {
+                    relevance += 10;
+                }
+                if covered_by_feeds(&self.feeds, &lc_tags, false).is_some() {
+                    relevance += 20;
+                }
+                match &post.realm {
+                    Some(realm) if self.realms.contains(realm) => relevance += 15,
+                    // posts from realms are only included if the user is part of it
+                    Some(_) => return None,
+                    None => {}
+                }
+                if relevance == 0 {
+                    return None;
+                }
+                relevance += affinity
+                    .get(&post.user)
+                    .copied()
+                    .unwrap_or_default()
+                    .min(10)
+                    * 5;
+                if state
+                    .users
+                    .get(&post.user)
+                    .map(|author| author.karma_donations.contains_key(&self.id))
+                    .unwrap_or_default()
+                {
+                    relevance += 20;
+                }
+                relevance += lc_tags.intersection(&bookmarked_tags).count().min(4) as u64 * 5;
+
+                let engagement = post
+                    .reactions
+                    .values()
+                    .map(|users| users.len())
+                    .sum::<usize>() as u64
+                    + post.tree_size as u64
+                    + 2 * post.tips.len() as u64;
+                // the engagement counts logarithmically
+                let engagement_bits = 64 - engagement.leading_zeros() as u64;
+                let age_hours = newest.saturating_sub(post.timestamp()) / HOUR;
+                let score = (relevance + 10 * engagement_bits) * 1000 / (age_hours + 2);
+                Some((score, post))
+            })
+            .collect::<Vec<_>>();
+        ranked.sort_unstable_by_key(|(score, post)| std::cmp::Reverse((*score, post.id)));
+        ranked
+            .into_iter()
+            .skip(page * CONFIG.feed_page_size)
+            .take(CONFIG.feed_page_size)
+            .map(|(_, post)| post)
+            .collect()
+    }
+
+    // Collects the last posts of every followee, joined realm and tag feed, so that slower sources
+    // are not crowded out by the global activity, plus the last posts of all users for the
+    // followees of followees. All sources share one scan of the last posts, which is capped, so
+    // that the costs don't grow with the history or the number of sources.
+    fn for_you_feed_candidates<'a>(&'a self, state: &'a State) -> Vec<&'a Post> {
+        let per_source = CONFIG.for_you_feed_candidates_per_source;
+        let mut followees = self
+            .followees
+            .iter()
+            .filter(|id| matches!(state.users.get(id), Some(user) if user.num_posts > 0))
+            .map(|id| (*id, 0))
+            .collect::<BTreeMap<_, _>>();
+        let mut realms = self
+            .realms
+            .iter()
+            .map(|realm| (realm, 0))
+            .collect::<BTreeMap<_, _>>();
+        let mut feeds = vec![0; self.feeds.len()];
+        let mut candidates = Vec::new();
+        for (i, post) in state
+            .last_posts(self.principal, None, false)
+            .take(CONFIG.for_you_feed_scan_limit)
+            .enumerate()
+        {
+            let mut include = i < CONFIG.for_you_feed_candidates;
+            let mut take = |count: &mut usize| {
+                if *count < per_source {
+                    *count += 1;
+                    include = true;
+                }
+            };
+            if let Some(count) = followees.get_mut(&post.user) {
+                take(count);
+            }
+            if let Some(count) = post.realm.as_ref().and_then(|realm| realms.get_mut(realm)) {
+                take(count);
+            }
+            if !feeds.is_empty() {
+                let lc_tags: BTreeSet<_> = post.tags.iter().map(|t| t.to_lowercase()).collect();
+                for (feed, count) in self.feeds.iter().zip(feeds.iter_mut()) {
+                    if covered_by_feeds(std::slice::from_ref(feed), &lc_tags, false).is_some() {
+                        take(count);
+                    }
+                }
+            }
+            if include {
+                candidates.push(post);
+            }
+            if i + 1 >= CONFIG.for_you_feed_candidates
+                && followees
+                    .values()
+                    .chain(realms.values())
+                    .chain(feeds.iter())
+                    .all(|count| *count >= per_source)
+            {
+                break;
+            }
+        }
+        candidates.reverse();
+        candidates
+    }
+
     pub fn notify_with_params<T: AsRef<str>>(&mut self, message: T, predicate: Option<Predicate>) {
         self.messages += 1;
         let id = self.messages;
@@ -293,6 +768,22 @@ impl User {
         self.controllers.iter().any(|p| p.len() == 27)
     }
 
+    // Archived users are skipped by all chores iterating over users, so we only archive users
+    // whom these chores have nothing left to do with.
+    fn archivable(&self, now: u64) -> bool {
+        !self.active_within_weeks(now, CONFIG.inactivity_duration_weeks)
+            && self.karma() <= 0
+            && self.karma_to_reward() == 0
+            && self.cycles() == 0
+            && self.treasury_e8s == 0
+            && !self.stalwart
+            && self.report.is_none()
+            && self.controllers.is_empty()
+            && self.delegates.is_empty()
+            && self.guardians.is_empty()
+            && self.deletion_requested.is_none()
+    }
+
     pub fn change_cycles<T: ToString>(
         &mut self,
         amount: Cycles,
@@ -418,7 +909,7 @@ impl User {
                 .users
                 .values()
                 .filter(|user| user.principal != caller)
-                .flat_map(|user| user.controllers.iter())
+                .flat_map(|user| user.controllers.iter().chain(user.delegates.keys()))
                 .collect::<BTreeSet<_>>()
                 .intersection(&principals.iter().collect())
                 .count()
@@ -481,11 +972,118 @@ impl<'a, T: Clone + PartialOrd> Iterator for IteratorMerger<'a, T> {
     }
 }
 
+/// Moves the longest inactive users from the heap to the stable memory until at most
+/// `max_users_in_heap` users remain in the heap. Archived users can still be found by their id,
+/// their principal, their current or previous names.
+pub fn archive_inactive_users(state: &mut State, max_users_in_heap: usize, now: u64) {
+    let mut users = state
+        .users
+        .values()
+        .filter(|user| user.archivable(now))
+        .map(|user| (user.last_activity, user.id))
+        .collect::<Vec<_>>();
+    // We cap the number at `max_users_in_heap` to stay within the instruction limit.
+    let users_to_archive = state
+        .users
+        .len()
+        .saturating_sub(state.users.archived() + max_users_in_heap)
+        .min(max_users_in_heap)
+        .min(users.len());
+    if users_to_archive == 0 {
+        return;
+    }
+
+    users.sort_unstable();
+    for (_, user_id) in users.into_iter().take(users_to_archive) {
+        let user = state.users.get(&user_id).expect("no user found");
+        let labels = std::iter::once(&user.name)
+            .chain(user.previous_names.iter())
+            .map(|name| name.to_lowercase())
+            .collect::<Vec<_>>();
+        state
+            .users
+            .archive(&user_id, labels)
+            .expect("couldn't archive user");
+    }
+
+    state
+        .logger
+        .info(format!("`{}` inactive users archived.", users_to_archive));
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::env::tests::pr;
 
+    #[test]
+    fn test_bookmark_collections() {
+        let mut user = User::new(pr(0), 66, 0, Default::default());
+        let name = "Reading list".to_string();
+        assert!(user.create_bookmark_collection(name.clone()).is_ok());
+        assert_eq!(
+            user.create_bookmark_collection(name.clone()),
+            Err("collection exists".into())
+        );
+        assert_eq!(
+            user.create_bookmark_collection(" ".into()),
+            Err("invalid collection name".into())
+        );
+
+        for post_id in 0..3 {
+            assert!(user.collect_bookmark(&name, post_id, String::new()).is_ok());
+        }
+        assert!(user.collect_bookmark(&name, 1, "read later".into()).is_ok());
+        let collection = user.bookmark_collections.get(&name).unwrap();
+        assert_eq!(collection.posts, vec![0, 1, 2]);
+        assert_eq!(collection.notes.get(&1), Some(&"read later".to_string()));
+        // collected posts are bookmarked
+        assert_eq!(user.bookmarks.len(), 3);
+
+        // ordering
+        assert!(user.move_bookmark(&name, 2, 0).is_ok());
+        assert!(user.move_bookmark(&name, 0, 10).is_ok());
+        assert_eq!(
+            user.bookmark_collections.get(&name).unwrap().posts,
+            vec![2, 1, 0]
+        );
+        assert_eq!(
+            user.move_bookmark(&name, 7, 0),
+            Err("post not in collection".into())
+        );
+
+        // removing a bookmark removes it from all collections together with its note
+        assert!(!user.toggle_bookmark(1));
+        let collection = user.bookmark_collections.get(&name).unwrap();
+        assert_eq!(collection.posts, vec![2, 0]);
+        assert!(collection.notes.is_empty());
+        assert!(user.uncollect_bookmark(&name, 0).is_ok());
+        assert_eq!(user.bookmark_collections.get(&name).unwrap().posts, vec![2]);
+
+        // collections are limited in size
+        for post_id in 10..(9 + CONFIG.max_bookmarks_per_collection as PostId) {
+            assert!(user.collect_bookmark(&name, post_id, String::new()).is_ok());
+        }
+        assert_eq!(
+            user.collect_bookmark(&name, 0, String::new()),
+            Err("collection is full".into())
+        );
+        // notes of collected posts can still be updated
+        assert!(user.collect_bookmark(&name, 2, "note".into()).is_ok());
+        assert!(user.uncollect_bookmark(&name, 10).is_ok());
+
+        // only existing collections can be public
+        assert_eq!(
+            user.set_public_bookmark_collection(Some("Other".into())),
+            Err("no collection found".into())
+        );
+        assert!(user
+            .set_public_bookmark_collection(Some(name.clone()))
+            .is_ok());
+        assert!(user.delete_bookmark_collection(&name).is_ok());
+        assert!(user.public_bookmark_collection.is_none());
+    }
+
     #[test]
     fn test_automatic_top_up() {
         let mut user = User::new(pr(0), 66, 0, Default::default());
@@ -116,6 +116,21 @@ fn route(path: &str) -> Option<(Headers, ByteBuf)> {
                     "profile",
                 )
             }
+            (Some("bookmarks"), Some(handle)) => {
+                let user = state.user(handle)?;
+                let (name, posts) = user.public_bookmarks(state)?;
+                index(
+                    domain,
+                    &format!("bookmarks/{}", user.name),
+                    &format!("{} by @{}", filter(&name), user.name),
+                    &format!(
+                        "A collection of {} posts bookmarked by @{}",
+                        posts.len(),
+                        user.name
+                    ),
+                    "website",
+                )
+            }
             (Some("realm"), Some(arg)) => {
                 let id = arg.to_uppercase();
                 let realm = state.realms.get(&id)?;
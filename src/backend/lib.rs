@@ -1,16 +1,19 @@
 use std::{
+    borrow::Cow,
     cell::RefCell,
     collections::{BTreeMap, BTreeSet, HashMap},
 };
 
+use candid::Principal;
 use env::{
+    badges::Attestation,
     canisters::get_full_neuron,
     config::{reaction_karma, CONFIG},
-    memory,
     post::{Extension, Post, PostId},
     proposals::{Release, Reward},
+    reports::ReportCategory,
     storage::Storage,
-    token::account,
+    token::{account, Transaction},
     user::{Draft, User, UserId},
     State, *,
 };
@@ -32,8 +35,6 @@ mod env;
 mod http;
 mod metadata;
 
-const BACKUP_PAGE_SIZE: u32 = 1024 * 1024;
-
 thread_local! {
     static STATE: RefCell<State> = Default::default();
 }
@@ -65,6 +66,9 @@ fn set_timers() {
 fn init() {
     mutate(|state| {
         state.load();
+        state
+            .memory
+            .set_schema_version(env::migrations::schema_version());
         state.last_weekly_chores = time();
         state.last_daily_chores = time();
         state.last_hourly_chores = time();
@@ -72,7 +76,7 @@ fn init() {
     set_timers();
     set_timer(std::time::Duration::from_secs(1), || {
         spawn(async {
-            if let Err(err) = Storage::allocate_space().await {
+            if let Err(err) = Storage::allocate_space(None).await {
                 mutate(|state| state.logger.error(err));
             }
         })
@@ -95,16 +99,30 @@ fn post_upgrade() {
         }
     }
     stable_to_heap_core();
-    mutate(|state| state.load());
     set_timers();
+    set_timer(std::time::Duration::from_secs(1), migrate);
+}
 
-    // temporary post upgrade logic goes here
-    // set_timer(std::time::Duration::from_secs(1), move || {
-    //     spawn(post_upgrade_fixtures())
-    // });
+// Runs pending schema migrations step by step, one step per timer tick.
+fn migrate() {
+    if mutate(env::migrations::step) {
+        set_timer(std::time::Duration::ZERO, migrate);
+    }
 }
 
-// async fn post_upgrade_fixtures() {}
+// Computes the page hashes of the running backup, a few pages per timer tick.
+fn hash_backup_pages() {
+    if mutate(env::backup::hash_pages) {
+        set_timer(std::time::Duration::ZERO, hash_backup_pages);
+    }
+}
+
+// Checks the consistency of the state, a few objects per timer tick.
+fn check_state() {
+    if mutate(env::backup::check_step) {
+        set_timer(std::time::Duration::ZERO, check_state);
+    }
+}
 
 /*
  * UPDATES
@@ -137,11 +155,17 @@ fn vote_on_poll() {
 #[export_name = "canister_update report"]
 fn report() {
     mutate(|state| {
-        let (domain, id, reason): (String, u64, String) = parse(&arg_data_raw());
-        reply(state.report(caller(), domain, id, reason))
+        let (domain, id, reason, category): (String, u64, String, ReportCategory) =
+            parse(&arg_data_raw());
+        reply(state.report(caller(), domain, id, reason, category))
     });
 }
 
+#[export_name = "canister_query moderation_queue"]
+fn moderation_queue() {
+    read(|state| reply(reports::moderation_queue(state, time())));
+}
+
 #[export_name = "canister_update vote_on_report"]
 fn vote_on_report() {
     mutate(|state| {
@@ -150,6 +174,22 @@ fn vote_on_report() {
     });
 }
 
+#[export_name = "canister_update appeal_report"]
+fn appeal_report() {
+    mutate(|state| {
+        let (domain, id, reason): (String, u64, String) = parse(&arg_data_raw());
+        reply(state.appeal_report(caller(), domain, id, reason))
+    });
+}
+
+#[export_name = "canister_update vote_on_appeal"]
+fn vote_on_appeal() {
+    mutate(|state| {
+        let (domain, id, vote): (String, u64, bool) = parse(&arg_data_raw());
+        reply(state.vote_on_appeal(caller(), domain, id, vote))
+    });
+}
+
 #[export_name = "canister_update clear_notifications"]
 fn clear_notifications() {
     mutate(|state| {
@@ -191,6 +231,114 @@ fn change_principal() {
     });
 }
 
+#[export_name = "canister_update set_guardians"]
+fn set_guardians() {
+    mutate(|state| {
+        let (guardians, threshold): (BTreeSet<UserId>, usize) = parse(&arg_data_raw());
+        reply(env::recovery::set_guardians(
+            state,
+            caller(),
+            guardians,
+            threshold,
+        ))
+    })
+}
+
+#[export_name = "canister_update approve_recovery"]
+fn approve_recovery() {
+    mutate(|state| {
+        let (user_id, principal): (UserId, String) = parse(&arg_data_raw());
+        reply(env::recovery::approve(
+            state,
+            caller(),
+            user_id,
+            principal,
+            api::time(),
+        ))
+    })
+}
+
+#[export_name = "canister_update veto_recovery"]
+fn veto_recovery() {
+    mutate(|state| reply(env::recovery::veto(state, caller())))
+}
+
+#[export_name = "canister_update complete_recovery"]
+fn complete_recovery() {
+    spawn(async {
+        let user_id: UserId = parse(&arg_data_raw());
+        reply(env::recovery::complete(caller(), user_id, api::time()).await);
+    });
+}
+
+#[export_name = "canister_query recovery"]
+fn recovery() {
+    let user_id: UserId = parse(&arg_data_raw());
+    read(|state| {
+        let caller_id = state.principal_to_user(caller()).map(|user| user.id);
+        reply(
+            state
+                .users
+                .get(&user_id)
+                .filter(|user| {
+                    Some(user.id) == caller_id
+                        || caller_id
+                            .map(|id| user.guardians.contains(&id))
+                            .unwrap_or_default()
+                })
+                .and_then(|_| state.recoveries.get(&user_id)),
+        )
+    });
+}
+
+#[export_name = "canister_update set_delegate"]
+fn set_delegate() {
+    mutate(|state| {
+        let (principal, permissions, weekly_cycles_limit, weekly_e8s_limit, expiry_days, realm): (
+            String,
+            BTreeSet<env::delegates::Permission>,
+            Cycles,
+            u64,
+            Option<u64>,
+            String,
+        ) = parse(&arg_data_raw());
+        reply(env::delegates::set_delegate(
+            state,
+            caller(),
+            principal,
+            permissions,
+            weekly_cycles_limit,
+            weekly_e8s_limit,
+            expiry_days.map(|days| api::time() + days * DAY),
+            optional(realm),
+        ))
+    })
+}
+
+#[export_name = "canister_update remove_delegate"]
+fn remove_delegate() {
+    mutate(|state| {
+        let principal: String = parse(&arg_data_raw());
+        reply(env::delegates::remove_delegate(state, caller(), &principal))
+    })
+}
+
+#[export_name = "canister_update request_account_deletion"]
+fn request_account_deletion() {
+    mutate(|state| {
+        reply(env::deletion::request_deletion(
+            state,
+            caller(),
+            api::time(),
+        ))
+    })
+}
+
+#[export_name = "canister_update cancel_account_deletion"]
+fn cancel_account_deletion() {
+    mutate(|state| reply(env::deletion::cancel_deletion(state, caller())))
+}
+
 #[export_name = "canister_update update_user"]
 fn update_user() {
     let (new_name, about, principals, settings): (String, String, Vec<String>, String) =
@@ -313,6 +461,81 @@ fn propose_funding() {
     })
 }
 
+#[export_name = "canister_update propose_abandoned_realm"]
+fn propose_abandoned_realm() {
+    let (description, realm, controller): (String, String, Option<UserId>) = parse(&arg_data_raw());
+    mutate(|state| {
+        reply(proposals::propose(
+            state,
+            caller(),
+            description,
+            proposals::Payload::AbandonedRealm(realm, controller),
+            time(),
+        ))
+    })
+}
+
+#[export_name = "canister_update propose_badge"]
+fn propose_badge() {
+    let (description, user_id, attestation, revoke): (String, UserId, Attestation, bool) =
+        parse(&arg_data_raw());
+    mutate(|state| {
+        reply(proposals::propose(
+            state,
+            caller(),
+            description,
+            if revoke {
+                proposals::Payload::RevokeBadge(user_id, attestation)
+            } else {
+                proposals::Payload::Badge(user_id, attestation)
+            },
+            time(),
+        ))
+    })
+}
+
+// Returns the certified badges of the user together with the certificate and the witness tree.
+#[export_name = "canister_query badges"]
+fn badges() {
+    let user_id: UserId = parse(&arg_data_raw());
+    read(|state| {
+        let badges = state
+            .users
+            .get(&user_id)
+            .map(|user| user.badges.clone())
+            .unwrap_or_default();
+        let (certificate, tree) = assets::badge_certificate(user_id);
+        reply((env::badges::certified_content(&badges), certificate, tree))
+    })
+}
+
+#[export_name = "canister_update issue_realm_badge"]
+fn issue_realm_badge() {
+    let (user_id, realm): (UserId, String) = parse(&arg_data_raw());
+    mutate(|state| {
+        reply(env::badges::issue_realm_badge(
+            state,
+            caller(),
+            user_id,
+            realm,
+            api::time(),
+        ))
+    })
+}
+
+#[export_name = "canister_update revoke_realm_badge"]
+fn revoke_realm_badge() {
+    let (user_id, realm): (UserId, String) = parse(&arg_data_raw());
+    mutate(|state| {
+        reply(env::badges::revoke_realm_badge(
+            state,
+            caller(),
+            user_id,
+            realm,
+        ))
+    })
+}
+
 #[export_name = "canister_update vote_on_proposal"]
 fn vote_on_proposal() {
     let (proposal_id, vote, data): (u32, bool, String) = parse(&arg_data_raw());
@@ -344,9 +567,9 @@ async fn add_post(
     realm: Option<String>,
     extension: Option<Blob>,
 ) -> Result<PostId, String> {
-    let post_id = mutate(|state| {
+    let (post_id, files) = mutate(|state| {
         let extension: Option<Extension> = extension.map(|bytes| parse(&bytes));
-        Post::create(
+        Post::create_post(
             state,
             body,
             &blobs,
@@ -355,15 +578,58 @@ async fn add_post(
             parent,
             realm,
             extension,
+            true,
         )
     })?;
     let call_name = format!("blobs_storing_for_{}", post_id);
     canisters::open_call(&call_name);
-    let result = Post::save_blobs(post_id, blobs).await;
+    let result = Post::save_blobs(post_id, files).await;
     canisters::close_call(&call_name);
     result.map(|_| post_id)
 }
 
+#[update]
+/// This method lets stalwarts approve or reject posts held by the spam filter.
+async fn review_held_post(user_id: UserId, approve: bool) -> Result<Option<PostId>, String> {
+    let result = mutate(|state| spam::review(state, caller(), user_id, approve, api::time()))?;
+    match result {
+        Some((post_id, blobs)) => Post::save_blobs(post_id, blobs)
+            .await
+            .map(|_| Some(post_id)),
+        None => Ok(None),
+    }
+}
+
+#[export_name = "canister_query held_posts"]
+fn held_posts() {
+    read(|state| {
+        if !state
+            .principal_to_user(caller())
+            .map(|user| user.stalwart)
+            .unwrap_or_default()
+        {
+            return reply(Vec::<()>::new());
+        }
+        reply(
+            state
+                .held_posts
+                .values()
+                .map(|post| {
+                    (
+                        post.user,
+                        &post.body,
+                        post.parent,
+                        &post.realm,
+                        post.blobs.len(),
+                        post.score,
+                        post.timestamp,
+                    )
+                })
+                .collect::<Vec<_>>(),
+        )
+    })
+}
+
 #[update]
 /// This method initiates an asynchronous post creation.
 fn add_post_data(body: String, realm: Option<String>, extension: Option<Blob>) {
@@ -374,6 +640,7 @@ fn add_post_data(body: String, realm: Option<String>, extension: Option<Blob>) {
                 realm,
                 extension,
                 blobs: Default::default(),
+                timestamp: api::time(),
             });
         };
     })
@@ -382,19 +649,44 @@ fn add_post_data(body: String, realm: Option<String>, extension: Option<Blob>) {
 #[update]
 /// This method adds a blob to a post being created
 fn add_post_blob(id: String, blob: Blob) -> Result<(), String> {
+    env::images::check_type(&blob)?;
     mutate(|state| {
+        state.check_draft_growth(caller(), blob.len())?;
         if let Some(user) = state.principal_to_user_mut(caller()) {
             let cycles = user.cycles();
             if let Some(draft) = user.draft.as_mut() {
+                if draft.blobs.len() >= CONFIG.max_draft_blobs {
+                    return Err("too many attachments".into());
+                }
                 if cycles < (draft.blobs.len() + 1) as u64 * CONFIG.blob_cost {
                     user.draft.take();
-                    return;
+                    return Ok(());
                 }
                 draft.blobs.push((id, blob))
             }
         }
-    });
-    Ok(())
+        Ok(())
+    })
+}
+
+#[update]
+/// This method appends a chunk to a blob of the post being created, so that media larger than
+/// one message can be uploaded.
+fn append_post_blob(id: String, chunk: Blob) -> Result<(), String> {
+    mutate(|state| {
+        state.check_draft_growth(caller(), chunk.len())?;
+        let blob = state
+            .principal_to_user_mut(caller())
+            .and_then(|user| user.draft.as_mut())
+            .and_then(|draft| draft.blobs.iter_mut().find(|(blob_id, _)| *blob_id == id))
+            .map(|(_, blob)| blob)
+            .ok_or("no blob found")?;
+        if blob.len() + chunk.len() > CONFIG.max_media_size_bytes {
+            return Err("blob too large".into());
+        }
+        blob.extend_from_slice(&chunk);
+        Ok(())
+    })
 }
 
 #[update]
@@ -405,6 +697,7 @@ async fn commit_post() -> Result<PostId, String> {
         realm,
         extension,
         blobs,
+        ..
     })) = mutate(|state| {
         state
             .principal_to_user_mut(caller())
@@ -447,6 +740,68 @@ fn toggle_bookmark() {
     });
 }
 
+#[export_name = "canister_update create_bookmark_collection"]
+fn create_bookmark_collection() {
+    let name: String = parse(&arg_data_raw());
+    mutate_caller(|user| user.create_bookmark_collection(name));
+}
+
+#[export_name = "canister_update delete_bookmark_collection"]
+fn delete_bookmark_collection() {
+    let name: String = parse(&arg_data_raw());
+    mutate_caller(|user| user.delete_bookmark_collection(&name));
+}
+
+#[export_name = "canister_update collect_bookmark"]
+fn collect_bookmark() {
+    let (name, post_id, note): (String, PostId, String) = parse(&arg_data_raw());
+    mutate(|state| reply(state.collect_bookmark(caller(), &name, post_id, note)));
+}
+
+#[export_name = "canister_update uncollect_bookmark"]
+fn uncollect_bookmark() {
+    let (name, post_id): (String, PostId) = parse(&arg_data_raw());
+    mutate_caller(|user| user.uncollect_bookmark(&name, post_id));
+}
+
+#[export_name = "canister_update move_bookmark"]
+fn move_bookmark() {
+    let (name, post_id, position): (String, PostId, usize) = parse(&arg_data_raw());
+    mutate_caller(|user| user.move_bookmark(&name, post_id, position));
+}
+
+#[export_name = "canister_update set_public_bookmark_collection"]
+fn set_public_bookmark_collection() {
+    let name: String = parse(&arg_data_raw());
+    mutate_caller(|user| user.set_public_bookmark_collection(optional(name)));
+}
+
+#[export_name = "canister_query export_bookmark_collections"]
+fn export_bookmark_collections() {
+    read(|state| {
+        reply(
+            state
+                .principal_to_user(caller())
+                .map(|user| user.bookmark_collections.clone())
+                .unwrap_or_default(),
+        )
+    });
+}
+
+#[export_name = "canister_query public_bookmarks"]
+fn public_bookmarks() {
+    let handle: String = parse(&arg_data_raw());
+    read(|state| {
+        reply(
+            state
+                .user(&handle)
+                .and_then(|user| user.public_bookmarks(state))
+                .map(|(_, posts)| posts.into_iter().cloned().collect::<Vec<Post>>())
+                .unwrap_or_default(),
+        )
+    });
+}
+
 #[export_name = "canister_update toggle_following_post"]
 fn toggle_following_post() {
     let post_id: PostId = parse(&arg_data_raw());
@@ -538,6 +893,22 @@ fn toggle_realm_membership() {
     })
 }
 
+#[export_name = "canister_update transfer_realm"]
+fn transfer_realm() {
+    mutate(|state| {
+        let (name, recipient): (String, UserId) = parse(&arg_data_raw());
+        reply(state.transfer_realm(caller(), name, recipient))
+    })
+}
+
+#[export_name = "canister_update accept_realm_transfer"]
+fn accept_realm_transfer() {
+    mutate(|state| {
+        let name: String = parse(&arg_data_raw());
+        reply(state.accept_realm_transfer(caller(), name))
+    })
+}
+
 #[export_name = "canister_update toggle_filter"]
 fn toggle_filter() {
     mutate(|state| {
@@ -550,6 +921,30 @@ fn toggle_filter() {
     })
 }
 
+#[export_name = "canister_update toggle_block"]
+fn toggle_block() {
+    mutate(|state| {
+        let user_id: UserId = parse(&arg_data_raw());
+        reply(state.toggle_block(caller(), user_id))
+    })
+}
+
+#[export_name = "canister_update mute"]
+fn mute() {
+    mutate(|state| {
+        let (kind, value, hours): (String, String, Option<u64>) = parse(&arg_data_raw());
+        reply(state.mute(caller(), kind, value, hours, api::time()))
+    })
+}
+
+#[export_name = "canister_update unmute"]
+fn unmute() {
+    mutate(|state| {
+        let (kind, value): (String, String) = parse(&arg_data_raw());
+        reply(state.unmute(caller(), kind, value))
+    })
+}
+
 #[update]
 async fn set_emergency_release(binary: ByteBuf) {
     mutate(|state| {
@@ -625,30 +1020,56 @@ fn balances() {
     });
 }
 
+#[export_name = "canister_query blob_replica"]
+fn blob_replica() {
+    let (bucket_id, offset): (String, u64) = parse(&arg_data_raw());
+    read(|state| {
+        reply(
+            Principal::from_text(bucket_id)
+                .ok()
+                .and_then(|id| state.storage.replica(id, offset)),
+        )
+    });
+}
+
 #[export_name = "canister_query transaction"]
 fn transaction() {
     let id: usize = parse(&arg_data_raw());
-    read(|state| reply(state.ledger.get(id).ok_or("not found")));
+    read(|state| reply(state.ledger.get(&(id as u64)).ok_or("not found")));
 }
 
 #[export_name = "canister_query transactions"]
 fn transactions() {
     let (page, search_term): (usize, String) = parse(&arg_data_raw());
     read(|state| {
-        let iter = state.ledger.iter().enumerate();
-        let iter: Box<dyn DoubleEndedIterator<Item = _>> = if search_term.is_empty() {
-            Box::new(iter)
+        // Principals are looked up in the index; other search terms are only matched against
+        // the transactions in the heap, so that no query deserializes the whole archive.
+        let iter: Box<dyn Iterator<Item = (u64, Cow<'_, Transaction>)>> = if search_term.is_empty()
+        {
+            Box::new(
+                (0..state.ledger.len() as u64)
+                    .rev()
+                    .skip(page * CONFIG.feed_page_size)
+                    .filter_map(|id| Some((id, state.ledger.get(&id)?))),
+            )
+        } else if let Ok(principal) = Principal::from_text(&search_term) {
+            Box::new(
+                token::principal_transactions(state, principal).skip(page * CONFIG.feed_page_size),
+            )
         } else {
-            Box::new(iter.filter(|(_, t)| {
-                (t.to.owner.to_string() + &t.from.owner.to_string()).contains(&search_term)
-            }))
+            Box::new(
+                state
+                    .ledger
+                    .iter()
+                    .rev()
+                    .filter(|(_, t)| {
+                        (t.to.owner.to_string() + &t.from.owner.to_string()).contains(&search_term)
+                    })
+                    .skip(page * CONFIG.feed_page_size)
+                    .map(|(id, t)| (*id, Cow::Borrowed(t))),
+            )
         };
-        reply(
-            iter.rev()
-                .skip(page * CONFIG.feed_page_size)
-                .take(CONFIG.feed_page_size)
-                .collect::<Vec<(usize, _)>>(),
-        );
+        reply(iter.take(CONFIG.feed_page_size).collect::<Vec<_>>());
     });
 }
 
@@ -656,13 +1077,7 @@ fn transactions() {
 fn proposal() {
     read(|state| {
         let id: u32 = parse(&arg_data_raw());
-        reply(
-            state
-                .proposals
-                .iter()
-                .find(|proposal| proposal.id == id)
-                .ok_or("no proposal found"),
-        )
+        reply(state.proposals.get(&id).ok_or("no proposal found"))
     })
 }
 
@@ -672,12 +1087,11 @@ fn proposals() {
     let page: usize = parse(&arg_data_raw());
     read(|state| {
         reply(
-            state
-                .proposals
-                .iter()
+            (0..state.proposals.len() as u32)
                 .rev()
                 .skip(page * page_size)
                 .take(page_size)
+                .filter_map(|id| state.proposals.get(&id))
                 .filter_map(|proposal| Post::get(state, &proposal.post_id))
                 .collect::<Vec<_>>(),
         )
@@ -685,7 +1099,11 @@ fn proposals() {
 }
 
 fn sorted_realms(state: &State) -> Vec<(&'_ String, &'_ Realm)> {
-    let mut realms = state.realms.iter().collect::<Vec<_>>();
+    let mut realms = state
+        .realms
+        .iter()
+        .filter(|(_name, realm)| !realm.archived)
+        .collect::<Vec<_>>();
     realms.sort_unstable_by_key(|(_name, realm)| {
         std::cmp::Reverse(realm.num_posts * realm.num_members)
     });
@@ -796,6 +1214,12 @@ fn user() {
             } else {
                 let karma = reaction_karma();
                 user.bookmarks.clear();
+                user.mutes = Default::default();
+                user.muted_by.clear();
+                user.bookmark_collections.clear();
+                user.blocked.clear();
+                user.guardians.clear();
+                user.delegates.clear();
                 user.settings.clear();
                 user.inbox.clear();
                 user.karma_from_last_posts = user
@@ -872,7 +1296,7 @@ fn journal() {
 #[export_name = "canister_query hot_posts"]
 fn hot_posts() {
     let (realm, page): (String, usize) = parse(&arg_data_raw());
-    read(|state| reply(state.hot_posts(optional(realm), page)));
+    read(|state| reply(state.hot_posts(caller(), optional(realm), page)));
 }
 
 #[export_name = "canister_query realms_posts"]
@@ -924,6 +1348,21 @@ fn personal_feed() {
     });
 }
 
+#[export_name = "canister_query for_you_feed"]
+fn for_you_feed() {
+    let page: usize = parse(&arg_data_raw());
+    read(|state| {
+        reply(match state.principal_to_user(caller()) {
+            None => Default::default(),
+            Some(user) => user
+                .for_you_feed(state, page)
+                .into_iter()
+                .cloned()
+                .collect::<Vec<Post>>(),
+        })
+    });
+}
+
 #[export_name = "canister_query thread"]
 fn thread() {
     let id: PostId = parse(&arg_data_raw());
@@ -978,6 +1417,12 @@ fn recovery_state() {
     read(|state| reply(state.recovery_state()));
 }
 
+#[export_name = "canister_query export_user"]
+fn export_user() {
+    let page: usize = parse(&arg_data_raw());
+    read(|state| reply(env::export::export_user(state, caller(), page)));
+}
+
 #[export_name = "canister_query stats"]
 fn stats() {
     read(|state| reply(state.stats(api::time())));
@@ -986,25 +1431,87 @@ fn stats() {
 #[export_name = "canister_query search"]
 fn search() {
     let term: String = parse(&arg_data_raw());
-    read(|state| reply(state.search(term)));
+    read(|state| reply(state.search(caller(), term, false)));
 }
 
-#[query]
-fn stable_mem_read(page: u64) -> Vec<(u64, Blob)> {
-    let offset = page * BACKUP_PAGE_SIZE as u64;
-    let (heap_off, heap_size) = memory::heap_address();
-    let memory_end = heap_off + heap_size;
-    if offset > memory_end {
-        return Default::default();
-    }
-    let chunk_size = (BACKUP_PAGE_SIZE as u64).min(memory_end - offset) as usize;
-    let mut buf = Vec::with_capacity(chunk_size);
-    buf.spare_capacity_mut();
-    unsafe {
-        buf.set_len(chunk_size);
+// Same as `search`, but skips the results muted or filtered out by the caller.
+#[export_name = "canister_query filtered_search"]
+fn filtered_search() {
+    let term: String = parse(&arg_data_raw());
+    read(|state| reply(state.search(caller(), term, true)));
+}
+
+fn controller_only() -> Result<(), String> {
+    if !api::is_controller(&caller()) {
+        return Err("only controllers can manage backups".into());
     }
-    api::stable::stable64_read(offset, &mut buf);
-    vec![(page, ByteBuf::from(buf))]
+    Ok(())
+}
+
+#[update]
+fn backup_start() -> Result<env::backup::Manifest, String> {
+    controller_only()?;
+    let manifest = mutate(env::backup::start)?;
+    set_timer(std::time::Duration::ZERO, hash_backup_pages);
+    Ok(manifest)
+}
+
+// The manifest and pages are returned in the format of the arguments of `import_start` and
+// `import_page`, so that the responses can be sent to the importing canister as they are.
+#[query]
+fn backup_manifest() -> Result<env::backup::Manifest, String> {
+    controller_only()?;
+    read(env::backup::manifest)
+}
+
+#[query]
+fn backup_page(page: u64) -> Result<(u64, ByteBuf), String> {
+    controller_only()?;
+    read(|state| env::backup::page(state, page)).map(|bytes| (page, ByteBuf::from(bytes)))
+}
+
+#[update]
+fn backup_finish() -> Result<(), String> {
+    controller_only()?;
+    mutate(env::backup::finish)
+}
+
+#[update]
+fn import_start(manifest: Result<env::backup::Manifest, String>) -> Result<(), String> {
+    controller_only()?;
+    let manifest = manifest?;
+    mutate(|state| env::backup::start_import(state, manifest))
+}
+
+#[update]
+fn import_page(page: Result<(u64, ByteBuf), String>) -> Result<(), String> {
+    controller_only()?;
+    let (page, bytes) = page?;
+    mutate(|state| env::backup::import_page(state, page, &bytes))
+}
+
+// Restores the state from the imported backup and starts a consistency check; the result of the
+// check is logged.
+#[update]
+fn import_finish() -> Result<(), String> {
+    controller_only()?;
+    mutate(env::backup::finish_import)?;
+    stable_to_heap_core();
+    mutate(|state| {
+        state.logger.info("Backup imported.");
+        env::backup::start_check(state)
+    })?;
+    set_timer(std::time::Duration::ZERO, check_state);
+    Ok(())
+}
+
+// Starts a consistency check of the state; the result of the check is logged.
+#[update]
+fn check() -> Result<(), String> {
+    controller_only()?;
+    mutate(env::backup::start_check)?;
+    set_timer(std::time::Duration::ZERO, check_state);
+    Ok(())
 }
 
 fn parse<'a, T: serde::Deserialize<'a>>(bytes: &'a [u8]) -> T {
@@ -1017,8 +1524,17 @@ fn reply<T: serde::Serialize>(data: T) {
 
 fn resolve_handle(handle: Option<String>) -> Option<User> {
     read(|state| match handle {
-        Some(handle) => state.user(&handle).cloned(),
-        None => Some(state.principal_to_user(caller())?.clone()),
+        Some(handle) => state.user(&handle).map(Cow::into_owned),
+        None => state.principal_to_user(caller()).map(Cow::into_owned),
+    })
+}
+
+fn mutate_caller<T: serde::Serialize>(f: impl FnOnce(&mut User) -> Result<T, String>) {
+    mutate(|state| {
+        reply(match state.principal_to_user_mut(caller()) {
+            Some(user) => f(user),
+            None => Err("no user found".into()),
+        })
     })
 }
 
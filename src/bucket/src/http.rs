@@ -0,0 +1,146 @@
+//! Serving of blobs over HTTP.
+//!
+//! A blob is requested by its coordinates, e.g. `/<offset>/<len>`. Responses support the
+//! `Range` header with a single byte range, so that browsers can stream media and seek in it.
+//! Since a query response is limited in size, a response never contains more than
+//! `MAX_RESPONSE_BYTES` bytes: a range exceeding it is served partially, and a request without
+//! a range for a larger blob is rejected with 413, so that clients fetch such blobs in several
+//! range requests.
+
+use super::{pointer, read_range};
+use candid::CandidType;
+use ic_cdk::api::call::{arg_data, reply};
+use serde::{Deserialize, Serialize};
+
+const MAX_RESPONSE_BYTES: u64 = 1536 * 1024;
+
+type Headers = Vec<(String, String)>;
+
+#[derive(CandidType, Deserialize)]
+struct HttpRequest {
+    url: String,
+    headers: Headers,
+}
+
+#[derive(CandidType, Serialize)]
+struct HttpResponse {
+    status_code: u16,
+    headers: Headers,
+    body: Vec<u8>,
+}
+
+impl HttpResponse {
+    fn error(status_code: u16, headers: Headers) -> Self {
+        Self {
+            status_code,
+            headers,
+            body: Default::default(),
+        }
+    }
+}
+
+#[export_name = "canister_query http_request"]
+fn http_request() {
+    let (req,): (HttpRequest,) = arg_data();
+    reply((respond(&req),));
+}
+
+fn respond(req: &HttpRequest) -> HttpResponse {
+    let (offset, len) = match coordinates(&req.url) {
+        Some((offset, len))
+            if offset >= 8 && matches!(offset.checked_add(len), Some(end) if end <= pointer()) =>
+        {
+            (offset, len)
+        }
+        _ => return HttpResponse::error(404, Default::default()),
+    };
+    let range = req
+        .headers
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case("range"))
+        .map(|(_, value)| parse_range(value, len));
+    let (start, end, partial) = match range {
+        Some(Some((start, end))) => (start, end, true),
+        Some(None) => {
+            return HttpResponse::error(
+                416,
+                vec![("Content-Range".into(), format!("bytes */{}", len))],
+            )
+        }
+        None if len > MAX_RESPONSE_BYTES => {
+            return HttpResponse::error(413, vec![("Accept-Ranges".into(), "bytes".into())])
+        }
+        None => (0, len.saturating_sub(1), false),
+    };
+    // a range too large for one response is served partially
+    let end = end.min(start + MAX_RESPONSE_BYTES - 1);
+    let body = if len == 0 {
+        Default::default()
+    } else {
+        read_range(offset + start, end + 1 - start)
+    };
+    let mut headers = vec![("Accept-Ranges".to_string(), "bytes".to_string())];
+    if let Some(mime_type) = mime_type(&read_range(offset, len.min(12))) {
+        headers.push(("Content-Type".into(), mime_type.into()));
+    }
+    if partial {
+        headers.push((
+            "Content-Range".into(),
+            format!("bytes {}-{}/{}", start, end, len),
+        ));
+    }
+    HttpResponse {
+        status_code: if partial { 206 } else { 200 },
+        headers,
+        body,
+    }
+}
+
+// Parses a path of the form `/<offset>/<len>`, ignoring the query string.
+fn coordinates(url: &str) -> Option<(u64, u64)> {
+    let path = url.split('?').next()?;
+    let mut parts = path.trim_start_matches('/').split('/');
+    let offset = parts.next()?.parse().ok()?;
+    let len = parts.next()?.parse().ok()?;
+    parts.next().is_none().then_some((offset, len))
+}
+
+// Parses a single byte range of a blob with the given length into the first and the last byte
+// position. Returns `None` for unsatisfiable or unsupported ranges.
+fn parse_range(value: &str, len: u64) -> Option<(u64, u64)> {
+    let range = value.trim().strip_prefix("bytes=")?;
+    if range.contains(',') || len == 0 {
+        return None;
+    }
+    let (start, end) = range.split_once('-')?;
+    let (start, end) = match (start.trim(), end.trim()) {
+        ("", suffix) => {
+            let suffix: u64 = suffix.parse().ok()?;
+            (len.saturating_sub(suffix), len - 1)
+        }
+        (start, "") => (start.parse().ok()?, len - 1),
+        (start, end) => (start.parse().ok()?, end.parse::<u64>().ok()?.min(len - 1)),
+    };
+    (start <= end && start < len).then_some((start, end))
+}
+
+// Detects the mime type of common image and video formats from the first bytes of a blob.
+fn mime_type(bytes: &[u8]) -> Option<&'static str> {
+    let matches =
+        |offset: usize, magic: &[u8]| bytes.get(offset..offset + magic.len()) == Some(magic);
+    if matches(0, &[0xff, 0xd8, 0xff]) {
+        Some("image/jpeg")
+    } else if matches(0, b"\x89PNG") {
+        Some("image/png")
+    } else if matches(0, b"GIF8") {
+        Some("image/gif")
+    } else if matches(0, b"RIFF") && matches(8, b"WEBP") {
+        Some("image/webp")
+    } else if matches(4, b"ftyp") {
+        Some("video/mp4")
+    } else if matches(0, &[0x1a, 0x45, 0xdf, 0xa3]) {
+        Some("video/webm")
+    } else {
+        None
+    }
+}
@@ -5,9 +5,33 @@ use ic_cdk::api::{
     canister_balance,
     stable::*,
 };
+use sha2::{Digest, Sha256};
+use std::{cell::RefCell, collections::BTreeMap};
+
+mod http;
 
 static mut CONTROLLER: Option<Principal> = None;
 
+// The number of bytes read from the stable memory at once while hashing an uploaded blob.
+const HASHING_CHUNK: u64 = 1024 * 1024;
+
+// Sessions not committed within this time are dropped, so that late chunks of an abandoned
+// upload cannot overwrite the range after the main canister reused it.
+const SESSION_TTL_NANOS: u64 = 60 * 60 * 1_000_000_000;
+
+// An upload of a blob in chunks into a reserved range starting at the offset the session is
+// keyed by. Sessions are kept in the heap only, so they don't survive upgrades.
+struct Session {
+    len: u64,
+    hash: [u8; 32],
+    received: u64,
+    started: u64,
+}
+
+thread_local! {
+    static SESSIONS: RefCell<BTreeMap<u64, Session>> = Default::default();
+}
+
 fn set_controller() {
     unsafe {
         CONTROLLER = Some(Principal::from_slice(&arg_data_raw()));
@@ -41,13 +65,7 @@ fn read() {
     let args = &arg_data_raw();
     let offset = bytes_to_u64(args, 0);
     let len = bytes_to_u64(args, 8);
-    let mut buf = Vec::with_capacity(len as usize);
-    buf.spare_capacity_mut();
-    unsafe {
-        buf.set_len(len as usize);
-    }
-    stable64_read(offset, &mut buf);
-    reply_raw(&buf);
+    reply_raw(&read_range(offset, len));
 }
 
 #[export_name = "canister_update update_pointer"]
@@ -61,10 +79,8 @@ fn update_pointer() {
 #[export_name = "canister_update write"]
 fn write() {
     assert_controller();
-    let mut offset_bytes: [u8; 8] = Default::default();
-    api::stable::stable64_read(0, &mut offset_bytes);
     let blob = arg_data_raw();
-    write_at(u64::from_be_bytes(offset_bytes), &blob, true);
+    write_at(pointer(), &blob, true);
 }
 
 #[export_name = "canister_update write_at_offset"]
@@ -75,6 +91,111 @@ fn write_at_offset() {
     write_at(offset, &params[8..], false);
 }
 
+// Starts a chunked upload: reserves a range for a blob with the given length and SHA-256 hash
+// and replies with its offset.
+#[export_name = "canister_update begin_upload"]
+fn begin_upload() {
+    assert_controller();
+    let args = &arg_data_raw();
+    let len = bytes_to_u64(args, 0);
+    let mut hash = [0; 32];
+    hash.copy_from_slice(&args[8..40]);
+    let offset = pointer();
+    let end = offset.checked_add(len).expect("invalid length");
+    grow_to_fit(end);
+    api::stable::stable64_write(0, &end.to_be_bytes());
+    let now = api::time();
+    SESSIONS.with(|sessions| {
+        let mut sessions = sessions.borrow_mut();
+        sessions.retain(|_, session| session.started + SESSION_TTL_NANOS > now);
+        sessions.insert(
+            offset,
+            Session {
+                len,
+                hash,
+                received: 0,
+                started: now,
+            },
+        )
+    });
+    reply_raw(&offset.to_be_bytes());
+}
+
+// Writes a chunk at the given position of the blob. Chunks can be rewritten but must not leave
+// gaps. Replies with the number of bytes received so far.
+#[export_name = "canister_update append_upload"]
+fn append_upload() {
+    assert_controller();
+    let args = &arg_data_raw();
+    let offset = bytes_to_u64(args, 0);
+    let position = bytes_to_u64(args, 8);
+    let chunk = &args[16..];
+    let received = SESSIONS.with(|sessions| {
+        let mut sessions = sessions.borrow_mut();
+        let session = active_session(&mut sessions, offset);
+        let end = position
+            .checked_add(chunk.len() as u64)
+            .expect("invalid position");
+        assert!(position <= session.received, "chunks must not leave gaps");
+        assert!(end <= session.len, "chunk exceeds the blob length");
+        stable64_write(offset + position, chunk);
+        session.received = session.received.max(end);
+        session.received
+    });
+    reply_raw(&received.to_be_bytes());
+}
+
+// Completes the upload after verifying the checksum of the received blob. Replies with the
+// offset of the blob.
+#[export_name = "canister_update commit_upload"]
+fn commit_upload() {
+    assert_controller();
+    let offset = bytes_to_u64(&arg_data_raw(), 0);
+    SESSIONS.with(|sessions| {
+        let mut sessions = sessions.borrow_mut();
+        let session = active_session(&mut sessions, offset);
+        assert_eq!(session.received, session.len, "upload incomplete");
+        let mut hasher = Sha256::new();
+        let mut position = 0;
+        while position < session.len {
+            let len = HASHING_CHUNK.min(session.len - position);
+            hasher.update(read_range(offset + position, len));
+            position += len;
+        }
+        assert_eq!(
+            hasher.finalize().as_slice(),
+            &session.hash,
+            "checksum mismatch"
+        );
+        sessions.remove(&offset);
+    });
+    reply_raw(&offset.to_be_bytes());
+}
+
+// Returns the session of the upload at the given offset unless it expired.
+fn active_session(sessions: &mut BTreeMap<u64, Session>, offset: u64) -> &mut Session {
+    match sessions.get_mut(&offset) {
+        Some(session) if session.started + SESSION_TTL_NANOS > api::time() => session,
+        _ => panic!("no upload session found"),
+    }
+}
+
+fn pointer() -> u64 {
+    let mut offset_bytes: [u8; 8] = Default::default();
+    api::stable::stable64_read(0, &mut offset_bytes);
+    u64::from_be_bytes(offset_bytes)
+}
+
+fn read_range(offset: u64, len: u64) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(len as usize);
+    buf.spare_capacity_mut();
+    unsafe {
+        buf.set_len(len as usize);
+    }
+    stable64_read(offset, &mut buf);
+    buf
+}
+
 fn write_at(offset: u64, blob: &[u8], update_pointer: bool) {
     grow_to_fit(offset + blob.len() as u64);
     stable64_write(offset, blob);
@@ -53,8 +53,18 @@ export type Backend = {
 
     add_post_blob: (id: string, blob: Uint8Array) => Promise<JsonValue | null>;
 
+    append_post_blob: (
+        id: string,
+        chunk: Uint8Array,
+    ) => Promise<JsonValue | null>;
+
     commit_post: () => Promise<JsonValue | null>;
 
+    review_held_post: (
+        user_id: number,
+        approve: boolean,
+    ) => Promise<JsonValue | null>;
+
     edit_post: (
         id: number,
         text: string,
@@ -252,6 +262,20 @@ export const ApiGenerator = (
                 response,
             )[0];
         },
+        append_post_blob: async (
+            id: string,
+            chunk: Uint8Array,
+        ): Promise<JsonValue | null> => {
+            const arg = IDL.encode([IDL.Text, IDL.Vec(IDL.Nat8)], [id, chunk]);
+            const response = await call_raw(undefined, "append_post_blob", arg);
+            if (!response) {
+                return null;
+            }
+            return IDL.decode(
+                [IDL.Variant({ Ok: IDL.Null, Err: IDL.Text })],
+                response,
+            )[0];
+        },
         commit_post: async (): Promise<JsonValue | null> => {
             const arg = IDL.encode([], []);
             const response = await call_raw(undefined, "commit_post", arg);
@@ -263,6 +287,27 @@ export const ApiGenerator = (
                 response,
             )[0];
         },
+        review_held_post: async (
+            user_id: number,
+            approve: boolean,
+        ): Promise<JsonValue | null> => {
+            const arg = IDL.encode(
+                [IDL.Nat64, IDL.Bool],
+                [BigInt(user_id), approve],
+            );
+            const response = await call_raw(
+                undefined,
+                "review_held_post",
+                arg,
+            );
+            if (!response) {
+                return null;
+            }
+            return IDL.decode(
+                [IDL.Variant({ Ok: IDL.Opt(IDL.Nat64), Err: IDL.Text })],
+                response,
+            )[0];
+        },
         edit_post: async (
             id: number,
             text: string,
@@ -3,7 +3,14 @@ import * as React from "react";
 import DiffMatchPatch from "diff-match-patch";
 import { Clipboard, ClipboardCheck, Flag, Menu, Share } from "./icons";
 import { loadFile } from "./form";
-import { Post, PostId, Report, User, UserId } from "./types";
+import {
+    Post,
+    PostId,
+    Report,
+    ReportCategory,
+    User,
+    UserId,
+} from "./types";
 
 export const MAX_POST_SIZE_BYTES = Math.ceil(1024 * 1024 * 1.9);
 
@@ -553,28 +560,75 @@ export const BurgerButton = ({
     );
 };
 
+const BLOB_READ_CHUNK_BYTES = 1024 * 1024;
+
 export const loadPostBlobs = async (files: {
     [id: string]: [number, number];
 }) => {
     const ids = Object.keys(files);
     const blobs: [string, ArrayBuffer][] = await Promise.all(
-        ids.map(async (id) => {
+        ids.map(async (id): Promise<[string, ArrayBuffer]> => {
             const [blobId, bucket_id] = id.split("@");
             const [offset, len] = files[id];
-            let offsetBEBytes = intToBEBytes(offset);
-            let lenBEBytes = intToBEBytes(len);
-            let args = new Uint8Array(offsetBEBytes.length + lenBEBytes.length);
-            args.set(offsetBEBytes);
-            args.set(lenBEBytes, offsetBEBytes.length);
             // This allows us to see the bucket pics in dev mode.
             const api = window.backendCache.stats.buckets.every(
                 ([id]) => id != bucket_id,
             )
                 ? window.mainnet_api
                 : window.api;
-            return api
-                .query_raw(bucket_id, "read", Buffer.from(args))
-                .then((blob) => [blobId, blob || new ArrayBuffer(0)]);
+            const readChunk = (
+                bucket: string,
+                offset: number,
+                len: number,
+            ) => {
+                let offsetBEBytes = intToBEBytes(offset);
+                let lenBEBytes = intToBEBytes(len);
+                let args = new Uint8Array(
+                    offsetBEBytes.length + lenBEBytes.length,
+                );
+                args.set(offsetBEBytes);
+                args.set(lenBEBytes, offsetBEBytes.length);
+                return api
+                    .query_raw(bucket, "read", Buffer.from(args))
+                    .catch(() => null);
+            };
+            // Large blobs don't fit into one query response.
+            const read = async (bucket: string, offset: number) => {
+                const chunks = await Promise.all(
+                    Array.from(
+                        { length: Math.ceil(len / BLOB_READ_CHUNK_BYTES) },
+                        (_, i) =>
+                            readChunk(
+                                bucket,
+                                offset + i * BLOB_READ_CHUNK_BYTES,
+                                Math.min(
+                                    BLOB_READ_CHUNK_BYTES,
+                                    len - i * BLOB_READ_CHUNK_BYTES,
+                                ),
+                            ),
+                    ),
+                );
+                if (chunks.some((chunk) => !chunk)) return null;
+                if (chunks.length == 1) return chunks[0];
+                const blob = new Uint8Array(len);
+                let pos = 0;
+                for (const chunk of chunks as ArrayBuffer[]) {
+                    blob.set(new Uint8Array(chunk), pos);
+                    pos += chunk.byteLength;
+                }
+                return blob.buffer;
+            };
+            let blob = await read(bucket_id, offset);
+            // Fall back to the replica if the bucket doesn't respond.
+            if (!blob) {
+                const replica = await api.query<[string, number]>(
+                    "blob_replica",
+                    bucket_id,
+                    offset,
+                );
+                if (replica) blob = await read(replica[0], replica[1]);
+            }
+            return [blobId, blob || new ArrayBuffer(0)];
         }),
     );
     return blobs.reduce(
@@ -658,6 +712,36 @@ export const intToBEBytes = (val: number) => {
     return new Uint8Array(buffer);
 };
 
+const REPORT_CATEGORIES: [ReportCategory, string][] = [
+    ["Spam", "SPAM"],
+    ["Abuse", "ABUSE"],
+    ["IllegalContent", "ILLEGAL CONTENT"],
+    ["Impersonation", "IMPERSONATION"],
+    ["OffTopic", "OFF-TOPIC"],
+    ["Other", "OTHER"],
+];
+
+// Mirrors the penalties of report categories in the backend.
+const reportingPenalty = (category: ReportCategory, domain: string) => {
+    const config = window.backendCache.config;
+    switch (category) {
+        case "Spam":
+            return config.reporting_penalty_spam;
+        case "Abuse":
+            return config.reporting_penalty_abuse;
+        case "IllegalContent":
+            return config.reporting_penalty_illegal_content;
+        case "Impersonation":
+            return config.reporting_penalty_impersonation;
+        case "OffTopic":
+            return config.reporting_penalty_off_topic;
+        default:
+            return domain == "post"
+                ? config.reporting_penalty_post
+                : config.reporting_penalty_misbehaviour;
+    }
+};
+
 export const FlagButton = ({
     id,
     domain,
@@ -666,40 +750,172 @@ export const FlagButton = ({
     id: number;
     domain: string;
     text?: boolean;
-}) => (
-    <ButtonWithLoading
-        title="Flag post"
-        classNameArg="max_width_col"
-        onClick={async () => {
-            let reason = prompt(
-                `You are reporting this ${
-                    domain == "post" ? "post" : "user"
-                } to stalwarts. ` +
-                    `If the report gets rejected, you'll lose ` +
-                    window.backendCache.config[
-                        domain == "post"
-                            ? "reporting_penalty_post"
-                            : "reporting_penalty_misbehaviour"
-                    ] +
-                    ` cycles and karma. If you want to continue, please justify the report.`,
-            );
-            if (reason) {
-                let response = await window.api.call<{ [name: string]: any }>(
-                    "report",
-                    domain,
-                    id,
-                    reason,
-                );
-                if (response && "Err" in response) {
-                    alert(`Error: ${response.Err}`);
-                    return;
-                }
-                alert("Report accepted! Thank you!");
-            }
-        }}
-        label={text ? "REPORT" : <Flag />}
-    />
-);
+}) => {
+    const [picking, setPicking] = React.useState(false);
+    const [category, setCategory] = React.useState<ReportCategory>("Spam");
+    if (!picking)
+        return (
+            <button
+                title="Flag post"
+                className="max_width_col"
+                onClick={() => setPicking(true)}
+            >
+                {text ? "REPORT" : <Flag />}
+            </button>
+        );
+    return (
+        <div className="row_container max_width_col">
+            <select
+                value={category}
+                onChange={(e) => setCategory(e.target.value as ReportCategory)}
+            >
+                {REPORT_CATEGORIES.map(([value, label]) => (
+                    <option key={value} value={value}>
+                        {label}
+                    </option>
+                ))}
+            </select>
+            <ButtonWithLoading
+                title="Flag post"
+                onClick={async () => {
+                    let reason = prompt(
+                        `You are reporting this ${
+                            domain == "post" ? "post" : "user"
+                        } to stalwarts. ` +
+                            `If the report gets rejected, you'll lose ` +
+                            reportingPenalty(category, domain) +
+                            ` cycles and karma. If you want to continue, please justify the report.`,
+                    );
+                    if (reason) {
+                        let response = await window.api.call<{
+                            [name: string]: any;
+                        }>("report", domain, id, reason, category);
+                        if (response && "Err" in response) {
+                            alert(`Error: ${response.Err}`);
+                            return;
+                        }
+                        alert("Report accepted! Thank you!");
+                        setPicking(false);
+                    }
+                }}
+                label="REPORT"
+            />
+        </div>
+    );
+};
+
+export const AppealBanner = ({
+    id,
+    reportArg,
+    domain,
+    penalizedUser,
+}: {
+    id: number;
+    reportArg: Report;
+    domain: string;
+    penalizedUser: UserId;
+}) => {
+    const [report, setReport] = React.useState(reportArg);
+    const user = window.user;
+    const { appeal } = report;
+    const reload = async () => {
+        const updatedReport =
+            domain == "post"
+                ? (await loadPosts([id]))[0].report
+                : (await window.api.query<User>("user", [id.toString()]))
+                      ?.report;
+        if (updatedReport) setReport(updatedReport);
+    };
+    if (!user) return null;
+    if (!appeal) {
+        if (user.id != penalizedUser) return null;
+        return (
+            <div className="post_head banner">
+                <h4>
+                    You can appeal this report. Appeals cost{" "}
+                    {window.backendCache.config.report_appeal_deposit} cycles,
+                    which are returned if stalwarts reverse the penalty.
+                </h4>
+                <ButtonWithLoading
+                    label="APPEAL"
+                    onClick={async () => {
+                        const reason = prompt(
+                            "Why should the report be reversed?",
+                        );
+                        if (!reason) return;
+                        let result = await window.api.call<{
+                            [name: string]: any;
+                        }>("appeal_report", domain, id, reason);
+                        if (result && "Err" in result) {
+                            alert(`Error: ${result.Err}`);
+                            return;
+                        }
+                        await reload();
+                    }}
+                />
+            </div>
+        );
+    }
+    const canVote =
+        !appeal.closed &&
+        user.stalwart &&
+        user.id != penalizedUser &&
+        user.id != report.reporter &&
+        !report.confirmed_by.concat(report.rejected_by).includes(user.id) &&
+        !appeal.confirmed_by.concat(appeal.rejected_by).includes(user.id);
+    const buttons: [string, boolean][] = [
+        ["🛑 KEEP THE PENALTY", false],
+        ["✅ REVERSE THE PENALTY", true],
+    ];
+    return (
+        <div className="post_head banner">
+            <h3>
+                The report was <b>APPEALED</b>
+                {appeal.closed
+                    ? appeal.confirmed_by.length > appeal.rejected_by.length
+                        ? " and the penalty was reversed."
+                        : " and the appeal was rejected."
+                    : "."}
+            </h3>
+            <h4>Reason: {appeal.reason}</h4>
+            <div className="medium_text">
+                {appeal.confirmed_by.length > 0 && (
+                    <div>
+                        REVERSAL CONFIRMED BY {userList(appeal.confirmed_by)}
+                    </div>
+                )}
+                {appeal.rejected_by.length > 0 && (
+                    <div>
+                        REVERSAL REJECTED BY {userList(appeal.rejected_by)}
+                    </div>
+                )}
+            </div>
+            {canVote && (
+                <div
+                    className="row_container"
+                    style={{ justifyContent: "center" }}
+                >
+                    {buttons.map(([label, val]) => (
+                        <ButtonWithLoading
+                            key={label}
+                            label={label}
+                            onClick={async () => {
+                                let result = await window.api.call<{
+                                    [name: string]: any;
+                                }>("vote_on_appeal", domain, id, val);
+                                if (result && "Err" in result) {
+                                    alert(`Error: ${result.Err}`);
+                                    return;
+                                }
+                                await reload();
+                            }}
+                        />
+                    ))}
+                </div>
+            )}
+        </div>
+    );
+};
 
 export const ReportBanner = ({
     id,
@@ -210,6 +210,12 @@ export const Dashboard = ({}) => {
                                 </label>{" "}
                                 {sizeMb(stats.state_size)}
                             </div>
+                            <div className="db_cell bottom_spaced">
+                                <label>
+                                    <Box /> FRAGMENTATION
+                                </label>{" "}
+                                {Math.round(stats.fragmentation * 100)}%
+                            </div>
                             <div className="db_cell">
                                 <label>
                                     <Cycles /> IC-CYCLES
@@ -221,7 +227,7 @@ export const Dashboard = ({}) => {
                                 )}
                             </div>
                         </div>
-                        {stats.buckets.map(([bucket_id, size], i) => (
+                        {stats.buckets.map(([bucket_id, size, status], i) => (
                             <div key={bucket_id} className="db_cell">
                                 <a
                                     href={`https://dashboard.internetcomputer.org/canister/${bucket_id}`}
@@ -234,12 +240,18 @@ export const Dashboard = ({}) => {
                                     </label>{" "}
                                     {sizeMb(size)}
                                 </div>
-                                <div className="db_cell">
+                                <div className="db_cell bottom_spaced">
                                     <label>
                                         <Cycles /> IC-CYCLES
                                     </label>{" "}
                                     <CycleBalance id={bucket_id} />
                                 </div>
+                                <div className="db_cell">
+                                    <label>
+                                        <Binary /> VERSION
+                                    </label>{" "}
+                                    <code>{status}</code>
+                                </div>
                             </div>
                         ))}
                         <div className="db_cell bottom_spaced">
@@ -264,6 +276,23 @@ export const Dashboard = ({}) => {
                     {userList(stats.stalwarts)}
                 </div>
                 <hr />
+                <h2>MODERATION</h2>
+                <hr />
+                <div className="dynamic_table">
+                    {stats.moderation.map(([id, accuracy, responseTime]) => (
+                        <div className="db_cell" key={id}>
+                            <UserLink id={id} />
+                            <code>
+                                {accuracy == null ? "-" : `${accuracy}%`} /{" "}
+                                {Math.ceil(
+                                    responseTime / 60 / 1000000000,
+                                ).toLocaleString()}
+                                m
+                            </code>
+                        </div>
+                    ))}
+                </div>
+                <hr />
                 <h2>
                     <Trophy /> WEEKLY KARMA LEADERS
                 </h2>
@@ -14,6 +14,7 @@ import {
     CarretDown,
     Cycles,
     Document,
+    Flag,
     Gear,
     Gem,
     Journal,
@@ -244,6 +245,15 @@ export const Header = ({
                     >
                         <Balloon /> PROPOSALS
                     </a>
+                    {user && user.stalwart && (
+                        <a
+                            className="iconed"
+                            onClick={() => toggleButtonBar(!showButtonBar)}
+                            href="/#/moderation"
+                        >
+                            <Flag /> MODERATION
+                        </a>
+                    )}
                     <a
                         className="iconed"
                         onClick={() => toggleButtonBar(!showButtonBar)}
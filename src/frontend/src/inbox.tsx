@@ -44,6 +44,7 @@ export const Inbox = () => {
                     const message = inbox[k];
                     let msg = "";
                     let id = null;
+                    let userId = null;
                     if ("Generic" in message) {
                         msg = message.Generic;
                     } else if ("NewPost" in message) {
@@ -53,6 +54,14 @@ export const Inbox = () => {
                         const payload = message.Conditional[1];
                         if ("ReportOpen" in payload) id = payload.ReportOpen;
                         else if ("Proposal" in payload) id = payload.Proposal;
+                        else if ("AppealOpen" in payload)
+                            id = payload.AppealOpen;
+                        else if ("UserReportOpen" in payload)
+                            userId = payload.UserReportOpen;
+                        else if ("UserAppealOpen" in payload)
+                            userId = payload.UserAppealOpen;
+                        else if ("HeldPost" in payload)
+                            userId = payload.HeldPost;
                         msg = message.Conditional[0];
                     } else if ("WatchedPostEntries" in message) {
                         id = parseInt(k.split("_")[1]);
@@ -87,6 +96,17 @@ export const Inbox = () => {
                                     <Close classNameArg="action" />
                                 </button>
                             </div>
+                            {userId != null && (
+                                <a
+                                    className="left_spaced medium_text"
+                                    href={`#/user/${userId}`}
+                                >
+                                    {`@${
+                                        window.backendCache.users[userId] ||
+                                        userId
+                                    }`}
+                                </a>
+                            )}
                             {id && (
                                 <PostView
                                     id={id}
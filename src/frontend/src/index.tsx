@@ -26,6 +26,7 @@ import { Settings } from "./settings";
 import { ApiGenerator } from "./api";
 import { Wallet, WelcomeInvited } from "./wallet";
 import { Proposals } from "./proposals";
+import { Moderation } from "./moderation";
 import { Tokens, TransactionView } from "./tokens";
 import { Whitepaper } from "./whitepaper";
 import { Recovery } from "./recovery";
@@ -149,8 +150,27 @@ const App = () => {
         content = <TransactionView id={parseInt(param)} />;
     } else if (handler == "proposals") {
         content = <Proposals />;
+    } else if (handler == "moderation") {
+        content = <Moderation />;
     } else if (handler == "tokens") {
         content = <Tokens />;
+    } else if (handler == "bookmarks" && param) {
+        setTitle(`@${param}'s bookmarks`);
+        content = (
+            <PostFeed
+                useList={true}
+                title={
+                    <HeadBar
+                        title={`@${param}'s bookmarks`}
+                        shareLink={`bookmarks/${param}`}
+                    />
+                }
+                includeComments={true}
+                feedLoader={async () =>
+                    await api.query("public_bookmarks", param)
+                }
+            />
+        );
     } else if (handler == "bookmarks") {
         content = auth(
             <PostFeed
@@ -0,0 +1,123 @@
+import * as React from "react";
+import { ButtonWithLoading, HeadBar, Loading, timeAgo } from "./common";
+import { Content } from "./content";
+import { HeldPost, OpenReport } from "./types";
+
+export const Moderation = () => {
+    const [queue, setQueue] = React.useState<OpenReport[] | null>(null);
+    const [heldPosts, setHeldPosts] = React.useState<HeldPost[]>([]);
+
+    const loadHeldPosts = async () =>
+        setHeldPosts((await window.api.query<HeldPost[]>("held_posts")) || []);
+
+    React.useEffect(() => {
+        window.api
+            .query<OpenReport[]>("moderation_queue")
+            .then((queue) => setQueue(queue || []));
+        loadHeldPosts();
+    }, []);
+
+    return (
+        <>
+            <HeadBar title="MODERATION" shareLink="moderation" />
+            <div className="spaced">
+                {heldPosts.length > 0 && <h2>Posts held by the spam filter</h2>}
+                {heldPosts.map(
+                    ([user, body, _parent, realm, blobs, score, timestamp]) => (
+                        <div key={user} className="stands_out">
+                            <div className="small_text">
+                                Posted {timeAgo(timestamp)} by{" "}
+                                <a href={`#/user/${user}`}>
+                                    {`@${
+                                        window.backendCache.users[user] || user
+                                    }`}
+                                </a>
+                                {realm && ` in realm ${realm}`}, spam score{" "}
+                                <code>{score}</code>, <code>{blobs}</code>{" "}
+                                attachments
+                            </div>
+                            <Content value={body} />
+                            <div
+                                className="row_container"
+                                style={{ justifyContent: "center" }}
+                            >
+                                {(
+                                    [
+                                        ["🛑 REJECT", false],
+                                        ["✅ APPROVE", true],
+                                    ] as [string, boolean][]
+                                ).map(([label, approve]) => (
+                                    <ButtonWithLoading
+                                        key={label}
+                                        label={label}
+                                        onClick={async () => {
+                                            const result: any =
+                                                await window.api.review_held_post(
+                                                    user,
+                                                    approve,
+                                                );
+                                            if (result && "Err" in result) {
+                                                alert(`Error: ${result.Err}`);
+                                                return;
+                                            }
+                                            await loadHeldPosts();
+                                        }}
+                                    />
+                                ))}
+                            </div>
+                        </div>
+                    ),
+                )}
+                <h2>Open reports</h2>
+                {queue == null && <Loading />}
+                {queue && queue.length == 0 && <h3>No open reports.</h3>}
+                {queue &&
+                    queue.map((report) => {
+                        const link =
+                            report.domain == "post"
+                                ? `#/post/${report.id}`
+                                : `#/user/${report.id}`;
+                        return (
+                            <div
+                                key={`${report.domain}_${report.id}`}
+                                className="stands_out"
+                            >
+                                <div className="row_container">
+                                    <a className="max_width_col" href={link}>
+                                        {report.domain == "post"
+                                            ? `POST #${report.id}`
+                                            : `USER @${
+                                                  window.backendCache.users[
+                                                      report.id
+                                                  ] || report.id
+                                              }`}
+                                    </a>
+                                    <code>{report.category}</code>
+                                </div>
+                                <p>Reason: {report.reason}</p>
+                                <div className="small_text">
+                                    Reported{" "}
+                                    {timeAgo(
+                                        Number(new Date()) * 1000000 -
+                                            report.age,
+                                    )}{" "}
+                                    by{" "}
+                                    <a href={`#/user/${report.reporter}`}>
+                                        {`@${
+                                            window.backendCache.users[
+                                                report.reporter
+                                            ]
+                                        }`}
+                                    </a>
+                                    , confirmed by{" "}
+                                    <code>{report.confirmations}</code>,
+                                    rejected by{" "}
+                                    <code>{report.rejections}</code> stalwarts
+                                </div>
+                            </div>
+                        );
+                    })}
+            </div>
+        </>
+    );
+};
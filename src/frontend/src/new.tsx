@@ -9,6 +9,28 @@ import {
 } from "./common";
 import { Extension, Post, PostId } from "./types";
 
+// The maximal number of bytes of a blob sent in one message.
+const BLOB_CHUNK_BYTES = 1024 * 1024;
+
+// Uploads a blob of a post being created in chunks fitting into one message each.
+const uploadBlob = async (id: string, blob: Uint8Array) => {
+    let result: any = await window.api.add_post_blob(
+        id,
+        blob.subarray(0, BLOB_CHUNK_BYTES),
+    );
+    for (
+        let start = BLOB_CHUNK_BYTES;
+        start < blob.length && result && !("Err" in result);
+        start += BLOB_CHUNK_BYTES
+    ) {
+        result = await window.api.append_post_blob(
+            id,
+            blob.subarray(start, start + BLOB_CHUNK_BYTES),
+        );
+    }
+    return result;
+};
+
 export const PostSubmissionForm = ({
     id,
     repost,
@@ -67,9 +89,7 @@ export const PostSubmissionForm = ({
                     encodeExtension(extension),
                 );
                 let results = await Promise.all(
-                    blobs.map(([id, blob]) =>
-                        window.api.add_post_blob(id, blob),
-                    ),
+                    blobs.map(([id, blob]) => uploadBlob(id, blob)),
                 );
                 let error: any = results.find((result: any) => "Err" in result);
                 if (error) {
@@ -23,6 +23,7 @@ import {
     UserLink,
     FlagButton,
     ReportBanner,
+    AppealBanner,
     icp,
     currentRealm,
 } from "./common";
@@ -273,7 +274,19 @@ export const PostView = ({
     if (prime) setTitle(`Post #${post.id} by @${post.userObject.name}`);
 
     if (deletedByModeration)
-        return <h4 className="banner">DELETED VIA MODERATION</h4>;
+        return (
+            <>
+                <h4 className="banner">DELETED VIA MODERATION</h4>
+                {post.report && (
+                    <AppealBanner
+                        id={post.id}
+                        reportArg={post.report}
+                        domain="post"
+                        penalizedUser={post.user}
+                    />
+                )}
+            </>
+        );
 
     let cls = "";
     if (!deleted && !isNSFW && !showReport) {
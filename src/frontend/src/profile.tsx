@@ -11,6 +11,7 @@ import {
     tokenBalance,
     FlagButton,
     ReportBanner,
+    AppealBanner,
     UserLink,
     percentage,
     ShareButton,
@@ -182,6 +183,19 @@ export const Profile = ({ handle }: { handle: string }) => {
                                             .then(window.reloadUser)
                                     }
                                 />
+                                <ToggleButton
+                                    offLabel="BLOCK"
+                                    onLabel="UNBLOCK"
+                                    classNameArg="max_width_col"
+                                    currState={() =>
+                                        user.blocked.includes(profile.id)
+                                    }
+                                    toggler={() =>
+                                        window.api
+                                            .call("toggle_block", profile.id)
+                                            .then(window.reloadUser)
+                                    }
+                                />
                             </>
                         )}
                     </div>
@@ -194,6 +208,17 @@ export const Profile = ({ handle }: { handle: string }) => {
                     domain="misbehaviour"
                 />
             )}
+            {profile.report &&
+                profile.report.closed &&
+                profile.report.confirmed_by.length >
+                    profile.report.rejected_by.length && (
+                    <AppealBanner
+                        id={profile.id}
+                        reportArg={profile.report}
+                        domain="misbehaviour"
+                        penalizedUser={profile.id}
+                    />
+                )}
             <UserInfo profile={profile} />
             {karma_from_last_posts.length > 0 && (
                 <>
@@ -35,7 +35,10 @@ export const Search = () => {
                             }
                             setSearching(true);
                             setResults(
-                                (await window.api.query("search", term)) || [],
+                                (await window.api.query(
+                                    "filtered_search",
+                                    term,
+                                )) || [],
                             );
                             setSearching(false);
                         }, 300),
@@ -1,7 +1,147 @@
 import * as React from "react";
-import { ButtonWithLoading, HeadBar } from "./common";
+import { ButtonWithLoading, HeadBar, timeAgo } from "./common";
 import { User } from "./types";
 
+const MUTE_DURATIONS: [string, string][] = [
+    ["24", "24 HOURS"],
+    ["168", "7 DAYS"],
+    ["720", "30 DAYS"],
+    ["", "FOREVER"],
+];
+
+const MuteSettings = () => {
+    const [mutes, setMutes] = React.useState(window.user.mutes);
+    const [kind, setKind] = React.useState("user");
+    const [value, setValue] = React.useState("");
+    const [hours, setHours] = React.useState("24");
+
+    const reload = async () => {
+        await window.reloadUser();
+        setMutes(window.user.mutes);
+    };
+
+    const entries: [string, string, string, BigInt][] = [
+        ...Object.entries(mutes.users).map(
+            ([id, expiry]): [string, string, string, BigInt] => [
+                "user",
+                id,
+                `@${window.backendCache.users[Number(id)] || id}`,
+                expiry,
+            ],
+        ),
+        ...Object.entries(mutes.tags).map(
+            ([tag, expiry]): [string, string, string, BigInt] => [
+                "tag",
+                tag,
+                `#${tag}`,
+                expiry,
+            ],
+        ),
+        ...Object.entries(mutes.threads).map(
+            ([id, expiry]): [string, string, string, BigInt] => [
+                "thread",
+                id,
+                `thread #${id}`,
+                expiry,
+            ],
+        ),
+    ];
+
+    // users are muted by their name, tags without the hash
+    const muteValue = () => {
+        const input = value.trim().replace(/^[@#]/, "");
+        if (kind != "user") return input;
+        const entry = Object.entries(window.backendCache.users).find(
+            ([_, name]) => name.toLowerCase() == input.toLowerCase(),
+        );
+        return entry ? entry[0] : input;
+    };
+
+    return (
+        <div className="top_spaced column_container">
+            <h2>Mutes</h2>
+            <p>
+                Muted users, tags and threads are hidden from your feeds and
+                search results. Muted threads don't send you notifications.
+            </p>
+            {entries.map(([kind, value, label, expiry]) => (
+                <div key={`${kind}_${value}`} className="row_container">
+                    <span className="max_width_col">
+                        {label}{" "}
+                        <span className="small_text">
+                            {Number(expiry) > 1e18
+                                ? "forever"
+                                : `until ${timeAgo(expiry, true)}`}
+                        </span>
+                    </span>
+                    <ButtonWithLoading
+                        label="UNMUTE"
+                        onClick={async () => {
+                            const response = await window.api.call<any>(
+                                "unmute",
+                                kind,
+                                value,
+                            );
+                            if (response && "Err" in response) {
+                                alert(`Error: ${response.Err}`);
+                                return;
+                            }
+                            await reload();
+                        }}
+                    />
+                </div>
+            ))}
+            <div className="row_container top_spaced">
+                <select value={kind} onChange={(e) => setKind(e.target.value)}>
+                    <option value="user">USER</option>
+                    <option value="tag">TAG</option>
+                    <option value="thread">THREAD</option>
+                </select>
+                <input
+                    className="max_width_col"
+                    type="text"
+                    placeholder={
+                        kind == "user"
+                            ? "user name"
+                            : kind == "tag"
+                            ? "tag"
+                            : "post id"
+                    }
+                    value={value}
+                    onChange={(e) => setValue(e.target.value)}
+                />
+                <select
+                    value={hours}
+                    onChange={(e) => setHours(e.target.value)}
+                >
+                    {MUTE_DURATIONS.map(([value, label]) => (
+                        <option key={label} value={value}>
+                            {label}
+                        </option>
+                    ))}
+                </select>
+                <ButtonWithLoading
+                    label="MUTE"
+                    onClick={async () => {
+                        const response = await window.api.call<any>(
+                            "mute",
+                            kind,
+                            muteValue(),
+                            hours ? Number(hours) : null,
+                        );
+                        if (response && "Err" in response) {
+                            alert(`Error: ${response.Err}`);
+                            return;
+                        }
+                        setValue("");
+                        await reload();
+                    }}
+                />
+            </div>
+        </div>
+    );
+};
+
 export const Settings = ({ invite }: { invite?: string }) => {
     const user = window.user;
     const [principal, setPrincipal] = React.useState(window.principalId);
@@ -160,6 +300,7 @@ export const Settings = ({ invite }: { invite?: string }) => {
                     onClick={submit}
                     label="SAVE"
                 />
+                {window.user && <MuteSettings />}
                 {window.user && (
                     <div className="top_spaced column_container">
                         <h2>Principal Change</h2>
@@ -46,8 +46,24 @@ export type Payload =
       }
     | {
           ["Reward"]: Reward;
+      }
+    | {
+          ["Badge"]: [UserId, Attestation];
+      }
+    | {
+          ["RevokeBadge"]: [UserId, Attestation];
       };
 
+export type Attestation =
+    | { ["RealmOfficial"]: string }
+    | { ["DomainVerified"]: string };
+
+export type Badge = {
+    attestation: Attestation;
+    issuer: { ["RealmController"]: UserId } | { ["Dao"]: number };
+    timestamp: BigInt;
+};
+
 export type Proposal = {
     id: number;
     proposer: UserId;
@@ -117,6 +133,30 @@ type Filters = {
     realms: string[];
 };
 
+export type BookmarkCollection = {
+    posts: PostId[];
+    notes: { [id: PostId]: string };
+};
+
+export type DelegatePermission = "Post" | "Comment" | "React" | "Tip";
+
+export type Delegate = {
+    permissions: DelegatePermission[];
+    weekly_cycles_limit: number;
+    cycles_spent: number;
+    weekly_e8s_limit: number;
+    e8s_spent: number;
+    expiry?: BigInt;
+    realm?: string;
+    log: [BigInt, number, string][];
+};
+
+type Mutes = {
+    users: { [id: UserId]: BigInt };
+    tags: { [tag: string]: BigInt };
+    threads: { [id: PostId]: BigInt };
+};
+
 export type Predicate =
     | {
           ["ReportOpen"]: PostId;
@@ -126,6 +166,15 @@ export type Predicate =
       }
     | {
           ["Proposal"]: PostId;
+      }
+    | {
+          ["AppealOpen"]: PostId;
+      }
+    | {
+          ["UserAppealOpen"]: UserId;
+      }
+    | {
+          ["HeldPost"]: UserId;
       };
 
 export type Notification =
@@ -172,15 +221,58 @@ export type User = {
     balance: number;
     controllers: string[];
     filters: Filters;
+    mutes: Mutes;
+    bookmark_collections: { [name: string]: BookmarkCollection };
+    public_bookmark_collection?: string;
+    blocked: UserId[];
+    guardians: UserId[];
+    guardian_threshold: number;
+    delegates: { [principal: string]: Delegate };
+    deletion_requested?: BigInt;
+    badges: Badge[];
     inbox: { [key: string]: Notification };
 };
 
+export type ReportCategory =
+    | "Other"
+    | "Spam"
+    | "Abuse"
+    | "IllegalContent"
+    | "Impersonation"
+    | "OffTopic";
+
+export type HeldPost = [
+    UserId,
+    string,
+    PostId | null,
+    string | null,
+    number,
+    number,
+    number,
+];
+
+export type OpenReport = {
+    domain: string;
+    id: number;
+    reporter: UserId;
+    category: ReportCategory;
+    reason: string;
+    confirmations: number;
+    rejections: number;
+    age: number;
+};
+
 export type Report = {
     reason: string;
     reporter: UserId;
     confirmed_by: UserId[];
     rejected_by: UserId[];
     closed: boolean;
+    category: ReportCategory;
+    timestamp: BigInt;
+    penalty_karma: number;
+    penalty_cycles: number;
+    appeal?: Report;
 };
 
 export type Theme = { [name: string]: string };
@@ -233,6 +325,7 @@ declare global {
                 comments: number;
                 bootcamp_users: number;
                 state_size: number;
+                fragmentation: number;
                 account: string;
                 last_weekly_chores: BigInt;
                 holders: number;
@@ -241,8 +334,9 @@ declare global {
                 canister_id: string;
                 team_tokens: { [name: UserId]: number };
                 last_upgrade: number;
-                buckets: [string, number][];
+                buckets: [string, number, string][];
                 stalwarts: UserId[];
+                moderation: [UserId, number | null, number][];
             };
             config: {
                 min_cycles_for_inviting: number;
@@ -254,6 +348,7 @@ declare global {
                 poll_cost: number;
                 max_post_length: number;
                 max_blob_size_bytes: number;
+                max_media_size_bytes: number;
                 name_change_cost: number;
                 realm_cleanup_penalty: number;
                 max_realm_name: number;
@@ -277,6 +372,12 @@ declare global {
                 domains: string[];
                 reporting_penalty_post: number;
                 reporting_penalty_misbehaviour: number;
+                reporting_penalty_spam: number;
+                reporting_penalty_abuse: number;
+                reporting_penalty_illegal_content: number;
+                reporting_penalty_impersonation: number;
+                reporting_penalty_off_topic: number;
+                report_appeal_deposit: number;
             };
         };
     }